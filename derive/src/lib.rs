@@ -0,0 +1,137 @@
+// vim: tw=80
+
+//! Derive macros for [`futures-locks`].  Don't use this crate directly; use
+//! the `derive` feature of `futures-locks` instead.
+//!
+//! [`futures-locks`]: https://docs.rs/futures-locks
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use] extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{Data, DeriveInput, Error, Fields, Meta, NestedMeta};
+use syn::spanned::Spanned;
+
+/// Which kind of lock protects a field
+enum LockKind {
+    Mutex,
+    RwLock,
+}
+
+/// Parse a field's `#[async_locked(...)]` attribute, if any.
+fn lock_kind(field: &syn::Field) -> Result<LockKind, Error> {
+    let mut kind = LockKind::Mutex;
+    for attr in field.attrs.iter().filter(|a| a.path.is_ident("async_locked")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(),
+                "expected #[async_locked(mutex)] or #[async_locked(rwlock)]"))
+        };
+        for nested in list.nested.iter() {
+            match *nested {
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("mutex") => {
+                    kind = LockKind::Mutex
+                },
+                NestedMeta::Meta(Meta::Path(ref p)) if p.is_ident("rwlock") => {
+                    kind = LockKind::RwLock
+                },
+                _ => return Err(Error::new(nested.span(),
+                    "expected `mutex` or `rwlock`"))
+            }
+        }
+    }
+    Ok(kind)
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(Error::new(input.ident.span(),
+                "AsyncLocked only supports structs with named fields"))
+        },
+        _ => return Err(Error::new(input.ident.span(),
+            "AsyncLocked only supports structs with named fields"))
+    };
+    let vis = &input.vis;
+    let name = &input.ident;
+    let locked = format_ident!("{}Locked", name);
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    let doc = format!("A copy of [`{}`] with each field behind its own lock.  \
+        Generated by `#[derive(AsyncLocked)]`.", name);
+
+    let mut decls = Vec::new();
+    let mut clones = Vec::new();
+    let mut inits = Vec::new();
+    let mut accessors = Vec::new();
+    for field in fields.iter() {
+        let fname = field.ident.as_ref().unwrap();
+        let fvis = &field.vis;
+        let fty = &field.ty;
+        let lock_ty = match lock_kind(field)? {
+            LockKind::Mutex => quote!(::futures_locks::Mutex<#fty>),
+            LockKind::RwLock => quote!(::futures_locks::RwLock<#fty>),
+        };
+        let accessor_doc = format!("Returns the lock protecting `{}`.",
+                                   fname);
+        decls.push(quote!(#fname: #lock_ty));
+        clones.push(quote!(#fname: self.#fname.clone()));
+        inits.push(quote!(#fname: <#lock_ty>::new(s.#fname)));
+        accessors.push(quote! {
+            #[doc = #accessor_doc]
+            #fvis fn #fname(&self) -> &#lock_ty {
+                &self.#fname
+            }
+        });
+    }
+
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #locked #impl_generics #where_clause {
+            #(#decls,)*
+        }
+
+        impl #impl_generics #locked #ty_generics #where_clause {
+            #(#accessors)*
+        }
+
+        impl #impl_generics ::std::clone::Clone for #locked #ty_generics
+            #where_clause
+        {
+            fn clone(&self) -> Self {
+                #locked {
+                    #(#clones,)*
+                }
+            }
+        }
+
+        impl #impl_generics ::std::convert::From<#name #ty_generics>
+            for #locked #ty_generics #where_clause
+        {
+            fn from(s: #name #ty_generics) -> Self {
+                #locked {
+                    #(#inits,)*
+                }
+            }
+        }
+    })
+}
+
+/// Generates a companion struct, named `<Struct>Locked`, with each field
+/// behind its own `futures_locks::Mutex`, or `RwLock` if the field is marked
+/// `#[async_locked(rwlock)]`.
+///
+/// The companion has an accessor for each field's lock, with the same
+/// visibility as the field, a `Clone` implementation that shares the locks,
+/// and a `From` implementation to build it from the original struct.
+#[proc_macro_derive(AsyncLocked, attributes(async_locked))]
+pub fn derive_async_locked(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
@@ -0,0 +1,70 @@
+// vim: tw=80
+
+//! Chaos mode: random spurious failures of the `try_*` methods.
+//!
+//! Downstream code often has a fallback path for when `try_lock` fails, but
+//! that path can be nearly impossible to reach deterministically in tests.
+//! With the `chaos` feature, [`Mutex::try_lock`], [`RwLock::try_read`],
+//! [`RwLock::try_write`], and [`RwLock::try_upgradable_read`] will sometimes
+//! fail even when the lock is free.  Only those methods are affected.  Other
+//! operations that try the lock internally, like formatting a lock with
+//! `Display`, behave normally.
+//!
+//! This feature is intended for tests only.  Never enable it in production.
+//! It requires Rust 1.43.0 or later.  Tests that need the `try_*` methods to
+//! succeed can be compiled out with `#[cfg(not(feature = "chaos"))]`.
+//!
+//! [`Mutex::try_lock`]: ../struct.Mutex.html#method.try_lock
+//! [`RwLock::try_read`]: ../struct.RwLock.html#method.try_read
+//! [`RwLock::try_write`]: ../struct.RwLock.html#method.try_write
+//! [`RwLock::try_upgradable_read`]: ../struct.RwLock.html#method.try_upgradable_read
+
+// This feature requires Rust 1.43.0, newer than the crate's own minimum.
+#![allow(clippy::incompatible_msrv)]
+
+use rand::Rng;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The failure probability, scaled to the full range of a `u32`.
+/// Defaults to one half.
+static THRESHOLD: AtomicU32 = AtomicU32::new(1 << 31);
+
+/// Returns the probability that a `try_*` method will spuriously fail.
+pub fn failure_probability() -> f64 {
+    f64::from(THRESHOLD.load(Ordering::Relaxed)) / f64::from(u32::MAX)
+}
+
+/// Sets the probability that a `try_*` method will spuriously fail.  The
+/// default is `0.5`.  It applies to every lock in the process.
+///
+/// # Panics
+///
+/// Panics if `p` is not in the range `[0.0, 1.0]`.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// chaos::set_failure_probability(1.0);
+/// let mtx = Mutex::<u32>::new(0);
+/// assert!(mtx.try_lock().is_err());
+/// chaos::set_failure_probability(0.0);
+/// assert!(mtx.try_lock().is_ok());
+/// # }
+/// ```
+pub fn set_failure_probability(p: f64) {
+    assert!((0.0..=1.0).contains(&p),
+            "failure probability must be between 0 and 1");
+    let threshold = (p * f64::from(u32::MAX)) as u32;
+    THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+/// Should the current `try_*` operation spuriously fail?
+pub(crate) fn spurious_failure() -> bool {
+    match THRESHOLD.load(Ordering::Relaxed) {
+        0 => false,
+        u32::MAX => true,
+        t => rand::thread_rng().gen::<u32>() < t
+    }
+}
@@ -0,0 +1,54 @@
+// vim: tw=80
+
+//! Detection of a task trying to lock a `Mutex` that it already holds.
+//!
+//! Only compiled with the `deadlock-detection` feature, and only in builds
+//! with debug assertions.
+
+use futures::task::{self, Task};
+use std::panic::Location;
+
+/// Records who currently owns a `Mutex`, and where they acquired it.
+#[derive(Debug)]
+pub(crate) struct Holder {
+    /// The task that acquired the lock, if it was acquired from within a task.
+    /// `try_lock` may be called outside of any task, so this is optional.
+    task: Option<Task>,
+    site: &'static Location<'static>,
+}
+
+impl Holder {
+    /// A holder that acquired the lock from within the current task.
+    pub(crate) fn current(site: &'static Location<'static>) -> Self {
+        Holder{task: Some(task::current()), site}
+    }
+
+    /// A holder that acquired the lock from outside of any known task.
+    pub(crate) fn detached(site: &'static Location<'static>) -> Self {
+        Holder{task: None, site}
+    }
+
+    /// Is this holder the current task?
+    ///
+    /// Must only be called from within a task.
+    pub(crate) fn is_current(&self) -> bool {
+        self.task.as_ref().map_or(false, Task::will_notify_current)
+    }
+
+    /// If this holder is the current task, return a description of the
+    /// deadlock that would result from `site` waiting on it.  `name` is the
+    /// `Mutex`'s name, if it has one.
+    ///
+    /// Must only be called from within a task.
+    pub(crate) fn check(&self, name: Option<&str>,
+                        site: &'static Location<'static>) -> Option<String>
+    {
+        if !self.is_current() {
+            return None;
+        }
+        let name = name.map(|n| format!(" \"{}\"", n)).unwrap_or_default();
+        Some(format!("Deadlock detected: Mutex{} locked at {} is already held \
+                      by the same task, which acquired it at {}", name, site,
+                      self.site))
+    }
+}
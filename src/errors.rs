@@ -0,0 +1,45 @@
+// vim: tw=80
+
+//! All of the crate's error types, in one place.
+//!
+//! Every lock reports a failed acquisition with the same types, whichever
+//! primitive it is.  A `Future` that gives up fails with [`AcquireError`],
+//! whose variants cover timeouts (`TimedOut`), closed locks (`Closed`), and
+//! the rest.  A `try_*` method fails with [`TryLockError`].  The `*_checked`
+//! methods, like [`Mutex::lock_checked`], report poisoning with the standard
+//! library's `PoisonError`, which is re-exported here too.
+//!
+//! Each of them implements `std::error::Error`, so they can be propagated
+//! with `?`.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate futures_locks;
+//! use futures_locks::errors::TryLockError;
+//! # #[cfg(not(feature = "mutex"))] fn main() {}
+//! # #[cfg(feature = "mutex")]
+//! # fn main() {
+//! use futures_locks::Mutex;
+//! use std::error::Error;
+//!
+//! fn bump(mtx: &Mutex<u32>) -> Result<u32, Box<dyn Error>> {
+//!     let mut guard = mtx.try_lock()?;
+//!     *guard += 1;
+//!     Ok(*guard)
+//! }
+//!
+//! let mtx = Mutex::<u32>::new(0);
+//! assert_eq!(bump(&mtx).unwrap(), 1);
+//! let _guard = mtx.try_lock().unwrap();
+//! let e = bump(&mtx).unwrap_err();
+//! assert_eq!(e.downcast_ref(), Some(&TryLockError::WouldBlock));
+//! # }
+//! ```
+//!
+//! [`AcquireError`]: ../enum.AcquireError.html
+//! [`TryLockError`]: ../enum.TryLockError.html
+//! [`Mutex::lock_checked`]: ../struct.Mutex.html#method.lock_checked
+
+pub use super::{AcquireError, TryLockError, TryUnwrapError};
+pub use std::sync::{LockResult, PoisonError};
@@ -16,6 +16,8 @@
 //! # use futures_locks::*;
 //! # use futures::executor::{Spawn, spawn};
 //! # use futures::Future;
+//! # #[cfg(not(feature = "mutex"))] fn main() {}
+//! # #[cfg(feature = "mutex")]
 //! # fn main() {
 //! let mtx = Mutex::<u32>::new(0);
 //! let fut = mtx.lock().map(|mut guard| { *guard += 5; });
@@ -24,27 +26,853 @@
 //! # }
 //! ```
 //!
+//! # Timers
+//!
+//! With the `timer` feature, some methods use the Tokio timer of the current
+//! runtime.  They handle a missing or failed timer in one consistent way.  If
+//! the timer only affects how long an acquisition waits, the method falls
+//! back to its untimed behavior.  That's the case for `Mutex::lock_eventually`,
+//! which joins the wait queue instead of backing off.  If enforcing a limit is
+//! the point of the method, it fails as though the limit had expired, rather
+//! than ignoring it.  So `Mutex::lock_timeout`, `RwLock::read_timeout`, and
+//! their relatives fail with `AcquireError::TimedOut` the first time that
+//! they would have to wait.  And `MutexGuard::with_hold_limit` releases the
+//! `Mutex` and returns `HoldLimitError::NoTimer`.
+//!
 //! [`Futures`]: https://github.com/rust-lang-nursery/futures-rs
 //! [`Tokio`]: https:/tokio.rs
 
 #![cfg_attr(feature = "nightly-docs", feature(doc_cfg))]
+// With neither lock type enabled, the shared internals have no users
+#![cfg_attr(not(any(feature = "mutex", feature = "rwlock")),
+            allow(dead_code))]
 
 extern crate futures;
+#[cfg(feature = "derive")] extern crate futures_locks_derive;
+#[cfg(feature = "metrics-histogram")] extern crate hdrhistogram;
+#[cfg(feature = "chaos")] extern crate rand;
 #[cfg(feature = "tokio")] extern crate tokio_current_thread;
 #[cfg(feature = "tokio")] extern crate tokio_executor;
+#[cfg(feature = "timer")] extern crate tokio_timer;
+#[cfg(feature = "tracing")] #[macro_use] extern crate tracing;
 
+#[cfg(feature = "chaos")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "chaos")))]
+pub mod chaos;
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+mod deadlock;
+pub mod errors;
+#[cfg(any(feature = "mutex", feature = "rwlock"))]
+mod local;
+#[cfg(feature = "mutex")]
+#[doc(hidden)]
+pub mod multi_lock;
+#[cfg(feature = "mutex")]
 mod mutex;
+pub mod prelude;
+#[cfg(feature = "mutex")]
+mod reentrant;
+#[cfg(feature = "mutex")]
+mod scoped;
+#[cfg(feature = "rwlock")]
 mod rwlock;
+#[cfg(feature = "test-util")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "test-util")))]
+pub mod test_util;
 
-pub use mutex::{Mutex, MutexFut, MutexGuard, MutexWeak};
-pub use rwlock::{RwLock, RwLockReadFut, RwLockWriteFut,
-                 RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "mutex")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "mutex")))]
+pub use mutex::{BlockingAdapter, LockSomeFut, MappedMutex, MappedMutexFut,
+                MappedMutexGuard, Mutex, MutexBuilder, MutexChanges,
+                MutexChunkGuard, MutexFut, MutexGuard, MutexLocks, MutexWeak,
+                ReadOnlyGuard, TransactionGuard, TransactionMode,
+                WaitUntilFut};
+#[cfg(all(feature = "mutex", feature = "timer"))]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub use mutex::{HoldLimitError, HoldLimitGuard, LockEventuallyFut,
+                LockTimeoutFut};
+#[cfg(feature = "mutex")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "mutex")))]
+pub use reentrant::{ReentrantMutex, ReentrantMutexFut, ReentrantMutexGuard};
+#[cfg(feature = "mutex")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "mutex")))]
+pub use scoped::{ScopedMutex, ScopedMutexFut, ScopedMutexGuard};
+#[cfg(feature = "mutex")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "mutex")))]
+pub use local::{LocalMutex, LocalMutexFut, LocalMutexGuard};
+#[cfg(feature = "rwlock")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "rwlock")))]
+pub use local::{LocalRwLock, LocalRwLockReadFut, LocalRwLockReadGuard,
+                LocalRwLockWriteFut, LocalRwLockWriteGuard};
+#[cfg(feature = "rwlock")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "rwlock")))]
+pub use rwlock::{MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock,
+                 RwLockBuilder, RwLockCowGuard, RwLockReadFut, RwLockReadGuard,
+                 RwLockUpgradableReadFut, RwLockUpgradableReadGuard,
+                 RwLockUpgradeFut, RwLockWeak, RwLockWriteFut, RwLockWriteGuard,
+                 WriterStarvation};
+#[cfg(all(feature = "rwlock", feature = "timer"))]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub use rwlock::{RwLockReadTimeoutFut, RwLockWriteTimeoutFut};
+#[cfg(feature = "derive")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "derive")))]
+pub use futures_locks_derive::AsyncLocked;
+#[cfg(feature = "metrics-histogram")]
+#[cfg_attr(feature = "nightly-docs",
+           doc(cfg(feature = "metrics-histogram")))]
+pub use hdrhistogram::Histogram;
 
+use futures::{Async, Future, Poll};
+use futures::executor::{self, Notify};
+use futures::task::{self, Task};
 use futures::sync::oneshot;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::{error, fmt, io, sync};
+#[cfg(feature = "metrics-histogram")] use std::time::Duration;
+
+/// The error type of all lock-acquiring `Future`s in this crate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AcquireError {
+    /// The lock's internal bookkeeping was poisoned by a panic, and the lock's
+    /// [`PoisonPolicy`](enum.PoisonPolicy.html) is `Error`.
+    Poisoned,
+    /// The lock already had as many waiters as it allows.  See
+    /// [`MutexBuilder::max_waiters`](struct.MutexBuilder.html#method.max_waiters).
+    QueueFull,
+    /// The lock couldn't be acquired before a timeout or deadline.  See
+    /// [`Mutex::lock_timeout`](struct.Mutex.html#method.lock_timeout) and
+    /// [`RwLock::read_timeout`](struct.RwLock.html#method.read_timeout).
+    TimedOut,
+    /// The acquisition's cancellation token fired first.  See
+    /// [`Mutex::lock_cancellable`](struct.Mutex.html#method.lock_cancellable).
+    Cancelled,
+    /// The lock was closed.  See
+    /// [`Mutex::close`](struct.Mutex.html#method.close).
+    Closed,
+    /// A panic unwound through an exclusive guard of a lock built with
+    /// `poison_on_panic`, and the lock hasn't been cleared since.  The
+    /// `*_checked` methods, like
+    /// [`Mutex::lock_checked`](struct.Mutex.html#method.lock_checked), can
+    /// still acquire it.
+    Panicked,
+}
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AcquireError::Poisoned => f.write_str("lock state is poisoned"),
+            AcquireError::QueueFull => f.write_str("lock's wait queue is full"),
+            AcquireError::TimedOut => f.write_str("timed out acquiring lock"),
+            AcquireError::Cancelled => f.write_str("lock acquisition cancelled"),
+            AcquireError::Closed => f.write_str("lock is closed"),
+            AcquireError::Panicked =>
+                f.write_str("lock was poisoned by a panic"),
+        }
+    }
+}
+
+impl error::Error for AcquireError {}
+
+/// Allows lock acquisitions to be chained with I/O operations using `?`.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use std::io;
+/// # fn main() {
+/// let e = io::Error::from(AcquireError::Poisoned);
+/// assert_eq!(e.kind(), io::ErrorKind::Other);
+/// assert_eq!(e.to_string(), "lock state is poisoned");
+/// # }
+/// ```
+impl From<AcquireError> for io::Error {
+    fn from(e: AcquireError) -> Self {
+        let kind = match e {
+            AcquireError::Poisoned => io::ErrorKind::Other,
+            AcquireError::QueueFull => io::ErrorKind::WouldBlock,
+            AcquireError::TimedOut => io::ErrorKind::TimedOut,
+            AcquireError::Cancelled => io::ErrorKind::Other,
+            AcquireError::Closed => io::ErrorKind::BrokenPipe,
+            AcquireError::Panicked => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// The error type of the crate's nonblocking acquisitions, like
+/// [`Mutex::try_lock`](struct.Mutex.html#method.try_lock).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TryLockError {
+    /// The lock couldn't be acquired without waiting.
+    WouldBlock,
+    /// The lock's internal bookkeeping was poisoned by a panic, and the lock's
+    /// [`PoisonPolicy`](enum.PoisonPolicy.html) isn't `Panic`.
+    Poisoned,
+    /// The lock was closed.  See
+    /// [`Mutex::close`](struct.Mutex.html#method.close).
+    Closed,
+    /// A panic poisoned the lock.  See
+    /// [`AcquireError::Panicked`](enum.AcquireError.html#variant.Panicked).
+    Panicked,
+}
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TryLockError::WouldBlock =>
+                f.write_str("lock couldn't be acquired without waiting"),
+            TryLockError::Poisoned => f.write_str("lock state is poisoned"),
+            TryLockError::Closed => f.write_str("lock is closed"),
+            TryLockError::Panicked =>
+                f.write_str("lock was poisoned by a panic"),
+        }
+    }
+}
+
+impl error::Error for TryLockError {}
+
+impl From<TryLockError> for io::Error {
+    fn from(e: TryLockError) -> Self {
+        let kind = match e {
+            TryLockError::WouldBlock => io::ErrorKind::WouldBlock,
+            TryLockError::Poisoned => io::ErrorKind::Other,
+            TryLockError::Closed => io::ErrorKind::BrokenPipe,
+            TryLockError::Panicked => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// A `Future` representing a lock acquisition that gives up once a
+/// cancellation token completes.  Then it fails with
+/// [`AcquireError::Cancelled`], and leaves the lock's wait queue.  Returned by
+/// [`Mutex::lock_cancellable`], [`RwLock::read_cancellable`], and
+/// [`RwLock::write_cancellable`].
+///
+/// [`AcquireError::Cancelled`]: enum.AcquireError.html#variant.Cancelled
+/// [`Mutex::lock_cancellable`]: struct.Mutex.html#method.lock_cancellable
+/// [`RwLock::read_cancellable`]: struct.RwLock.html#method.read_cancellable
+/// [`RwLock::write_cancellable`]: struct.RwLock.html#method.write_cancellable
+#[derive(Debug)]
+pub struct CancellableFut<F, C> {
+    /// `None` once cancelled
+    fut: Option<F>,
+    token: C,
+}
+
+impl<F, C> CancellableFut<F, C> {
+    fn new(fut: F, token: C) -> Self {
+        CancellableFut{fut: Some(fut), token}
+    }
+}
+
+impl<F, C> Future for CancellableFut<F, C>
+    where F: Future<Error = AcquireError>,
+          C: Future
+{
+    type Item = F::Item;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let fut = self.fut.as_mut().expect("Double-poll of failed Future");
+            if let Async::Ready(guard) = fut.poll()? {
+                return Ok(Async::Ready(guard));
+            }
+        }
+        match self.token.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // A failed token can never fire, but it can't be polled again
+            // either, so treat it as fired.
+            Ok(Async::Ready(_)) | Err(_) => {
+                // Leave the lock's wait queue
+                self.fut = None;
+                Err(AcquireError::Cancelled)
+            }
+        }
+    }
+}
+
+/// The error returned by [`Mutex::try_unwrap`] and [`RwLock::try_unwrap`].
+/// It returns the lock, along with a snapshot of why it couldn't be unwrapped.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # #[cfg(not(feature = "mutex"))] fn main() {}
+/// # #[cfg(feature = "mutex")]
+/// # fn main() {
+/// let mtx = Mutex::<u32>::new(0);
+/// let guard = mtx.try_lock().unwrap();
+/// let e = mtx.try_unwrap().unwrap_err();
+/// assert_eq!(e.handles(), 2);
+/// assert!(e.is_locked());
+/// assert_eq!(e.to_string(),
+///            "lock has 2 handles, is locked, and has 0 waiters");
+/// drop(guard);
+/// assert_eq!(e.into_inner().try_unwrap().unwrap(), 0);
+/// # }
+/// ```
+///
+/// [`Mutex::try_unwrap`]: struct.Mutex.html#method.try_unwrap
+/// [`RwLock::try_unwrap`]: struct.RwLock.html#method.try_unwrap
+#[derive(Debug)]
+pub struct TryUnwrapError<L> {
+    lock: L,
+    handles: usize,
+    locked: bool,
+    waiters: usize,
+}
+
+impl<L> TryUnwrapError<L> {
+    /// Returns the lock that couldn't be unwrapped.
+    pub fn into_inner(self) -> L {
+        self.lock
+    }
+
+    /// The number of handles to the lock, including the returned one.  Guards
+    /// and pending acquisitions each hold a handle, too.
+    pub fn handles(&self) -> usize {
+        self.handles
+    }
+
+    /// Whether the lock was held by anybody.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// The number of tasks that were waiting to acquire the lock.
+    pub fn waiters(&self) -> usize {
+        self.waiters
+    }
+}
+
+impl<L> fmt::Display for TryUnwrapError<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock has {} handles, is {}, and has {} waiters",
+               self.handles, if self.locked {"locked"} else {"unlocked"},
+               self.waiters)
+    }
+}
+
+impl<L: fmt::Debug> error::Error for TryUnwrapError<L> {}
+
+/// What a lock should do if its internal bookkeeping has been poisoned.
+///
+/// Every lock in this crate keeps track of its owners and waiters behind a
+/// `std::sync::Mutex`.  If a thread ever panics while holding that `Mutex`, it
+/// becomes poisoned, and the lock's state may be inconsistent.  By default,
+/// every subsequent operation on the lock will panic too.  Long-running
+/// services may prefer to degrade more gracefully.
+///
+/// Releasing a lock can't fail, so releases treat `Error` like `Ignore`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PoisonPolicy {
+    /// Panic.  This is the default.
+    Panic,
+    /// Fail acquisitions with [`AcquireError::Poisoned`].  `try_*` methods
+    /// will return `Err` too.
+    ///
+    /// [`AcquireError::Poisoned`]: enum.AcquireError.html#variant.Poisoned
+    Error,
+    /// Ignore the poison and carry on as if nothing had happened.
+    Ignore,
+}
+
+// Deriving Default for enums requires Rust 1.62.0
+#[allow(clippy::derivable_impls)]
+impl Default for PoisonPolicy {
+    fn default() -> Self {
+        PoisonPolicy::Panic
+    }
+}
+
+impl PoisonPolicy {
+    fn from_usize(v: usize) -> Self {
+        match v {
+            0 => PoisonPolicy::Panic,
+            1 => PoisonPolicy::Error,
+            2 => PoisonPolicy::Ignore,
+            _ => unreachable!()
+        }
+    }
+
+    fn to_usize(self) -> usize {
+        match self {
+            PoisonPolicy::Panic => 0,
+            PoisonPolicy::Error => 1,
+            PoisonPolicy::Ignore => 2
+        }
+    }
+}
+
+/// Which waiter gets a lock when it's released.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fairness {
+    /// Hand the lock to the task that has waited longest.  This is the
+    /// default.
+    Fifo,
+    /// Hand the lock to the task that most recently started waiting.  That
+    /// can improve throughput, because its working set is likelier to still
+    /// be cached, but early waiters may starve.
+    Lifo,
+    /// Don't hand the lock to anybody.  Instead, release it and wake the task
+    /// that has waited longest, which must then compete for it with any task
+    /// that tries to lock it in the meantime.  That improves throughput when
+    /// critical sections are short, but waiters may starve.  `RwLock` treats
+    /// this like `Fifo`.
+    Barging,
+}
+
+// Deriving Default for enums requires Rust 1.62.0
+#[allow(clippy::derivable_impls)]
+impl Default for Fairness {
+    fn default() -> Self {
+        Fairness::Fifo
+    }
+}
+
+impl Fairness {
+    /// Remove the next waiter to be served from `queue`.
+    fn pop<W>(self, queue: &mut VecDeque<W>) -> Option<W> {
+        match self {
+            Fairness::Fifo | Fairness::Barging => queue.pop_front(),
+            Fairness::Lifo => queue.pop_back()
+        }
+    }
+
+    /// Given the tickets of a queue's live waiters, in the order they joined,
+    /// return the number that will be served before `ticket`.
+    fn queue_position<I>(self, mut live: I, ticket: u64) -> Option<usize>
+        where I: Iterator<Item = u64>
+    {
+        let pos = live.position(|t| t == ticket)?;
+        match self {
+            Fairness::Fifo | Fairness::Barging => Some(pos),
+            Fairness::Lifo => Some(live.count())
+        }
+    }
+}
+
+/// Whether an `RwLock` favors readers or writers when both want it.  See
+/// [`RwLockBuilder::preference`](struct.RwLockBuilder.html#method.preference).
+#[cfg(feature = "rwlock")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "rwlock")))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Preference {
+    /// Readers never wait behind a queued writer.  They only wait while a
+    /// writer holds the lock, or while an upgradable reader, which already
+    /// holds it, is upgrading.  That suits read-dominated data such as
+    /// caches, but a steady stream of readers can starve writers.  This is
+    /// the default.
+    Readers,
+    /// Once a writer is waiting, new readers must wait until it has been
+    /// served.  That keeps writers from starving, but reduces read
+    /// throughput.
+    Writers,
+}
+
+#[cfg(feature = "rwlock")]
+#[allow(clippy::derivable_impls)]
+impl Default for Preference {
+    fn default() -> Self {
+        Preference::Readers
+    }
+}
+
+/// Something that happened to a lock.  Reported by
+/// [`Mutex::events`](struct.Mutex.html#method.events).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LockEvent {
+    /// A task started waiting for the lock.
+    Queued,
+    /// A task acquired the lock, with or without waiting first.
+    Acquired,
+    /// The lock was released.
+    Released,
+    /// A task stopped waiting for the lock before acquiring it.
+    Cancelled,
+    /// A task stopped waiting for the lock because its time limit expired.
+    /// See [`Mutex::lock_timeout`](struct.Mutex.html#method.lock_timeout).
+    TimedOut,
+}
+
+/// Whether and how a lock is held.  See [`LockInfo`](struct.LockInfo.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LockState {
+    /// Nobody holds the lock.
+    Unlocked,
+    /// One or more readers share the lock.
+    Shared,
+    /// One task holds the lock exclusively.
+    Exclusive,
+}
+
+/// A snapshot of a lock's state and settings, for debugging endpoints and
+/// other runtime introspection.  Returned by
+/// [`Mutex::info`](struct.Mutex.html#method.info) and
+/// [`RwLock::info`](struct.RwLock.html#method.info).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LockInfo {
+    name: Option<Cow<'static, str>>,
+    state: LockState,
+    readers: u32,
+    waiters: usize,
+    fairness: Fairness,
+    max_waiters: Option<usize>,
+    yield_budget: Option<u32>,
+    spin_limit: u32,
+    #[cfg(feature = "rwlock")]
+    preference: Option<Preference>,
+    max_readers: Option<u32>,
+    poison_policy: PoisonPolicy,
+    poison_on_panic: bool,
+    closed: bool,
+    #[cfg(feature = "stats")]
+    stats: Option<LockStats>,
+}
+
+impl LockInfo {
+    /// The lock's name, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| &name[..])
+    }
+
+    /// Whether and how the lock is held.
+    pub fn state(&self) -> LockState {
+        self.state
+    }
+
+    /// How many readers share the lock.  Always 0 for a `Mutex`.
+    pub fn readers(&self) -> u32 {
+        self.readers
+    }
+
+    /// How many tasks are waiting to acquire the lock.
+    pub fn waiters(&self) -> usize {
+        self.waiters
+    }
+
+    /// The order in which waiters acquire the lock.
+    pub fn fairness(&self) -> Fairness {
+        self.fairness
+    }
+
+    /// The most tasks that may wait for the lock at once, if limited.
+    pub fn max_waiters(&self) -> Option<usize> {
+        self.max_waiters
+    }
+
+    /// How many times in a row one task may acquire the lock without
+    /// waiting, if limited.
+    pub fn yield_budget(&self) -> Option<u32> {
+        self.yield_budget
+    }
+
+    /// How many times an acquisition checks for the lock's release before
+    /// joining the wait queue.  Always 0 for an `RwLock`.
+    pub fn spin_limit(&self) -> u32 {
+        self.spin_limit
+    }
+
+    /// Whether new readers may pass waiting writers.  Always `None` for a
+    /// `Mutex`.
+    #[cfg(feature = "rwlock")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "rwlock")))]
+    pub fn preference(&self) -> Option<Preference> {
+        self.preference
+    }
+
+    /// The most readers that may share the lock at once, if limited.  Always
+    /// `None` for a `Mutex`.
+    pub fn max_readers(&self) -> Option<u32> {
+        self.max_readers
+    }
+
+    /// What the lock does if its internal bookkeeping is poisoned.
+    pub fn poison_policy(&self) -> PoisonPolicy {
+        self.poison_policy
+    }
+
+    /// Whether the lock is poisoned when a panic unwinds through an
+    /// exclusive guard.
+    pub fn poison_on_panic(&self) -> bool {
+        self.poison_on_panic
+    }
+
+    /// Whether the lock has been closed.  Always `false` for an `RwLock`.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The lock's usage statistics, if it collects them.
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn stats(&self) -> Option<&LockStats> {
+        self.stats.as_ref()
+    }
+}
+
+/// Settings chosen when a lock is built.  They never change afterwards.
+#[derive(Debug)]
+struct LockConfig {
+    name: Option<Cow<'static, str>>,
+    fairness: Fairness,
+    /// The most tasks that may wait for the lock at once
+    max_waiters: Option<usize>,
+    /// How many times in a row one task may acquire the lock without waiting
+    yield_budget: Option<u32>,
+    /// How many times to check for release before joining the wait queue
+    #[cfg(feature = "mutex")]
+    spin_limit: u32,
+    /// Whether new readers may pass waiting writers
+    #[cfg(feature = "rwlock")]
+    preference: Preference,
+    /// The most readers that may share the lock at once
+    #[cfg(feature = "rwlock")]
+    max_readers: Option<u32>,
+    /// Whether a panic while holding the lock exclusively poisons it
+    poison_on_panic: bool,
+    /// Whether to collect statistics
+    #[cfg(feature = "stats")]
+    stats: bool,
+}
+
+// Statistics are on by default, so this isn't derivable with "stats"
+#[allow(clippy::derivable_impls)]
+impl Default for LockConfig {
+    fn default() -> Self {
+        LockConfig {
+            name: None,
+            fairness: Fairness::default(),
+            max_waiters: None,
+            yield_budget: None,
+            #[cfg(feature = "mutex")]
+            spin_limit: 0,
+            #[cfg(feature = "rwlock")]
+            preference: Preference::default(),
+            #[cfg(feature = "rwlock")]
+            max_readers: None,
+            poison_on_panic: false,
+            #[cfg(feature = "stats")]
+            stats: true,
+        }
+    }
+}
+
+/// Counts how many times in a row the same task has acquired a lock without
+/// waiting, to enforce [`LockConfig::yield_budget`].
+#[derive(Debug, Default)]
+struct Streak {
+    task: Option<Task>,
+    len: u32,
+}
+
+impl Streak {
+    /// Records an immediate acquisition by the current task.  Returns true if
+    /// that would exceed `budget`, in which case the task has been scheduled to
+    /// run again, and should yield instead of acquiring the lock.
+    fn exhausted(&mut self, budget: Option<u32>) -> bool {
+        let budget = match budget {
+            Some(budget) => budget,
+            None => return false
+        };
+        let same = self.task.as_ref().map_or(false, Task::will_notify_current);
+        if same {
+            self.len += 1;
+        } else {
+            self.task = Some(task::current());
+            self.len = 1;
+        }
+        if self.len > budget {
+            self.len = 0;
+            task::current().notify();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Statistics about a lock's usage, collected over its lifetime or since the
+/// last call to `reset_stats`.
+///
+/// Every clone of a lock shares the same statistics.
+#[cfg(feature = "stats")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct LockStats {
+    /// The greatest number of tasks that were ever simultaneously waiting to
+    /// acquire the lock.  Useful for finding locks whose contention spikes
+    /// under load, even if it's low on average.
+    pub max_queue_depth: usize,
+    /// How long each acquisition of the lock had to wait, in nanoseconds.
+    /// Acquisitions that didn't wait at all are recorded as zero.  The
+    /// `try_*` methods aren't recorded.  An `RwLock` records its shared and
+    /// exclusive acquisitions together.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use futures::executor::spawn;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// spawn(mtx.lock()).wait_future().unwrap();
+    /// let wait_times = mtx.stats().wait_times;
+    /// assert_eq!(wait_times.len(), 1);
+    /// println!("p99 wait: {}ns", wait_times.value_at_quantile(0.99));
+    /// # }
+    /// ```
+    #[cfg(feature = "metrics-histogram")]
+    #[cfg_attr(feature = "nightly-docs",
+               doc(cfg(feature = "metrics-histogram")))]
+    pub wait_times: Histogram<u64>,
+}
+
+// Histogram's equality is a full equivalence relation, it just doesn't say so
+#[cfg(feature = "stats")]
+impl Eq for LockStats {}
+
+#[cfg(feature = "stats")]
+impl Default for LockStats {
+    fn default() -> Self {
+        LockStats {
+            max_queue_depth: 0,
+            // 3 significant figures, auto-resizing
+            #[cfg(feature = "metrics-histogram")]
+            wait_times: Histogram::new(3).expect("Histogram::new"),
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl LockStats {
+    fn record_queue_depth(&mut self, depth: usize) {
+        self.max_queue_depth = self.max_queue_depth.max(depth);
+    }
+
+    #[cfg(feature = "metrics-histogram")]
+    fn record_wait_time(&mut self, waited: Duration) {
+        let nanos = waited.as_secs().saturating_mul(1_000_000_000)
+            .saturating_add(u64::from(waited.subsec_nanos()));
+        // The histogram auto-resizes, so recording can't fail
+        self.wait_times.record(nanos).expect("Histogram::record");
+    }
+}
+
+/// A validator with its data type erased.  It must only ever be called with a
+/// pointer to the type that it was installed for.
+type ValidateFn = dyn Fn(*const ()) -> bool + Send + Sync;
+
+/// A user-supplied invariant check, installed by `debug_validate`.
+///
+/// It's type-erased so that the only field of a lock's `Inner` that depends
+/// on the protected type is the data itself.  That allows a lock's `Arc` to
+/// be unsize-coerced, for example to `dyn Any`.
+struct Validator(sync::Mutex<Option<Box<ValidateFn>>>);
+
+impl Validator {
+    fn set<T, F>(&self, f: F)
+        where T: 'static,
+              F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        let f = move |data: *const ()| f(unsafe { &*(data as *const T) });
+        let mut validator = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        *validator = Some(Box::new(f));
+    }
+
+    /// Check `data` against the installed validator, if any.  Always passes in
+    /// builds without debug assertions, and while the thread is already
+    /// panicking, to avoid aborting the process.
+    fn check<T: ?Sized>(&self, data: *const T) -> bool {
+        let data = data as *const ();
+        if !cfg!(debug_assertions) || std::thread::panicking() {
+            return true;
+        }
+        let validator = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        validator.as_ref().map_or(true, |f| f(data))
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator(sync::Mutex::new(None))
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let installed = self.0.lock().map(|v| v.is_some()).unwrap_or(true);
+        f.debug_tuple("Validator").field(&installed).finish()
+    }
+}
+
+/// Lock a lock's internal bookkeeping in order to acquire the lock.
+fn lock_state<S>(m: &sync::Mutex<S>, policy: PoisonPolicy)
+    -> Result<sync::MutexGuard<'_, S>, AcquireError>
+{
+    match m.lock() {
+        Ok(guard) => Ok(guard),
+        Err(e) => match policy {
+            PoisonPolicy::Panic => panic!("sync::Mutex::lock: {:?}", e),
+            PoisonPolicy::Error => Err(AcquireError::Poisoned),
+            PoisonPolicy::Ignore => Ok(e.into_inner()),
+        }
+    }
+}
+
+/// Lock a lock's internal bookkeeping in order to release the lock, or for
+/// some other operation that can't fail.
+fn lock_state_infallible<S>(m: &sync::Mutex<S>, policy: PoisonPolicy)
+    -> sync::MutexGuard<'_, S>
+{
+    match m.lock() {
+        Ok(guard) => guard,
+        Err(e) => match policy {
+            PoisonPolicy::Panic => panic!("sync::Mutex::lock: {:?}", e),
+            PoisonPolicy::Error | PoisonPolicy::Ignore => e.into_inner()
+        }
+    }
+}
 
 /// Poll state of all Futures in this crate.
 enum FutState {
     New,
     Pending(oneshot::Receiver<()>),
+    /// Ownership was received while queued, but hasn't yet been returned
+    #[cfg_attr(not(feature = "rwlock"), allow(dead_code))]
+    Granted,
     Acquired
 }
+
+impl FutState {
+    /// A short description, for `Debug` implementations
+    fn name(&self) -> &'static str {
+        match *self {
+            FutState::New => "New",
+            FutState::Pending(_) => "Pending",
+            FutState::Granted => "Granted",
+            FutState::Acquired => "Acquired"
+        }
+    }
+}
+
+/// A `Notify` that ignores all notifications.
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+/// Poll `fut` once from a private task, so that it may be polled outside of
+/// any executor.
+fn poll_detached<F: Future>(fut: &mut F) -> Poll<F::Item, F::Error> {
+    executor::spawn(fut).poll_future_notify(&sync::Arc::new(NoopNotify), 0)
+}
@@ -0,0 +1,642 @@
+// vim: tw=80
+
+//! Single-threaded locks, for tasks that all run on one thread.
+//!
+//! They work like [`Mutex`] and [`RwLock`], but they use `Rc` and `Cell`
+//! instead of `Arc` and atomics, so they're cheaper but neither `Send` nor
+//! `Sync`.  They're suitable for executors like Tokio's `current_thread`
+//! runtime.  Waiters are always served in FIFO order.
+//!
+//! [`Mutex`]: ../struct.Mutex.html
+//! [`RwLock`]: ../struct.RwLock.html
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use super::{AcquireError, TryLockError, TryUnwrapError};
+
+/// A task waiting for a local lock
+#[derive(Default)]
+struct Waiter {
+    /// Set once the lock has been handed to this waiter
+    granted: Cell<bool>,
+    task: RefCell<Option<Task>>,
+}
+
+impl Waiter {
+    fn new() -> Rc<Waiter> {
+        let waiter = Rc::new(Waiter::default());
+        *waiter.task.borrow_mut() = Some(task::current());
+        waiter
+    }
+
+    /// Hand the lock to this waiter.
+    fn grant(&self) {
+        self.granted.set(true);
+        if let Some(task) = self.task.borrow_mut().take() {
+            task.notify();
+        }
+    }
+
+    /// Returns whether the waiter has been handed the lock.  If not, arrange
+    /// for the current task to be notified when it is.
+    fn poll(&self) -> bool {
+        if !self.granted.get() {
+            *self.task.borrow_mut() = Some(task::current());
+        }
+        self.granted.get()
+    }
+}
+
+/// The progress of a pending acquisition of a local lock
+#[derive(Default)]
+struct Acquisition {
+    /// Our place in line, if we had to wait
+    waiter: Option<Rc<Waiter>>,
+    /// Set once the acquisition's `Future` has returned a guard
+    acquired: bool,
+}
+
+impl Acquisition {
+    /// Returns `true` if the lock was handed to us, but our `Future` was
+    /// dropped before it could take it.  Otherwise, leaves `queue`.
+    fn abandon(&mut self, queue: &mut VecDeque<Rc<Waiter>>) -> bool {
+        match self.waiter.take() {
+            Some(ref w) if !self.acquired => {
+                if w.granted.get() {
+                    true
+                } else {
+                    queue.retain(|x| !Rc::ptr_eq(x, w));
+                    false
+                }
+            },
+            _ => false
+        }
+    }
+}
+
+#[cfg(feature = "mutex")]
+struct LocalMutexInner<T: ?Sized> {
+    owned: Cell<bool>,
+    waiters: RefCell<VecDeque<Rc<Waiter>>>,
+    data: UnsafeCell<T>,
+}
+
+/// A single-threaded version of [`Mutex`](struct.Mutex.html).  It's neither
+/// `Send` nor `Sync`, but it's cheaper to lock and unlock.
+///
+/// # Examples
+/// ```
+/// # extern crate futures;
+/// # extern crate futures_locks;
+/// # extern crate tokio_ as tokio;
+/// # use futures_locks::*;
+/// # use futures::Future;
+/// # use tokio::runtime::current_thread;
+/// # fn main() {
+/// let mtx = LocalMutex::<u32>::new(0);
+/// let mut rt = current_thread::Runtime::new().unwrap();
+/// for _ in 0..3 {
+///     rt.spawn(mtx.lock().map(|mut guard| *guard += 1).map_err(|_| ()));
+/// }
+/// rt.run().unwrap();
+/// assert_eq!(mtx.try_unwrap().unwrap(), 3);
+/// # }
+/// ```
+#[cfg(feature = "mutex")]
+pub struct LocalMutex<T: ?Sized> {
+    inner: Rc<LocalMutexInner<T>>,
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> Clone for LocalMutex<T> {
+    fn clone(&self) -> LocalMutex<T> {
+        LocalMutex{inner: self.inner.clone()}
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> fmt::Debug for LocalMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.inner.owned.get() { "Locked" } else { "Unlocked" };
+        f.debug_struct("LocalMutex")
+            .field("state", &state)
+            .field("waiters", &self.inner.waiters.borrow().len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: Default> Default for LocalMutex<T> {
+    fn default() -> Self {
+        LocalMutex::new(T::default())
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T> From<T> for LocalMutex<T> {
+    fn from(t: T) -> Self {
+        LocalMutex::new(t)
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T> LocalMutex<T> {
+    /// Create a new `LocalMutex` in the unlocked state.
+    pub fn new(t: T) -> LocalMutex<T> {
+        let inner = LocalMutexInner {
+            owned: Cell::new(false),
+            waiters: RefCell::new(VecDeque::new()),
+            data: UnsafeCell::new(t),
+        };
+        LocalMutex{inner: Rc::new(inner)}
+    }
+
+    /// Consumes the `LocalMutex` and returns the wrapped data, like
+    /// [`Mutex::try_unwrap`](struct.Mutex.html#method.try_unwrap).
+    pub fn try_unwrap(self) -> Result<T, TryUnwrapError<LocalMutex<T>>> {
+        match Rc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.data.into_inner()),
+            Err(rc) => {
+                let locked = rc.owned.get();
+                let waiters = rc.waiters.borrow().len();
+                let handles = Rc::strong_count(&rc);
+                let lock = LocalMutex{inner: rc};
+                Err(TryUnwrapError{lock, handles, locked, waiters})
+            }
+        }
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> LocalMutex<T> {
+    /// Returns a reference to the underlying data, if there are no other
+    /// clones of the `LocalMutex`.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        Rc::get_mut(&mut self.inner)
+            .map(|inner| unsafe {&mut *inner.data.get()})
+    }
+
+    /// Acquires the `LocalMutex`, like [`Mutex::lock`].  The returned
+    /// `Future` must be polled from within a task.
+    ///
+    /// [`Mutex::lock`]: struct.Mutex.html#method.lock
+    pub fn lock(&self) -> LocalMutexFut<T> {
+        LocalMutexFut{mutex: self.clone(), acq: Acquisition::default()}
+    }
+
+    /// Attempts to acquire the `LocalMutex` without waiting, like
+    /// [`Mutex::try_lock`].
+    ///
+    /// [`Mutex::try_lock`]: struct.Mutex.html#method.try_lock
+    pub fn try_lock(&self) -> Result<LocalMutexGuard<T>, TryLockError> {
+        if self.inner.owned.get() {
+            Err(TryLockError::WouldBlock)
+        } else {
+            self.inner.owned.set(true);
+            Ok(LocalMutexGuard{mutex: self.clone()})
+        }
+    }
+
+    /// Returns true if the two `LocalMutex`es point to the same data.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Rc::ptr_eq(&this.inner, &other.inner)
+    }
+
+    fn unlock(&self) {
+        let next = self.inner.waiters.borrow_mut().pop_front();
+        match next {
+            Some(waiter) => waiter.grant(),
+            None => self.inner.owned.set(false)
+        }
+    }
+}
+
+/// A `Future` representing a pending `LocalMutex` acquisition.
+#[cfg(feature = "mutex")]
+pub struct LocalMutexFut<T: ?Sized> {
+    mutex: LocalMutex<T>,
+    acq: Acquisition,
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> Future for LocalMutexFut<T> {
+    type Item = LocalMutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        assert!(!self.acq.acquired, "Double-poll of ready Future");
+        let inner = &self.mutex.inner;
+        match self.acq.waiter {
+            Some(ref waiter) => {
+                if !waiter.poll() {
+                    return Ok(Async::NotReady);
+                }
+            },
+            None if inner.owned.get() => {
+                let waiter = Waiter::new();
+                inner.waiters.borrow_mut().push_back(waiter.clone());
+                self.acq.waiter = Some(waiter);
+                return Ok(Async::NotReady);
+            },
+            None => inner.owned.set(true)
+        }
+        self.acq.acquired = true;
+        Ok(Async::Ready(LocalMutexGuard{mutex: self.mutex.clone()}))
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> Drop for LocalMutexFut<T> {
+    fn drop(&mut self) {
+        let abandoned = {
+            let mut waiters = self.mutex.inner.waiters.borrow_mut();
+            self.acq.abandon(&mut waiters)
+        };
+        if abandoned {
+            self.mutex.unlock();
+        }
+    }
+}
+
+/// An RAII guard for a [`LocalMutex`](struct.LocalMutex.html), much like
+/// [`MutexGuard`](struct.MutexGuard.html).
+#[cfg(feature = "mutex")]
+pub struct LocalMutexGuard<T: ?Sized> {
+    mutex: LocalMutex<T>,
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> Deref for LocalMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.mutex.inner.data.get()}
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> DerefMut for LocalMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {&mut *self.mutex.inner.data.get()}
+    }
+}
+
+#[cfg(feature = "mutex")]
+impl<T: ?Sized> Drop for LocalMutexGuard<T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[cfg(feature = "rwlock")]
+#[derive(Default)]
+struct LocalRwLockState {
+    exclusive: bool,
+    num_readers: u32,
+    read_waiters: VecDeque<Rc<Waiter>>,
+    write_waiters: VecDeque<Rc<Waiter>>,
+}
+
+#[cfg(feature = "rwlock")]
+struct LocalRwLockInner<T: ?Sized> {
+    state: RefCell<LocalRwLockState>,
+    data: UnsafeCell<T>,
+}
+
+/// A single-threaded version of [`RwLock`](struct.RwLock.html).  It's neither
+/// `Send` nor `Sync`, but it's cheaper to lock and unlock.
+///
+/// # Examples
+/// ```
+/// # extern crate futures;
+/// # extern crate futures_locks;
+/// # extern crate tokio_ as tokio;
+/// # use futures_locks::*;
+/// # use futures::Future;
+/// # use tokio::runtime::current_thread;
+/// # fn main() {
+/// let lock = LocalRwLock::<u32>::new(0);
+/// let mut rt = current_thread::Runtime::new().unwrap();
+/// rt.spawn(lock.write().map(|mut guard| *guard += 1).map_err(|_| ()));
+/// rt.spawn(lock.read().map(|guard| assert_eq!(*guard, 1)).map_err(|_| ()));
+/// rt.run().unwrap();
+/// # }
+/// ```
+#[cfg(feature = "rwlock")]
+pub struct LocalRwLock<T: ?Sized> {
+    inner: Rc<LocalRwLockInner<T>>,
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Clone for LocalRwLock<T> {
+    fn clone(&self) -> LocalRwLock<T> {
+        LocalRwLock{inner: self.inner.clone()}
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> fmt::Debug for LocalRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lock_state = self.inner.state.borrow();
+        let state = if lock_state.exclusive {
+            "Write"
+        } else if lock_state.num_readers > 0 {
+            "Read"
+        } else {
+            "Unlocked"
+        };
+        let waiters = lock_state.read_waiters.len() +
+            lock_state.write_waiters.len();
+        f.debug_struct("LocalRwLock")
+            .field("state", &state)
+            .field("readers", &lock_state.num_readers)
+            .field("waiters", &waiters)
+            .finish()
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: Default> Default for LocalRwLock<T> {
+    fn default() -> Self {
+        LocalRwLock::new(T::default())
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T> From<T> for LocalRwLock<T> {
+    fn from(t: T) -> Self {
+        LocalRwLock::new(t)
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T> LocalRwLock<T> {
+    /// Create a new `LocalRwLock` in the unlocked state.
+    pub fn new(t: T) -> LocalRwLock<T> {
+        let inner = LocalRwLockInner {
+            state: RefCell::new(LocalRwLockState::default()),
+            data: UnsafeCell::new(t),
+        };
+        LocalRwLock{inner: Rc::new(inner)}
+    }
+
+    /// Consumes the `LocalRwLock` and returns the wrapped data, like
+    /// [`RwLock::try_unwrap`](struct.RwLock.html#method.try_unwrap).
+    pub fn try_unwrap(self) -> Result<T, TryUnwrapError<LocalRwLock<T>>> {
+        match Rc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.data.into_inner()),
+            Err(rc) => {
+                let (locked, waiters) = {
+                    let state = rc.state.borrow();
+                    (state.exclusive || state.num_readers > 0,
+                     state.read_waiters.len() + state.write_waiters.len())
+                };
+                let handles = Rc::strong_count(&rc);
+                let lock = LocalRwLock{inner: rc};
+                Err(TryUnwrapError{lock, handles, locked, waiters})
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> LocalRwLock<T> {
+    /// Returns a reference to the underlying data, if there are no other
+    /// clones of the `LocalRwLock`.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        Rc::get_mut(&mut self.inner)
+            .map(|inner| unsafe {&mut *inner.data.get()})
+    }
+
+    /// Acquires the `LocalRwLock` nonexclusively, like [`RwLock::read`].  The
+    /// returned `Future` must be polled from within a task.
+    ///
+    /// [`RwLock::read`]: struct.RwLock.html#method.read
+    pub fn read(&self) -> LocalRwLockReadFut<T> {
+        LocalRwLockReadFut{rwlock: self.clone(), acq: Acquisition::default()}
+    }
+
+    /// Acquires the `LocalRwLock` exclusively, like [`RwLock::write`].  The
+    /// returned `Future` must be polled from within a task.
+    ///
+    /// [`RwLock::write`]: struct.RwLock.html#method.write
+    pub fn write(&self) -> LocalRwLockWriteFut<T> {
+        LocalRwLockWriteFut{rwlock: self.clone(), acq: Acquisition::default()}
+    }
+
+    /// Attempts to acquire the `LocalRwLock` nonexclusively without waiting,
+    /// like [`RwLock::try_read`].
+    ///
+    /// [`RwLock::try_read`]: struct.RwLock.html#method.try_read
+    pub fn try_read(&self) -> Result<LocalRwLockReadGuard<T>, TryLockError> {
+        let mut state = self.inner.state.borrow_mut();
+        if state.exclusive {
+            Err(TryLockError::WouldBlock)
+        } else {
+            state.num_readers += 1;
+            Ok(LocalRwLockReadGuard{rwlock: self.clone()})
+        }
+    }
+
+    /// Attempts to acquire the `LocalRwLock` exclusively without waiting,
+    /// like [`RwLock::try_write`].
+    ///
+    /// [`RwLock::try_write`]: struct.RwLock.html#method.try_write
+    pub fn try_write(&self) -> Result<LocalRwLockWriteGuard<T>, TryLockError>
+    {
+        let mut state = self.inner.state.borrow_mut();
+        if state.exclusive || state.num_readers > 0 {
+            Err(TryLockError::WouldBlock)
+        } else {
+            state.exclusive = true;
+            Ok(LocalRwLockWriteGuard{rwlock: self.clone()})
+        }
+    }
+
+    /// Returns true if the two `LocalRwLock`s point to the same data.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Rc::ptr_eq(&this.inner, &other.inner)
+    }
+
+    fn unlock_reader(&self) {
+        let mut state = self.inner.state.borrow_mut();
+        assert!(state.num_readers > 0);
+        state.num_readers -= 1;
+        if state.num_readers == 0 {
+            if let Some(waiter) = state.write_waiters.pop_front() {
+                state.exclusive = true;
+                waiter.grant();
+            }
+        }
+    }
+
+    fn unlock_writer(&self) {
+        let mut state = self.inner.state.borrow_mut();
+        assert!(state.exclusive);
+        if let Some(waiter) = state.write_waiters.pop_front() {
+            waiter.grant();
+            return;
+        }
+        state.exclusive = false;
+        state.num_readers += state.read_waiters.len() as u32;
+        for waiter in state.read_waiters.drain(..) {
+            waiter.grant();
+        }
+    }
+}
+
+/// A `Future` representing a pending nonexclusive `LocalRwLock` acquisition.
+#[cfg(feature = "rwlock")]
+pub struct LocalRwLockReadFut<T: ?Sized> {
+    rwlock: LocalRwLock<T>,
+    acq: Acquisition,
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Future for LocalRwLockReadFut<T> {
+    type Item = LocalRwLockReadGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        assert!(!self.acq.acquired, "Double-poll of ready Future");
+        match self.acq.waiter {
+            Some(ref waiter) => {
+                if !waiter.poll() {
+                    return Ok(Async::NotReady);
+                }
+            },
+            None => {
+                let mut state = self.rwlock.inner.state.borrow_mut();
+                if state.exclusive {
+                    let waiter = Waiter::new();
+                    state.read_waiters.push_back(waiter.clone());
+                    self.acq.waiter = Some(waiter);
+                    return Ok(Async::NotReady);
+                }
+                state.num_readers += 1;
+            }
+        }
+        self.acq.acquired = true;
+        Ok(Async::Ready(LocalRwLockReadGuard{rwlock: self.rwlock.clone()}))
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Drop for LocalRwLockReadFut<T> {
+    fn drop(&mut self) {
+        let abandoned = {
+            let mut state = self.rwlock.inner.state.borrow_mut();
+            self.acq.abandon(&mut state.read_waiters)
+        };
+        if abandoned {
+            self.rwlock.unlock_reader();
+        }
+    }
+}
+
+/// A `Future` representing a pending exclusive `LocalRwLock` acquisition.
+#[cfg(feature = "rwlock")]
+pub struct LocalRwLockWriteFut<T: ?Sized> {
+    rwlock: LocalRwLock<T>,
+    acq: Acquisition,
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Future for LocalRwLockWriteFut<T> {
+    type Item = LocalRwLockWriteGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        assert!(!self.acq.acquired, "Double-poll of ready Future");
+        match self.acq.waiter {
+            Some(ref waiter) => {
+                if !waiter.poll() {
+                    return Ok(Async::NotReady);
+                }
+            },
+            None => {
+                let mut state = self.rwlock.inner.state.borrow_mut();
+                if state.exclusive || state.num_readers > 0 {
+                    let waiter = Waiter::new();
+                    state.write_waiters.push_back(waiter.clone());
+                    self.acq.waiter = Some(waiter);
+                    return Ok(Async::NotReady);
+                }
+                state.exclusive = true;
+            }
+        }
+        self.acq.acquired = true;
+        Ok(Async::Ready(LocalRwLockWriteGuard{rwlock: self.rwlock.clone()}))
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Drop for LocalRwLockWriteFut<T> {
+    fn drop(&mut self) {
+        let abandoned = {
+            let mut state = self.rwlock.inner.state.borrow_mut();
+            self.acq.abandon(&mut state.write_waiters)
+        };
+        if abandoned {
+            self.rwlock.unlock_writer();
+        }
+    }
+}
+
+/// An RAII guard for nonexclusive access to a
+/// [`LocalRwLock`](struct.LocalRwLock.html).
+#[cfg(feature = "rwlock")]
+pub struct LocalRwLockReadGuard<T: ?Sized> {
+    rwlock: LocalRwLock<T>,
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Deref for LocalRwLockReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.rwlock.inner.data.get()}
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Drop for LocalRwLockReadGuard<T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_reader();
+    }
+}
+
+/// An RAII guard for exclusive access to a
+/// [`LocalRwLock`](struct.LocalRwLock.html).
+#[cfg(feature = "rwlock")]
+pub struct LocalRwLockWriteGuard<T: ?Sized> {
+    rwlock: LocalRwLock<T>,
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Deref for LocalRwLockWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.rwlock.inner.data.get()}
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> DerefMut for LocalRwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {&mut *self.rwlock.inner.data.get()}
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl<T: ?Sized> Drop for LocalRwLockWriteGuard<T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_writer();
+    }
+}
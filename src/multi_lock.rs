@@ -0,0 +1,169 @@
+// vim: tw=80
+
+//! Support code for the `multi_lock!` macro.  None of it is public API.
+
+use futures::{Future, Poll, future};
+use mutex::{Mutex, MutexFut, MutexGuard};
+use super::AcquireError;
+
+/// Acquires several [`Mutex`]es at once, returning a `Future` of a tuple of
+/// their guards, in argument order.
+///
+/// The `Mutex`es are always acquired in order of their addresses, regardless
+/// of argument order.  So two tasks that `multi_lock!` overlapping sets of
+/// `Mutex`es can't deadlock each other.  Supports two to four `Mutex`es, of
+/// any protected types.
+///
+/// # Panics
+///
+/// Polling the returned `Future` will panic if the same `Mutex` (or two
+/// clones of it) appears twice, since that could never succeed.
+///
+/// # Examples
+/// ```
+/// # #[macro_use] extern crate futures_locks;
+/// # extern crate futures;
+/// # use futures_locks::*;
+/// # use futures::Future;
+/// # use futures::executor::spawn;
+/// # fn main() {
+/// let a = Mutex::<u32>::new(1);
+/// let b = Mutex::<String>::new("two".to_owned());
+/// let fut = multi_lock!(a, b).map(|(mut ga, gb)| {
+///     *ga += gb.len() as u32;
+/// });
+/// spawn(fut).wait_future().unwrap();
+/// assert_eq!(*a.try_lock().unwrap(), 4);
+/// # }
+/// ```
+///
+/// [`Mutex`]: struct.Mutex.html
+#[macro_export]
+macro_rules! multi_lock {
+    ($a:expr, $b:expr $(,)*) => {{
+        let mut s0 = $crate::multi_lock::Slot::new(&$a);
+        let mut s1 = $crate::multi_lock::Slot::new(&$b);
+        let mut state = $crate::multi_lock::State::default();
+        $crate::multi_lock::poll_fn(move || {
+            if state.poll(&mut [&mut s0, &mut s1])?.is_not_ready() {
+                return Ok($crate::multi_lock::Async::NotReady);
+            }
+            Ok($crate::multi_lock::Async::Ready((s0.take(), s1.take())))
+        })
+    }};
+    ($a:expr, $b:expr, $c:expr $(,)*) => {{
+        let mut s0 = $crate::multi_lock::Slot::new(&$a);
+        let mut s1 = $crate::multi_lock::Slot::new(&$b);
+        let mut s2 = $crate::multi_lock::Slot::new(&$c);
+        let mut state = $crate::multi_lock::State::default();
+        $crate::multi_lock::poll_fn(move || {
+            if state.poll(&mut [&mut s0, &mut s1, &mut s2])?.is_not_ready() {
+                return Ok($crate::multi_lock::Async::NotReady);
+            }
+            Ok($crate::multi_lock::Async::Ready(
+                (s0.take(), s1.take(), s2.take())))
+        })
+    }};
+    ($a:expr, $b:expr, $c:expr, $d:expr $(,)*) => {{
+        let mut s0 = $crate::multi_lock::Slot::new(&$a);
+        let mut s1 = $crate::multi_lock::Slot::new(&$b);
+        let mut s2 = $crate::multi_lock::Slot::new(&$c);
+        let mut s3 = $crate::multi_lock::Slot::new(&$d);
+        let mut state = $crate::multi_lock::State::default();
+        $crate::multi_lock::poll_fn(move || {
+            if state.poll(&mut [&mut s0, &mut s1, &mut s2, &mut s3])?
+                .is_not_ready()
+            {
+                return Ok($crate::multi_lock::Async::NotReady);
+            }
+            Ok($crate::multi_lock::Async::Ready(
+                (s0.take(), s1.take(), s2.take(), s3.take())))
+        })
+    }};
+}
+
+pub use futures::Async;
+
+/// Like `futures::future::poll_fn`, but pins down the error type
+pub fn poll_fn<T, F>(f: F) -> future::PollFn<F>
+    where F: FnMut() -> Poll<T, AcquireError>
+{
+    future::poll_fn(f)
+}
+
+/// One of the `Mutex`es being acquired by `multi_lock!`
+pub struct Slot<T: ?Sized> {
+    mutex: Mutex<T>,
+    fut: Option<MutexFut<T>>,
+    guard: Option<MutexGuard<T>>,
+}
+
+impl<T: ?Sized> Slot<T> {
+    pub fn new(mutex: &Mutex<T>) -> Self {
+        Slot{mutex: mutex.clone(), fut: None, guard: None}
+    }
+
+    /// Take the guard, once every `Slot` has been acquired
+    pub fn take(&mut self) -> MutexGuard<T> {
+        self.guard.take().expect("multi_lock! polled after completion")
+    }
+}
+
+/// Type-erased interface to a `Slot`
+pub trait Acquire {
+    fn addr(&self) -> usize;
+
+    fn poll_acquire(&mut self) -> Poll<(), AcquireError>;
+}
+
+impl<T: ?Sized> Acquire for Slot<T> {
+    fn addr(&self) -> usize {
+        self.mutex.addr()
+    }
+
+    fn poll_acquire(&mut self) -> Poll<(), AcquireError> {
+        if self.fut.is_none() {
+            self.fut = Some(self.mutex.lock());
+        }
+        let guard = match self.fut.as_mut().unwrap().poll()? {
+            Async::Ready(guard) => guard,
+            Async::NotReady => return Ok(Async::NotReady)
+        };
+        self.fut = None;
+        self.guard = Some(guard);
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Progress of a `multi_lock!` acquisition
+#[derive(Default)]
+pub struct State {
+    /// Indices of the slots, in acquisition order.  Empty until first poll.
+    order: Vec<usize>,
+    /// How many slots, in acquisition order, have been acquired
+    acquired: usize,
+}
+
+impl State {
+    pub fn poll(&mut self, slots: &mut [&mut dyn Acquire])
+        -> Poll<(), AcquireError>
+    {
+        if self.order.is_empty() {
+            let mut order = (0..slots.len()).collect::<Vec<_>>();
+            order.sort_by_key(|&i| slots[i].addr());
+            for w in order.windows(2) {
+                assert!(slots[w[0]].addr() != slots[w[1]].addr(),
+                        "multi_lock! would deadlock on a duplicate Mutex");
+            }
+            self.order = order;
+        }
+        while self.acquired < self.order.len() {
+            let i = self.order[self.acquired];
+            if slots[i].poll_acquire()?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            self.acquired += 1;
+        }
+        Ok(Async::Ready(()))
+    }
+}
@@ -1,17 +1,43 @@
 // vim: tw=80
 
-use futures::{Async, Future, Poll};
-#[cfg(feature = "tokio")] use futures::future;
-#[cfg(feature = "tokio")] use futures::future::IntoFuture;
-use futures::sync::oneshot;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{self, Either, IntoFuture};
+use futures::sync::{mpsc, oneshot};
+use futures::task::{self, Task};
+use multi_lock::{self, Acquire, Slot, State};
+use std::any::Any;
+use std::borrow::Cow;
 use std::cell::UnsafeCell;
 use std::clone::Clone;
+#[cfg(feature = "timer")] use std::cmp;
 use std::collections::VecDeque;
+#[cfg(feature = "timer")] use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Deref, DerefMut};
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+use std::panic::Location;
+#[cfg(feature = "tokio")] use std::panic::AssertUnwindSafe;
+use std::ptr;
 use std::sync;
-use super::FutState;
+use std::sync::Arc;
+use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::{AcquireError, CancellableFut, Fairness, FutState, LockConfig,
+            LockEvent, LockInfo, LockState, PoisonPolicy, Streak, TryLockError,
+            TryUnwrapError, Validator, lock_state, lock_state_infallible,
+            poll_detached};
+#[cfg(feature = "stats")] use super::LockStats;
+#[cfg(any(feature = "metrics-histogram", feature = "timer"))]
+use std::time::{Duration, Instant};
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+use super::deadlock::Holder;
 #[cfg(feature = "tokio")] use tokio_executor::{self, Executor, SpawnError};
 #[cfg(feature = "tokio")] use tokio_current_thread as current_thread;
+#[cfg(feature = "timer")] use tokio_timer::Delay;
 
 /// An RAII mutex guard, much like `std::sync::MutexGuard`.  The wrapped data
 /// can be accessed via its `Deref` and `DerefMut` implementations.
@@ -21,7 +47,7 @@ pub struct MutexGuard<T: ?Sized> {
 
 impl<T: ?Sized> Drop for MutexGuard<T> {
     fn drop(&mut self) {
-        self.mutex.unlock();
+        self.mutex.release();
     }
 }
 
@@ -39,302 +65,3587 @@ impl<T: ?Sized> DerefMut for MutexGuard<T> {
     }
 }
 
-/// A `Future` representing a pending `Mutex` acquisition.
-pub struct MutexFut<T: ?Sized> {
-    state: FutState,
-    mutex: Mutex<T>,
-}
-
-impl<T: ?Sized> MutexFut<T> {
-    fn new(state: FutState, mutex: Mutex<T>) -> Self {
-        MutexFut{state, mutex}
-    }
-}
-
-impl<T: ?Sized> Drop for MutexFut<T> {
-    fn drop(&mut self) {
-        match &mut self.state {
-            &mut FutState::New => {
-                // Mutex hasn't yet been modified; nothing to do
-            },
-            &mut FutState::Pending(ref mut rx) => {
-                rx.close();
-                match rx.try_recv() {
-                    Ok(Some(())) => {
-                        // This future received ownership of the mutex, but got
-                        // dropped before it was ever polled.  Release the
-                        // mutex.
-                        self.mutex.unlock()
-                    },
-                    Ok(None) => {
-                        // Dropping the Future before it acquires the Mutex is
-                        // equivalent to cancelling it.
-                    },
-                    Err(oneshot::Canceled) => {
-                        // Never received ownership of the mutex
-                    }
-                }
-            },
-            &mut FutState::Acquired => {
-                // The MutexGuard will take care of releasing the Mutex
-            }
-        }
+/// Formats the protected data, like `std::sync::MutexGuard`.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
     }
 }
 
-impl<T: ?Sized> Future for MutexFut<T> {
-    type Item = MutexGuard<T>;
-    type Error = ();
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let (result, new_state) = match &mut self.state {
-            &mut FutState::New => {
-                let mut mtx_data = self.mutex.inner.mutex.lock()
-                    .expect("sync::Mutex::lock");
-                if mtx_data.owned {
-                    let (tx, mut rx) = oneshot::channel::<()>();
-                    mtx_data.waiters.push_back(tx);
-                    // Even though we know it isn't ready, we need to poll the
-                    // receiver in order to register our task for notification.
-                    assert!(rx.poll().unwrap().is_not_ready());
-                    (Ok(Async::NotReady), FutState::Pending(rx))
-                } else {
-                    mtx_data.owned = true;
-                    let guard = MutexGuard{mutex: self.mutex.clone()};
-                    (Ok(Async::Ready(guard)), FutState::Acquired)
-                }
-            },
-            &mut FutState::Pending(ref mut rx) => {
-                match rx.poll() {
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    // It's impossible for receiver.poll() to return an error.
-                    // The only way that would happen is if the sender got
-                    // dropped.  But that can't happen because the Mutex owns
-                    // the sender, and the Fut retains a clone of the Mutex
-                    Err(_) => unreachable!(),
-                    Ok(Async::Ready(_)) => {
-                        let state = FutState::Acquired;
-                        let result = Ok(Async::Ready(
-                                MutexGuard{mutex: self.mutex.clone()}));
-                        (result, state)
-                    }  //LCOV_EXCL_LINE    kcov false negative
-                }
-            },
-            &mut FutState::Acquired => panic!("Double-poll of ready Future")
-        };
-        self.state = new_state;
-        result
+/// Formats the protected data, like `std::sync::MutexGuard`.
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
     }
 }
 
-#[derive(Debug, Default)]
-struct MutexData {
-    owned: bool,
-    // FIFO queue of waiting tasks.
-    waiters: VecDeque<oneshot::Sender<()>>,
-}
-
-#[derive(Debug, Default)]
-struct Inner<T: ?Sized> {
-    mutex: sync::Mutex<MutexData>,
-    data: UnsafeCell<T>,
-}
-
-/// `MutexWeak` is a non-owning reference to a [`Mutex`].  `MutexWeak` is to 
-/// [`Mutex`] as [`std::sync::Weak`] is to [`std::sync::Arc`].
-/// 
+/// Writes to the protected data, so a shared writer can be used through its
+/// guard directly.
+///
 /// # Examples
 /// ```
-/// # use futures_locks::{Mutex,MutexGuard};
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use std::io::Write;
 /// # fn main() {
-/// let mutex = Mutex::<u32>::new(0);
-/// let mutex_weak = Mutex::downgrade(&mutex);
-/// let mutex_new = mutex_weak.upgrade().unwrap();
+/// let mtx = Mutex::<Vec<u8>>::new(Vec::new());
+/// write!(mtx.try_lock().unwrap(), "{}", 42).unwrap();
+/// assert_eq!(&mtx.try_unwrap().unwrap()[..], b"42");
 /// # }
 /// ```
-///
-/// [`Mutex`]: struct.Mutex.html
-/// [`std::sync::Weak`]: https://doc.rust-lang.org/std/sync/struct.Weak.html
-/// [`std::sync::Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
-#[derive(Debug)]
-pub struct MutexWeak<T: ?Sized> {
-    inner: sync::Weak<Inner<T>>,
-}
+impl<T: ?Sized + io::Write> io::Write for MutexGuard<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (**self).write(buf)
+    }
 
-impl<T: ?Sized> MutexWeak<T> {
-    /// Tries to upgrade the `MutexWeak` to `Mutex`. If the `Mutex` was dropped 
-    /// then the function return `None`.
-    pub fn upgrade(&self) -> Option<Mutex<T>> {
-        if let Some(inner) = self.inner.upgrade() {
-            return Some(Mutex{inner})
-        }
-        None
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
     }
-}
 
-impl<T: ?Sized> Clone for MutexWeak<T> {
-    fn clone(&self) -> MutexWeak<T> {
-        MutexWeak {inner: self.inner.clone()}
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
     }
-}
 
-unsafe impl<T: ?Sized + Send> Send for MutexWeak<T> {}
-unsafe impl<T: ?Sized + Send> Sync for MutexWeak<T> {}
+    fn write_fmt(&mut self, args: fmt::Arguments) -> io::Result<()> {
+        (**self).write_fmt(args)
+    }
+}
 
-/// A Futures-aware Mutex.
-///
-/// `std::sync::Mutex` cannot be used in an asynchronous environment like Tokio,
-/// because a mutex acquisition can block an entire reactor.  This class can be
-/// used instead.  It functions much like `std::sync::Mutex`.  Unlike that
-/// class, it also has a builtin `Arc`, making it accessible from multiple
-/// threads.  It's also safe to `clone`.  Also unlike `std::sync::Mutex`, this
-/// class does not detect lock poisoning.
+/// Writes to the protected data, so a shared buffer can be used through its
+/// guard directly.
 ///
 /// # Examples
-///
 /// ```
-/// # extern crate futures;
 /// # extern crate futures_locks;
 /// # use futures_locks::*;
-/// # use futures::executor::{Spawn, spawn};
-/// # use futures::Future;
+/// # use std::fmt::Write;
 /// # fn main() {
-/// let mtx = Mutex::<u32>::new(0);
-/// let fut = mtx.lock().map(|mut guard| { *guard += 5; });
-/// spawn(fut).wait_future();
-/// assert_eq!(mtx.try_unwrap().unwrap(), 5);
+/// let mtx = Mutex::<String>::new(String::new());
+/// write!(mtx.try_lock().unwrap(), "{}", 42).unwrap();
+/// assert_eq!(mtx.try_unwrap().unwrap(), "42");
 /// # }
 /// ```
-#[derive(Debug, Default)]
-pub struct Mutex<T: ?Sized> {
-    inner: sync::Arc<Inner<T>>,
-}
+impl<T: ?Sized + fmt::Write> fmt::Write for MutexGuard<T> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (**self).write_str(s)
+    }
 
-impl<T: ?Sized> Clone for Mutex<T> {
-    fn clone(&self) -> Mutex<T> {
-        Mutex { inner: self.inner.clone()}
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        (**self).write_char(c)
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
+        (**self).write_fmt(args)
     }
 }
 
-impl<T> Mutex<T> {
-    /// Create a new `Mutex` in the unlocked state.
-    pub fn new(t: T) -> Mutex<T> {
-        let mutex_data = MutexData {
-            owned: false,
-            waiters: VecDeque::new(),
+impl<T: ?Sized> MutexGuard<T> {
+    /// Consume the guard without releasing the `Mutex`, returning the `Mutex`.
+    fn into_mutex(this: MutexGuard<T>) -> Mutex<T> {
+        // Safe because the guard is forgotten, so its Mutex won't be dropped
+        // twice.
+        let mutex = unsafe { ptr::read(&this.mutex) };
+        mem::forget(this);
+        mutex
+    }
+
+    /// Returns a new handle to the `Mutex` that this guard locks, for example
+    /// to give to another task that will lock it later.
+    ///
+    /// This is an associated function, rather than a method, to avoid
+    /// conflicting with methods of the protected data.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let guard = Mutex::<u32>::new(0).try_lock().unwrap();
+    /// let mtx = MutexGuard::mutex(&guard);
+    /// assert!(mtx.try_lock().is_err());
+    /// drop(guard);
+    /// assert!(mtx.try_lock().is_ok());
+    /// # }
+    /// ```
+    pub fn mutex(this: &Self) -> Mutex<T> {
+        this.mutex.clone()
+    }
+
+    /// Returns which acquisition of the `Mutex` this guard represents,
+    /// counting from 1.  See
+    /// [`Mutex::current_generation`](struct.Mutex.html#method.current_generation).
+    pub fn generation(this: &Self) -> u64 {
+        // Nobody else can release the Mutex while we hold it
+        this.mutex.lock_state_infallible().releases.wrapping_add(1)
+    }
+
+    /// Consumes the guard without releasing the `Mutex`, and returns a
+    /// mutable reference to the protected data, like `Box::leak`.  The
+    /// `Mutex` stays locked forever, and its data is never freed, so the
+    /// reference may have any lifetime, including `'static`.  Useful for
+    /// process-lifetime singletons.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(Vec::new());
+    /// let registry: &'static mut Vec<u32> =
+    ///     MutexGuard::leak(mtx.try_lock().unwrap());
+    /// registry.push(42);
+    /// assert!(mtx.try_lock().is_err());
+    /// # }
+    /// ```
+    pub fn leak<'a>(this: Self) -> &'a mut T where T: 'a {
+        let data = this.mutex.inner.data.get();
+        // Leak our reference to the Mutex along with our ownership of it, so
+        // the data will outlive every other handle.
+        mem::forget(this);
+        unsafe {&mut *data}
+    }
+
+    /// Releases the `Mutex`, handing it to the task that has waited longest
+    /// regardless of the `Mutex`'s [`Fairness`](enum.Fairness.html).  The
+    /// releasing task won't get it back before every earlier waiter has had
+    /// a turn.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::builder().fairness(Fairness::Lifo).build(Vec::new());
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut fut1 = mtx.lock();
+    /// let mut fut2 = mtx.lock();
+    /// assert!(fut1.poll_once().unwrap().is_not_ready());
+    /// assert!(fut2.poll_once().unwrap().is_not_ready());
+    /// MutexGuard::unlock_fair(guard);
+    /// fut1.map(|mut g| g.push(1)).wait().unwrap();
+    /// fut2.map(|mut g| g.push(2)).wait().unwrap();
+    /// assert_eq!(mtx.try_unwrap().unwrap(), vec![1, 2]);
+    /// # }
+    /// ```
+    pub fn unlock_fair(this: Self) {
+        MutexGuard::into_mutex(this).release_with(Fairness::Fifo);
+    }
+
+    /// Narrows the guard to a part of the protected data, like
+    /// `parking_lot::MutexGuard::map`.  The `Mutex` stays locked until the
+    /// returned guard is dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// struct State { count: u32, name: String }
+    ///
+    /// let mtx = Mutex::new(State{count: 0, name: "foo".to_owned()});
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut count = MutexGuard::map(guard, |s| &mut s.count);
+    /// *count += 1;
+    /// assert!(mtx.try_lock().is_err());
+    /// drop(count);
+    /// assert_eq!(mtx.try_lock().unwrap().count, 1);
+    /// # }
+    /// ```
+    pub fn map<U, F>(mut this: Self, f: F) -> MappedMutexGuard<T, U>
+        where F: FnOnce(&mut T) -> &mut U,
+              U: ?Sized
+    {
+        let data = f(&mut *this) as *mut U;
+        MappedMutexGuard{mutex: MutexGuard::into_mutex(this), data}
+    }
+
+    /// Like [`map`](#method.map), but `f` may decline to narrow the guard, in
+    /// which case the original guard is returned.  Either way, the `Mutex`
+    /// stays locked.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<Option<u32>>::new(None);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut guard = MutexGuard::try_map(guard, Option::as_mut)
+    ///     .err().unwrap();
+    /// *guard = Some(1);
+    /// let inner = MutexGuard::try_map(guard, Option::as_mut).ok().unwrap();
+    /// assert_eq!(*inner, 1);
+    /// # }
+    /// ```
+    pub fn try_map<U, F>(mut this: Self, f: F)
+        -> Result<MappedMutexGuard<T, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>,
+              U: ?Sized
+    {
+        let data = match f(&mut *this) {
+            Some(data) => data as *mut U,
+            None => return Err(this)
         };
-        let inner = Inner {
-            mutex: sync::Mutex::new(mutex_data),
-            data: UnsafeCell::new(t)
-        };  //LCOV_EXCL_LINE    kcov false negative
-        Mutex { inner: sync::Arc::new(inner)}
+        Ok(MappedMutexGuard{mutex: MutexGuard::into_mutex(this), data})
     }
+}
 
-    /// Consumes the `Mutex` and returns the wrapped data.  If the `Mutex` still
-    /// has multiple references (not necessarily locked), returns a copy of
-    /// `self` instead.
-    pub fn try_unwrap(self) -> Result<T, Mutex<T>> {
-        match sync::Arc::try_unwrap(self.inner) {
-            Ok(inner) => Ok({
-                // `unsafe` is no longer needed as of somewhere around 1.25.0.
-                // https://github.com/rust-lang/rust/issues/35067
-                #[allow(unused_unsafe)]
-                unsafe { inner.data.into_inner() }
-            }),
-            Err(arc) => Err(Mutex {inner: arc})
-        }
+impl<T> MutexGuard<T> {
+    /// Replaces the protected data with `value`, returning the old value.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<String>::new("old".to_owned());
+    /// let mut guard = mtx.try_lock().unwrap();
+    /// assert_eq!(MutexGuard::replace(&mut guard, "new".to_owned()), "old");
+    /// assert_eq!(*guard, "new");
+    /// # }
+    /// ```
+    pub fn replace(this: &mut Self, value: T) -> T {
+        mem::replace(&mut **this, value)
+    }
+
+    /// Takes the protected data, leaving `T::default()` in its place.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![1, 2, 3]);
+    /// let mut guard = mtx.try_lock().unwrap();
+    /// assert_eq!(MutexGuard::take(&mut guard), vec![1, 2, 3]);
+    /// assert!(guard.is_empty());
+    /// # }
+    /// ```
+    pub fn take(this: &mut Self) -> T
+        where T: Default
+    {
+        MutexGuard::replace(this, T::default())
     }
 }
 
-impl<T: ?Sized> Mutex<T> {
-    /// Create a [`MutexWeak`] reference to this `Mutex`.
+impl MutexGuard<dyn Any + Send> {
+    /// Returns a reference to the protected data, if it's of type `U`.
     ///
-    /// [`MutexWeak`]: struct.MutexWeak.html
-    pub fn downgrade(this: &Mutex<T>) -> MutexWeak<T> {
-        MutexWeak {inner: sync::Arc::<Inner<T>>::downgrade(&this.inner)}
+    /// This is an associated function, rather than a method, to avoid
+    /// conflicting with `Any::downcast_ref` through `Deref`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use std::any::Any;
+    /// # fn main() {
+    /// let mtx: Mutex<dyn Any + Send> = Mutex::<u32>::new(42).into();
+    /// let guard = mtx.try_lock().unwrap();
+    /// assert_eq!(MutexGuard::downcast_ref::<u32>(&guard), Some(&42));
+    /// assert!(MutexGuard::downcast_ref::<i32>(&guard).is_none());
+    /// # }
+    /// ```
+    pub fn downcast_ref<U: Any>(this: &Self) -> Option<&U> {
+        (**this).downcast_ref::<U>()
     }
 
-    /// Returns a reference to the underlying data, if there are no other
-    /// clones of the `Mutex`.
+    /// Returns a mutable reference to the protected data, if it's of type
+    /// `U`.
+    pub fn downcast_mut<U: Any>(this: &mut Self) -> Option<&mut U> {
+        (**this).downcast_mut::<U>()
+    }
+
+    /// Converts the guard into one that dereferences directly to `U`, if the
+    /// protected data is of type `U`.  Otherwise, returns the original guard.
+    /// Either way, the `Mutex` stays locked.
     ///
-    /// Since this call borrows the `Mutex` mutably, no actual locking takes
-    /// place -- the mutable borrow statically guarantees no locks exist.
-    /// However, if the `Mutex` has already been cloned, then `None` will be
-    /// returned instead.
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use std::any::Any;
+    /// # fn main() {
+    /// let mtx: Mutex<dyn Any + Send> = Mutex::<u32>::new(42).into();
+    /// let guard = mtx.try_lock().unwrap();
+    /// let guard = MutexGuard::downcast::<i32>(guard).err().unwrap();
+    /// let mut guard = MutexGuard::downcast::<u32>(guard).ok().unwrap();
+    /// *guard += 1;
+    /// drop(guard);
+    /// let guard = mtx.try_lock().unwrap();
+    /// assert_eq!(MutexGuard::downcast_ref::<u32>(&guard), Some(&43));
+    /// # }
+    /// ```
+    pub fn downcast<U: Any + Send>(mut this: Self)
+        -> Result<MappedMutexGuard<dyn Any + Send, U>, Self>
+    {
+        let data = match MutexGuard::downcast_mut::<U>(&mut this) {
+            Some(data) => data as *mut U,
+            None => return Err(this)
+        };
+        Ok(MappedMutexGuard{mutex: MutexGuard::into_mutex(this), data})
+    }
+}
+
+impl<T: ?Sized> MutexGuard<T> {
+    /// Splits the guard into disjoint sub-guards, each covering `chunk_size`
+    /// elements of the protected slice (the last one may be shorter).  The
+    /// sub-guards may be sent to different tasks or threads.  The `Mutex`
+    /// stays locked until every sub-guard has been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use std::thread;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![0; 8]);
+    /// let chunks = MutexGuard::split_chunks(mtx.try_lock().unwrap(), 3);
+    /// assert_eq!(chunks.len(), 3);
+    /// let threads = chunks.into_iter().map(|mut chunk| {
+    ///     thread::spawn(move || {
+    ///         for x in chunk.iter_mut() {
+    ///             *x += 1;
+    ///         }
+    ///     })
+    /// }).collect::<Vec<_>>();
+    /// for t in threads {
+    ///     t.join().unwrap();
+    /// }
+    /// assert_eq!(*mtx.try_lock().unwrap(), vec![1; 8]);
+    /// # }
+    /// ```
+    pub fn split_chunks<E>(mut this: Self, chunk_size: usize)
+        -> Vec<MutexChunkGuard<T, E>>
+        where T: AsMut<[E]>
+    {
+        assert!(chunk_size > 0, "chunk_size must be nonzero");
+        let slice: *mut [E] = (*this).as_mut();
+        let release = Arc::new(SharedRelease(MutexGuard::into_mutex(this)));
+        // Safe because the chunks are disjoint, and the Mutex remains locked
+        // until the last of them holding `release` is dropped.
+        unsafe {&mut *slice}.chunks_mut(chunk_size)
+            .map(|chunk| MutexChunkGuard {
+                _release: release.clone(),
+                data: chunk as *mut [E]
+            }).collect()
+    }
+
+    /// Converts the guard into one that only allows shared access to the
+    /// protected data.  The `Mutex` stays locked until the returned guard is
+    /// dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// fn total(v: &[u32]) -> u32 { v.iter().sum() }
+    ///
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![1, 2]);
+    /// let mut guard = mtx.try_lock().unwrap();
+    /// guard.push(3);
+    /// let guard = MutexGuard::downgrade(guard);
+    /// assert_eq!(total(&guard), 6);
+    /// assert!(mtx.try_lock().is_err());
+    /// # }
+    /// ```
+    pub fn downgrade(this: Self) -> ReadOnlyGuard<T> {
+        ReadOnlyGuard{guard: this}
+    }
+
+    /// Releases the `Mutex`, returning a `Future` that completes once its next
+    /// owner's task has taken it.  That's useful for hand-off protocols, where
+    /// the releasing task must know that its successor is running.  If nobody
+    /// is waiting for the `Mutex`, the `Future` completes immediately.  With
+    /// [`Fairness::Barging`](enum.Fairness.html), the next owner is whichever
+    /// task takes the `Mutex` first.
+    ///
+    /// A waiter that gives up after being handed the `Mutex`, but before
+    /// taking it, passes the obligation on to the next waiter.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut successor = mtx.lock();
+    /// assert!(successor.poll_once().unwrap().is_not_ready());
+    /// let released = MutexGuard::release_async(guard);
+    /// let (guard, ()) = successor.join(released).wait().ok().unwrap();
+    /// assert_eq!(*guard, 0);
+    /// # }
+    /// ```
+    pub fn release_async(this: Self)
+        -> impl Future<Item = (), Error = AcquireError>
+    {
+        let (tx, rx) = oneshot::channel();
+        let mutex = MutexGuard::into_mutex(this);
+        mutex.lock_state_infallible().ack = Some(tx);
+        mutex.release();
+        // A dropped sender means that the Mutex itself is gone
+        rx.then(|_| Ok(()))
+    }
+
+    /// Gives waiting tasks a turn with the `Mutex`, like
+    /// `parking_lot::MutexGuard::bump`.  If any task is waiting, the `Mutex`
+    /// is handed to it and the caller gets back in line; the returned
+    /// `Future` resolves to a fresh guard once the `Mutex` comes back.
+    /// Otherwise, it resolves to the original guard immediately.
+    ///
+    /// That's useful for long-running loops that hold the `Mutex`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(Vec::new());
+    /// let mut guard = mtx.try_lock().unwrap();
+    /// guard.push(1);
+    /// let mut waiter = mtx.lock();
+    /// assert!(waiter.poll_once().unwrap().is_not_ready());
+    /// let bumped = MutexGuard::bump(guard);
+    /// // The waiter goes first
+    /// waiter.wait().unwrap().push(2);
+    /// bumped.wait().unwrap().push(3);
+    /// assert_eq!(mtx.try_unwrap().unwrap(), vec![1, 2, 3]);
+    /// # }
+    /// ```
+    pub fn bump(this: Self)
+        -> impl Future<Item = MutexGuard<T>, Error = AcquireError>
+    {
+        if this.mutex.lock_state_infallible().live_waiters() == 0 {
+            return Either::A(Ok(this).into_future());
+        }
+        let mutex = MutexGuard::into_mutex(this);
+        let fut = mutex.lock();
+        mutex.release();
+        Either::B(fut)
+    }
+
+    /// Releases the `Mutex` right away, runs `f` without it, and then
+    /// reacquires it.  The returned `Future` resolves to a fresh guard and
+    /// `f`'s output.  Other tasks may take the `Mutex` in the meantime, so any
+    /// assumptions about the protected data must be rechecked.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, future};
+    /// # use std::io;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let io = future::lazy(|| {
+    ///     // The Mutex is available while the I/O is in progress
+    ///     assert!(mtx.try_lock().is_ok());
+    ///     Ok::<u32, io::Error>(42)
+    /// });
+    /// let (mut guard, n) = MutexGuard::unlocked(guard, io).wait().unwrap();
+    /// *guard += n;
+    /// assert_eq!(*guard, 42);
+    /// # }
+    /// ```
+    pub fn unlocked<F>(this: Self, f: F)
+        -> impl Future<Item = (MutexGuard<T>, F::Item), Error = F::Error>
+        where F: IntoFuture,
+              F::Error: From<AcquireError>
+    {
+        let mutex = MutexGuard::into_mutex(this);
+        mutex.release();
+        f.into_future().and_then(move |item| {
+            mutex.lock()
+                .map(move |guard| (guard, item))
+                .map_err(F::Error::from)
+        })
+    }
+
+    /// Converts the guard into one that will release the `Mutex` by itself if
+    /// it's still held after `limit`.  That's a safety net for critical
+    /// sections that call into code that might never return control.
+    ///
+    /// Spawns a task onto the default executor to enforce the limit, using
+    /// the default Tokio timer.  If there's no timer, or the task can't be
+    /// spawned, the `Mutex` is released and an error is returned, because the
+    /// limit couldn't be enforced.  See [Timers](index.html#timers).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, lazy};
+    /// # use std::time::Duration;
+    /// # use tokio::runtime::current_thread;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let mut rt = current_thread::Runtime::new().unwrap();
+    /// rt.block_on(lazy(|| {
+    ///     let guard = mtx.try_lock().unwrap();
+    ///     let limited = MutexGuard::with_hold_limit(guard,
+    ///                                               Duration::from_secs(60))
+    ///         .unwrap();
+    ///     assert_eq!(HoldLimitGuard::with(&limited, |x| { *x += 1; *x }),
+    ///                Some(1));
+    ///     Ok::<(), ()>(())
+    /// })).unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    pub fn with_hold_limit(this: Self, limit: Duration)
+        -> Result<HoldLimitGuard<T>, HoldLimitError>
+        where T: Send + 'static
+    {
+        let mut delay = Delay::new(Instant::now() + limit);
+        if poll_detached(&mut delay).is_err() {
+            return Err(HoldLimitError::NoTimer);
+        }
+        let guard = Arc::new(sync::Mutex::new(Some(this)));
+        let weak = Arc::downgrade(&guard);
+        let deadline = delay
+            .then(move |_| {
+                // If the timer failed, release the Mutex early rather than
+                // never.
+                if let Some(guard) = weak.upgrade() {
+                    HoldLimitGuard{guard}.expire();
+                }
+                Ok(())
+            });
+        tokio_executor::DefaultExecutor::current().spawn(Box::new(deadline))
+            .map_err(HoldLimitError::Spawn)?;
+        Ok(HoldLimitGuard{guard})
+    }
+}
+
+/// An RAII guard that holds a `Mutex` locked, but only allows shared access to
+/// its data.  Created by [`MutexGuard::downgrade`].
+///
+/// [`MutexGuard::downgrade`]: struct.MutexGuard.html#method.downgrade
+pub struct ReadOnlyGuard<T: ?Sized> {
+    guard: MutexGuard<T>,
+}
+
+impl<T: ?Sized> Deref for ReadOnlyGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+/// An RAII guard that releases its `Mutex` when dropped, or when its hold
+/// limit expires, whichever comes first.  Created by
+/// [`MutexGuard::with_hold_limit`].
+///
+/// Since the `Mutex` may be released at any time, the protected data is only
+/// accessible through [`HoldLimitGuard::with`].
+///
+/// [`MutexGuard::with_hold_limit`]: struct.MutexGuard.html#method.with_hold_limit
+/// [`HoldLimitGuard::with`]: #method.with
+#[cfg(feature = "timer")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub struct HoldLimitGuard<T: ?Sized> {
+    guard: Arc<sync::Mutex<Option<MutexGuard<T>>>>,
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> HoldLimitGuard<T> {
+    fn slot(&self) -> sync::MutexGuard<'_, Option<MutexGuard<T>>> {
+        // A panic within `with` doesn't invalidate the slot itself
+        self.guard.lock().unwrap_or_else(sync::PoisonError::into_inner)
+    }
+
+    fn expire(&self) {
+        self.slot().take();
+    }
+
+    /// Calls `f` with the protected data, unless the hold limit has already
+    /// expired.  The `Mutex` can't be released while `f` runs.
+    pub fn with<F, R>(this: &Self, f: F) -> Option<R>
+        where F: FnOnce(&mut T) -> R
+    {
+        this.slot().as_mut().map(|guard| f(&mut **guard))
+    }
+
+    /// Returns `true` if the hold limit has expired, and the `Mutex` has been
+    /// released.
+    pub fn is_expired(this: &Self) -> bool {
+        this.slot().is_none()
+    }
+}
+
+/// The error returned by [`MutexGuard::with_hold_limit`].  Either way, the
+/// `Mutex` has already been released.
+///
+/// [`MutexGuard::with_hold_limit`]: struct.MutexGuard.html#method.with_hold_limit
+#[cfg(feature = "timer")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+#[derive(Debug)]
+pub enum HoldLimitError {
+    /// There's no Tokio timer to enforce the limit.
+    NoTimer,
+    /// The task that enforces the limit couldn't be spawned.
+    Spawn(SpawnError),
+}
+
+#[cfg(feature = "timer")]
+impl fmt::Display for HoldLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HoldLimitError::NoTimer => f.write_str("no timer is available"),
+            HoldLimitError::Spawn(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "timer")]
+impl error::Error for HoldLimitError {}
+
+/// Releases a `Mutex` when dropped.  Shared by the sub-guards of a split
+/// `MutexGuard`.
+struct SharedRelease<T: ?Sized>(Mutex<T>);
+
+impl<T: ?Sized> Drop for SharedRelease<T> {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// What a [`TransactionGuard`] does with its changes if it's dropped without
+/// being committed or rolled back.  Either way, a guard that's dropped during
+/// a panic rolls back, so a panic can't leave the data half-updated.
+///
+/// [`TransactionGuard`]: struct.TransactionGuard.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransactionMode {
+    /// Keep the changes.
+    Commit,
+    /// Discard the changes, for example after an early return or an error.
+    Rollback,
+}
+
+/// An RAII guard for a transaction on a `Mutex`'s protected data, created by
+/// [`Mutex::lock_transactional`].  Changes made through it apply directly,
+/// but a snapshot of the original data is restored if the transaction rolls
+/// back.  The `Mutex` is released when the guard is committed, rolled back,
+/// or dropped.
+///
+/// [`Mutex::lock_transactional`]: struct.Mutex.html#method.lock_transactional
+pub struct TransactionGuard<T> {
+    /// Only `None` while dropping
+    guard: Option<MutexGuard<T>>,
+    snapshot: Option<T>,
+    mode: TransactionMode,
+}
+
+impl<T> TransactionGuard<T> {
+    /// Keeps the changes, and releases the `Mutex`.
+    pub fn commit(mut this: Self) {
+        this.snapshot = None;
+    }
+
+    /// Discards the changes, and releases the `Mutex`.
+    pub fn rollback(mut this: Self) {
+        this.mode = TransactionMode::Rollback;
+    }
+}
+
+impl<T> Deref for TransactionGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for TransactionGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+/// Formats the protected data, like `std::sync::MutexGuard`.
+impl<T: fmt::Debug> fmt::Debug for TransactionGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for TransactionGuard<T> {
+    fn drop(&mut self) {
+        let mut guard = self.guard.take().unwrap();
+        let rollback = thread::panicking() ||
+            self.mode == TransactionMode::Rollback;
+        match self.snapshot.take() {
+            Some(snapshot) if rollback => {
+                *guard = snapshot;
+                // The data is as it was, so neither poison the Mutex nor
+                // check its validator.
+                MutexGuard::into_mutex(guard).unlock();
+            },
+            _ => drop(guard)
+        }
+    }
+}
+
+/// An RAII guard for one chunk of a `Mutex`'s protected slice, created by
+/// [`MutexGuard::split_chunks`].  The `Mutex` is released once all of the
+/// chunks' guards have been dropped.
+///
+/// [`MutexGuard::split_chunks`]: struct.MutexGuard.html#method.split_chunks
+pub struct MutexChunkGuard<T: ?Sized, E> {
+    _release: Arc<SharedRelease<T>>,
+    data: *mut [E],
+}
+
+impl<T: ?Sized, E> Deref for MutexChunkGuard<T, E> {
+    type Target = [E];
+
+    fn deref(&self) -> &[E] {
+        unsafe {&*self.data}
+    }
+}
+
+impl<T: ?Sized, E> DerefMut for MutexChunkGuard<T, E> {
+    fn deref_mut(&mut self) -> &mut [E] {
+        unsafe {&mut *self.data}
+    }
+}
+
+unsafe impl<T: ?Sized + Send, E: Send> Send for MutexChunkGuard<T, E> {}
+unsafe impl<T: ?Sized + Send, E: Sync> Sync for MutexChunkGuard<T, E> {}
+
+/// An RAII guard for a part of a `Mutex`'s protected data, created by
+/// [`MutexGuard::map`] or [`MutexGuard::downcast`].  The `Mutex` is released
+/// when the guard is dropped.
+///
+/// [`MutexGuard::map`]: struct.MutexGuard.html#method.map
+/// [`MutexGuard::downcast`]: struct.MutexGuard.html#method.downcast
+pub struct MappedMutexGuard<T: ?Sized, U: ?Sized> {
+    mutex: Mutex<T>,
+    data: *mut U,
+}
+
+impl<T: ?Sized, U: ?Sized> Drop for MappedMutexGuard<T, U> {
+    fn drop(&mut self) {
+        self.mutex.release();
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> MappedMutexGuard<T, U> {
+    /// Narrows the guard further, like [`MutexGuard::map`].
+    ///
+    /// [`MutexGuard::map`]: struct.MutexGuard.html#method.map
+    pub fn map<V, F>(this: Self, f: F) -> MappedMutexGuard<T, V>
+        where F: FnOnce(&mut U) -> &mut V,
+              V: ?Sized
+    {
+        let data = f(unsafe {&mut *this.data}) as *mut V;
+        MappedMutexGuard{mutex: MappedMutexGuard::into_mutex(this), data}
+    }
+
+    /// Narrows the guard further, if `f` returns `Some`, like
+    /// [`MutexGuard::try_map`].  Otherwise, returns the original guard.
+    ///
+    /// [`MutexGuard::try_map`]: struct.MutexGuard.html#method.try_map
+    pub fn try_map<V, F>(this: Self, f: F)
+        -> Result<MappedMutexGuard<T, V>, Self>
+        where F: FnOnce(&mut U) -> Option<&mut V>,
+              V: ?Sized
+    {
+        let data = match f(unsafe {&mut *this.data}) {
+            Some(data) => data as *mut V,
+            None => return Err(this)
+        };
+        Ok(MappedMutexGuard{mutex: MappedMutexGuard::into_mutex(this), data})
+    }
+
+    /// Consume the guard without releasing the `Mutex`, returning the `Mutex`.
+    fn into_mutex(this: Self) -> Mutex<T> {
+        // Safe because the guard is forgotten, so its Mutex won't be dropped
+        // twice.
+        let mutex = unsafe { ptr::read(&this.mutex) };
+        mem::forget(this);
+        mutex
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Deref for MappedMutexGuard<T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe {&*self.data}
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> DerefMut for MappedMutexGuard<T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe {&mut *self.data}
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug
+    for MappedMutexGuard<T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + fmt::Display> fmt::Display
+    for MappedMutexGuard<T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+unsafe impl<T: ?Sized + Send, U: ?Sized + Send> Send
+    for MappedMutexGuard<T, U> {}
+unsafe impl<T: ?Sized + Send, U: ?Sized + Sync> Sync
+    for MappedMutexGuard<T, U> {}
+
+type ProjectFn<T, U> = dyn Fn(&mut T) -> &mut U + Send + Sync;
+
+/// A handle to part of a `Mutex`'s protected data, created by
+/// [`Mutex::project`].  It shares the `Mutex`'s lock, but only exposes the
+/// projected part.
+///
+/// [`Mutex::project`]: struct.Mutex.html#method.project
+pub struct MappedMutex<T: ?Sized, U: ?Sized> {
+    mutex: Mutex<T>,
+    f: Arc<ProjectFn<T, U>>,
+}
+
+impl<T: ?Sized, U: ?Sized> Clone for MappedMutex<T, U> {
+    fn clone(&self) -> Self {
+        MappedMutex{mutex: self.mutex.clone(), f: self.f.clone()}
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> MappedMutex<T, U> {
+    fn map_guard(&self, mut guard: MutexGuard<T>) -> MappedMutexGuard<T, U> {
+        let data = (self.f)(&mut *guard) as *mut U;
+        MappedMutexGuard{mutex: MutexGuard::into_mutex(guard), data}
+    }
+
+    /// Acquires the underlying `Mutex`, like [`Mutex::lock`].
+    ///
+    /// [`Mutex::lock`]: struct.Mutex.html#method.lock
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock(&self) -> MappedMutexFut<T, U> {
+        MappedMutexFut{fut: self.mutex.lock(), mapped: self.clone()}
+    }
+
+    /// Attempts to acquire the underlying `Mutex` without waiting, like
+    /// [`Mutex::try_lock`].
+    ///
+    /// [`Mutex::try_lock`]: struct.Mutex.html#method.try_lock
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn try_lock(&self) -> Result<MappedMutexGuard<T, U>, TryLockError> {
+        self.mutex.try_lock_unperturbed().map(|guard| self.map_guard(guard))
+    }
+
+    /// Narrows the handle further, like [`Mutex::project`].  The result still
+    /// shares the original `Mutex`'s lock.
+    ///
+    /// [`Mutex::project`]: struct.Mutex.html#method.project
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::new((0u32, (1u32, 2u32)));
+    /// let pair = mtx.project(|state| &mut state.1);
+    /// let second = pair.project(|pair| &mut pair.1);
+    /// *second.try_lock().unwrap() += 1;
+    /// assert_eq!(*mtx.try_lock().unwrap(), (0, (1, 3)));
+    /// # }
+    /// ```
+    pub fn project<V, G>(&self, g: G) -> MappedMutex<T, V>
+        where T: 'static,
+              U: 'static,
+              V: ?Sized,
+              G: Fn(&mut U) -> &mut V + Send + Sync + 'static
+    {
+        let f = self.f.clone();
+        let f: Arc<ProjectFn<T, V>> = Arc::new(move |t| g(f(t)));
+        MappedMutex{mutex: self.mutex.clone(), f}
+    }
+}
+
+/// A `Future` representing a pending `MappedMutex` acquisition.
+pub struct MappedMutexFut<T: ?Sized, U: ?Sized> {
+    fut: MutexFut<T>,
+    mapped: MappedMutex<T, U>,
+}
+
+impl<T: ?Sized, U: ?Sized> Future for MappedMutexFut<T, U> {
+    type Item = MappedMutexGuard<T, U>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let guard = match self.fut.poll()? {
+            Async::Ready(guard) => guard,
+            Async::NotReady => return Ok(Async::NotReady)
+        };
+        Ok(Async::Ready(self.mapped.map_guard(guard)))
+    }
+}
+
+/// A `Future` representing a pending `Mutex` acquisition with a time limit.
+/// Returned by [`Mutex::lock_timeout`](struct.Mutex.html#method.lock_timeout)
+/// and [`Mutex::lock_until`](struct.Mutex.html#method.lock_until).
+#[cfg(feature = "timer")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub struct LockTimeoutFut<T: ?Sized> {
+    /// `None` once the time limit has expired
+    fut: Option<MutexFut<T>>,
+    delay: Delay,
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> fmt::Debug for LockTimeoutFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LockTimeoutFut")
+            .field("fut", &self.fut)
+            .field("deadline", &self.delay.deadline())
+            .finish()
+    }
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> Future for LockTimeoutFut<T> {
+    type Item = MutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let fut = self.fut.as_mut().expect("Double-poll of failed Future");
+            if let Async::Ready(guard) = fut.poll()? {
+                return Ok(Async::Ready(guard));
+            }
+        }
+        match self.delay.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // A timer error means that the delay will never fire, so give up
+            // now rather than wait forever.
+            Ok(Async::Ready(())) | Err(_) => {
+                // Leave the Mutex's wait queue
+                if let Some(mut fut) = self.fut.take() {
+                    fut.timed_out = true;
+                }
+                Err(AcquireError::TimedOut)
+            }
+        }
+    }
+}
+
+/// A `Future` representing a `Mutex` acquisition that polls with backoff
+/// instead of joining the wait queue.  Returned by
+/// [`Mutex::lock_eventually`](struct.Mutex.html#method.lock_eventually).
+#[cfg(feature = "timer")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub struct LockEventuallyFut<T: ?Sized> {
+    mutex: Mutex<T>,
+    /// The pause before the next attempt, if the last one failed
+    delay: Option<Delay>,
+    /// How long the next pause will be
+    backoff: Duration,
+    /// A queued acquisition, used only if the timer fails
+    fallback: Option<MutexFut<T>>,
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> LockEventuallyFut<T> {
+    /// The pause after the first failed attempt, in milliseconds
+    const MIN_BACKOFF_MS: u64 = 1;
+    /// Pauses double after each failed attempt, up to this many milliseconds
+    const MAX_BACKOFF_MS: u64 = 100;
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> fmt::Debug for LockEventuallyFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LockEventuallyFut")
+            .field("mutex", &self.mutex)
+            .field("backoff", &self.backoff)
+            .field("fallback", &self.fallback)
+            .finish()
+    }
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> Future for LockEventuallyFut<T> {
+    type Item = MutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut fut) = self.fallback {
+            return fut.poll();
+        }
+        loop {
+            if let Some(ref mut delay) = self.delay {
+                match delay.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => (),
+                    Err(_) => {
+                        // Without a timer, backing off would mean spinning.
+                        // Wait in line instead.
+                        let mut fut = self.mutex.lock();
+                        let r = fut.poll();
+                        self.fallback = Some(fut);
+                        return r;
+                    }
+                }
+            }
+            match self.mutex.try_lock_unperturbed() {
+                Ok(guard) => return Ok(Async::Ready(guard)),
+                Err(TryLockError::WouldBlock) => (),
+                Err(TryLockError::Poisoned) =>
+                    return Err(AcquireError::Poisoned),
+                Err(TryLockError::Closed) => return Err(AcquireError::Closed),
+                Err(TryLockError::Panicked) =>
+                    return Err(AcquireError::Panicked)
+            }
+            self.delay = Some(Delay::new(Instant::now() + self.backoff));
+            let max = Duration::from_millis(Self::MAX_BACKOFF_MS);
+            self.backoff = cmp::min(self.backoff * 2, max);
+        }
+    }
+}
+
+/// A blocking view of a [`Mutex`], shaped like `std::sync::Mutex`, for
+/// synchronous code that must share a lock with asynchronous code.  Both kinds
+/// of code contend for the same lock, and see the same data.
+///
+/// Its methods block the calling thread, so they must not be used from within
+/// an asynchronous task.
+///
+/// # Examples
+/// ```
+/// # extern crate futures;
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use futures::Future;
+/// # use std::thread;
+/// # fn main() {
+/// let mtx = Mutex::<u32>::new(0);
+/// let blocking = BlockingAdapter::new(mtx.clone());
+/// let sync_code = thread::spawn(move || {
+///     *blocking.lock().unwrap() += 1;
+/// });
+/// mtx.lock().map(|mut guard| *guard += 1).wait().unwrap();
+/// sync_code.join().unwrap();
+/// assert_eq!(mtx.try_unwrap().unwrap(), 2);
+/// # }
+/// ```
+///
+/// [`Mutex`]: struct.Mutex.html
+#[derive(Debug)]
+pub struct BlockingAdapter<T: ?Sized> {
+    mutex: Mutex<T>,
+}
+
+impl<T: ?Sized> Clone for BlockingAdapter<T> {
+    fn clone(&self) -> Self {
+        BlockingAdapter{mutex: self.mutex.clone()}
+    }
+}
+
+impl<T: ?Sized> BlockingAdapter<T> {
+    /// Wraps `mutex`, which may still be used asynchronously through its
+    /// clones.
+    pub fn new(mutex: Mutex<T>) -> Self {
+        BlockingAdapter{mutex}
+    }
+
+    /// Returns the underlying `Mutex`.
+    pub fn mutex(&self) -> &Mutex<T> {
+        &self.mutex
+    }
+
+    /// Blocks the current thread until the `Mutex` is acquired, like
+    /// `std::sync::Mutex::lock`.  Fails if the `Mutex` is poisoned; see
+    /// [`Mutex::lock_checked`](struct.Mutex.html#method.lock_checked).
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within a task, like
+    /// [`Mutex::blocking_lock`](struct.Mutex.html#method.blocking_lock).  Also
+    /// panics if the acquisition fails with an
+    /// [`AcquireError`](enum.AcquireError.html).
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock(&self) -> sync::LockResult<MutexGuard<T>> {
+        assert!(!task::is_in_task(),
+                "BlockingAdapter::lock called from within a task");
+        match self.mutex.lock_checked().wait() {
+            Ok(result) => result,
+            Err(e) => panic!("BlockingAdapter::lock: {}", e)
+        }
+    }
+
+    /// Attempts to acquire the `Mutex` without blocking, like
+    /// `std::sync::Mutex::try_lock`.  Fails with `TryLockError::WouldBlock` if
+    /// [`Mutex::try_lock`](struct.Mutex.html#method.try_lock) would fail, or
+    /// with `TryLockError::Poisoned` if the `Mutex` is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Mutex` is closed, or if its internal state is poisoned,
+    /// since waiting would never help.  [`lock`](#method.lock) panics in the
+    /// same cases.
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn try_lock(&self) -> sync::TryLockResult<MutexGuard<T>> {
+        let guard = match self.mutex.try_lock_unchecked() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) =>
+                return Err(sync::TryLockError::WouldBlock),
+            Err(e) => panic!("BlockingAdapter::try_lock: {}", e)
+        };
+        if self.mutex.is_poisoned() {
+            Err(sync::PoisonError::new(guard).into())
+        } else {
+            Ok(guard)
+        }
+    }
+}
+
+impl<T: ?Sized> From<Mutex<T>> for BlockingAdapter<T> {
+    fn from(mutex: Mutex<T>) -> Self {
+        BlockingAdapter::new(mutex)
+    }
+}
+
+/// A `Future` representing a pending `Mutex` acquisition.
+pub struct MutexFut<T: ?Sized> {
+    state: FutState,
+    mutex: Mutex<T>,
+    /// Identifies this future's entry in the waiter queue, once it's queued
+    ticket: u64,
+    /// See [`Mutex::lock_with_priority`]
+    priority: u8,
+    /// Set when a barging release woke this future without granting it the
+    /// `Mutex`, so it may rejoin the queue at the front
+    woken: bool,
+    /// Set when the acquisition's time limit expired, so that leaving the
+    /// queue is reported as `LockEvent::TimedOut`
+    timed_out: bool,
+    /// Set by `Mutex::lock_checked`, which returns the guard of a poisoned
+    /// `Mutex` instead of failing
+    checked: bool,
+    /// Where this acquisition was requested
+    #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+    site: &'static Location<'static>,
+    /// If this acquisition was requested by the task that already held the
+    /// `Mutex`, the `Mutex`'s release count at the time
+    #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+    suspect: Option<u64>,
+    /// When this acquisition started waiting
+    #[cfg(feature = "metrics-histogram")]
+    since: Option<Instant>,
+}
+
+impl<T: ?Sized> MutexFut<T> {
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               allow(clippy::incompatible_msrv))]
+    fn new(state: FutState, mutex: Mutex<T>) -> Self {
+        #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+        let suspect = if task::is_in_task() {
+            let mtx_data = mutex.lock_state_infallible();
+            match mtx_data.holder {
+                Some(ref holder) if holder.is_current() =>
+                    Some(mtx_data.releases),
+                _ => None
+            }
+        } else {
+            None
+        };
+        MutexFut {
+            state,
+            mutex,
+            ticket: 0,
+            priority: 0,
+            woken: false,
+            timed_out: false,
+            checked: false,
+            #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+            site: Location::caller(),
+            #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+            suspect,
+            #[cfg(feature = "metrics-histogram")]
+            since: None,
+        }
+    }
+
+    /// Polls the `Future` once, without needing an executor.  That's useful
+    /// for unit tests and for opportunistic fast paths.
+    ///
+    /// If the result is `NotReady`, then this `Future` has joined the
+    /// `Mutex`'s queue.  But nobody will be notified when it's granted the
+    /// `Mutex`, unless it's subsequently polled from within a task, for
+    /// example by handing it to an executor.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut fut = mtx.lock();
+    /// assert!(fut.poll_once().unwrap().is_not_ready());
+    /// drop(guard);
+    /// assert_eq!(*fut.wait().unwrap(), 0);
+    /// # }
+    /// ```
+    pub fn poll_once(&mut self) -> Poll<MutexGuard<T>, AcquireError> {
+        poll_detached(self)
+    }
+
+    /// Acquires the `Mutex` if that's possible without waiting.  Otherwise,
+    /// gives up and returns `None`.  Unlike [`Mutex::try_lock`], this
+    /// reports a poisoned `Mutex` as an error.
+    ///
+    /// [`Mutex::try_lock`]: struct.Mutex.html#method.try_lock
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.lock().now_or_never().unwrap().unwrap();
+    /// assert!(mtx.lock().now_or_never().unwrap().is_none());
+    /// # }
+    /// ```
+    pub fn now_or_never(mut self)
+        -> Result<Option<MutexGuard<T>>, AcquireError>
+    {
+        match self.poll_once()? {
+            Async::Ready(guard) => Ok(Some(guard)),
+            Async::NotReady => Ok(None)
+        }
+    }
+
+    /// Returns `true` if this `Future` has been granted the `Mutex`, even if
+    /// it hasn't yet been polled to completion.  A granted `Future` that gets
+    /// dropped will release the `Mutex`, so custom cancellation logic can use
+    /// this to decide whether to poll it once more instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut fut = mtx.lock();
+    /// assert!(fut.poll_once().unwrap().is_not_ready());
+    /// assert!(!fut.is_acquired());
+    /// drop(guard);
+    /// assert!(fut.is_acquired());
+    /// # }
+    /// ```
+    pub fn is_acquired(&self) -> bool {
+        match self.state {
+            FutState::New => false,
+            FutState::Pending(_) => {
+                self.mutex.lock_state_infallible().granted == Some(self.ticket)
+            },
+            FutState::Granted | FutState::Acquired => true
+        }
+    }
+
+    /// Returns the number of waiters queued ahead of this `Future`, or
+    /// `None` if it isn't queued.  It's only queued after it's been polled
+    /// once and before it's been granted the `Mutex`.
+    pub fn queue_position(&self) -> Option<usize> {
+        match self.state {
+            FutState::Pending(_) => {
+                let mtx_data = self.mutex.lock_state_infallible();
+                // Don't count waiters that were dropped while queued
+                let live = || mtx_data.waiters.iter()
+                    .filter(|w| !w.tx.is_canceled());
+                // Waiters with higher priorities go first, whenever they came
+                let ahead = live().filter(|w| w.priority > self.priority)
+                    .count();
+                let peers = live().filter(|w| w.priority == self.priority)
+                    .map(|w| w.ticket);
+                self.mutex.inner.config.fairness
+                    .queue_position(peers, self.ticket)
+                    .map(|n| n + ahead)
+            },
+            _ => None
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for MutexFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MutexFut")
+            .field("state", &self.state.name())
+            .field("queue_position", &self.queue_position())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Drop for MutexFut<T> {
+    fn drop(&mut self) {
+        match self.state {
+            FutState::New => {
+                // Mutex hasn't yet been modified; nothing to do
+            },
+            FutState::Pending(ref mut rx) => {
+                rx.close();
+                match rx.try_recv() {
+                    Ok(Some(())) => {
+                        let mut mtx_data = self.mutex.lock_state_infallible();
+                        if mtx_data.granted == Some(self.ticket) {
+                            // This future received ownership of the mutex, but
+                            // got dropped before it was ever polled.  Release
+                            // the mutex.
+                            mtx_data.granted = None;
+                            drop(mtx_data);
+                            self.mutex.unlock()
+                        } else if !mtx_data.owned && !mtx_data.wake_next() {
+                            // A barging release woke this future, and nobody
+                            // is left to pass the wakeup on to.
+                            mtx_data.acknowledge();
+                        }
+                    },
+                    Ok(None) | Err(oneshot::Canceled) => {
+                        // Dropping the Future before it acquires the Mutex is
+                        // equivalent to cancelling it.  Leave the queue, so we
+                        // don't count against max_waiters.
+                        let mut mtx_data = self.mutex.lock_state_infallible();
+                        let ticket = self.ticket;
+                        mtx_data.waiters.retain(|w| w.ticket != ticket);
+                        mtx_data.emit(if self.timed_out {
+                            LockEvent::TimedOut
+                        } else {
+                            LockEvent::Cancelled
+                        });
+                    }
+                }
+            },
+            FutState::Granted => unreachable!("MutexFut is never Granted"),
+            FutState::Acquired => {
+                // The MutexGuard will take care of releasing the Mutex
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+impl<T: ?Sized> MutexFut<T> {
+    /// Panics if this acquisition was requested by the task that holds the
+    /// `Mutex`, and that task has since been polled again without releasing
+    /// it.
+    fn check_deadlock(&mut self) {
+        if let Some(releases) = self.suspect {
+            let mtx_data = self.mutex.lock_state_infallible();
+            if mtx_data.releases != releases {
+                // The holder made progress, so we weren't waiting on ourself
+                self.suspect = None;
+                return;
+            }
+            let deadlock = mtx_data.holder.as_ref()
+                .and_then(|holder| holder.check(self.mutex.name(), self.site));
+            if let Some(msg) = deadlock {
+                // Don't poison the inner Mutex
+                drop(mtx_data);
+                panic!("{}", msg);
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Future for MutexFut<T> {
+    type Item = MutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut retry = false;
+        let (result, new_state) = match self.state {
+            FutState::New => {
+                self.mutex.spin();
+                let mut mtx_data = self.mutex.lock_state()?;
+                if mtx_data.closed {
+                    return Err(AcquireError::Closed);
+                }
+                // A woken waiter was already admitted, so it skips the
+                // admission checks.  It must still take the Mutex, so that it
+                // can pass the wakeup on when it releases it.
+                let woken = mem::replace(&mut self.woken, false);
+                if !self.checked && !woken && self.mutex.is_poisoned() {
+                    return Err(AcquireError::Panicked);
+                }
+                if mtx_data.owned {
+                    let config = &self.mutex.inner.config;
+                    if let (Some(max), false) = (config.max_waiters, woken) {
+                        if mtx_data.waiters.len() >= max {
+                            // Make room by discarding dropped waiters
+                            mtx_data.waiters.retain(|w| !w.tx.is_canceled());
+                            if mtx_data.waiters.len() >= max {
+                                return Err(AcquireError::QueueFull);
+                            }
+                        }
+                    }
+                    let (tx, mut rx) = oneshot::channel::<()>();
+                    if woken {
+                        // Somebody barged in ahead of us.  Keep our place and
+                        // our ticket.
+                        mtx_data.waiters.push_front(Waiter {
+                            ticket: self.ticket,
+                            priority: self.priority,
+                            tx
+                        });
+                    } else {
+                        self.ticket = mtx_data.next_ticket;
+                        mtx_data.next_ticket =
+                            mtx_data.next_ticket.wrapping_add(1);
+                        mtx_data.waiters.push_back(Waiter {
+                            ticket: self.ticket,
+                            priority: self.priority,
+                            tx
+                        });
+                        mtx_data.emit(LockEvent::Queued);
+                        #[cfg(feature = "stats")]
+                        {
+                            if config.stats {
+                                let depth = mtx_data.waiters.len();
+                                mtx_data.stats.record_queue_depth(depth);
+                            }
+                        }
+                    }
+                    #[cfg(feature = "metrics-histogram")]
+                    {
+                        if self.since.is_none() {
+                            self.since = Some(Instant::now());
+                        }
+                    }
+                    // Even though we know it isn't ready, we need to poll the
+                    // receiver in order to register our task for notification.
+                    assert!(rx.poll().unwrap().is_not_ready());
+                    #[cfg(all(feature = "deadlock-detection",
+                              debug_assertions))]
+                    {
+                        if self.suspect.is_some() && !woken {
+                            // Come back to see whether the holder made any
+                            // progress in the meantime
+                            task::current().notify();
+                        }
+                    }
+                    (Ok(Async::NotReady), FutState::Pending(rx))
+                } else if !woken && mtx_data.streak
+                    .exhausted(self.mutex.inner.config.yield_budget)
+                {
+                    (Ok(Async::NotReady), FutState::New)
+                } else {
+                    mtx_data.owned = true;
+                    self.mutex.inner.locked.store(true, Ordering::Relaxed);
+                    mtx_data.emit(LockEvent::Acquired);
+                    // After a barging release, whoever takes the Mutex first
+                    // is its next owner.
+                    mtx_data.acknowledge();
+                    #[cfg(all(feature = "deadlock-detection",
+                              debug_assertions))]
+                    {
+                        mtx_data.holder = Some(Holder::current(self.site));
+                    }
+                    #[cfg(feature = "metrics-histogram")]
+                    {
+                        if self.mutex.inner.config.stats {
+                            let waited = Duration::from_secs(0);
+                            mtx_data.stats.record_wait_time(waited);
+                        }
+                    }
+                    let guard = MutexGuard{mutex: self.mutex.clone()};
+                    (Ok(Async::Ready(guard)), FutState::Acquired)
+                }
+            },
+            FutState::Pending(ref mut rx) => {
+                match rx.poll() {
+                    Ok(Async::NotReady) => {
+                        #[cfg(all(feature = "deadlock-detection",
+                                  debug_assertions))]
+                        self.check_deadlock();
+                        return Ok(Async::NotReady);
+                    },
+                    // The Mutex only drops a waiter's sender when it's
+                    // closed, since the Fut retains a clone of the Mutex
+                    Err(_) => return Err(AcquireError::Closed),
+                    Ok(Async::Ready(_)) if self.mutex.lock_state_infallible()
+                        .granted != Some(self.ticket) =>
+                    {
+                        // A barging release woke us, without granting us the
+                        // Mutex.  Compete for it.
+                        self.woken = true;
+                        retry = true;
+                        (Ok(Async::NotReady), FutState::New)
+                    },
+                    Ok(Async::Ready(_)) => {
+                        let mut mtx_data = self.mutex.lock_state_infallible();
+                        mtx_data.granted = None;
+                        if mtx_data.ack_ticket == Some(self.ticket) {
+                            mtx_data.acknowledge();
+                        }
+                        #[cfg(all(feature = "deadlock-detection",
+                                  debug_assertions))]
+                        {
+                            mtx_data.holder = Some(Holder::current(self.site));
+                        }
+                        #[cfg(feature = "metrics-histogram")]
+                        {
+                            if self.mutex.inner.config.stats {
+                                let waited = self.since.map(|t| t.elapsed())
+                                    .unwrap_or_default();
+                                mtx_data.stats.record_wait_time(waited);
+                            }
+                        }
+                        drop(mtx_data);
+                        let state = FutState::Acquired;
+                        let result = Ok(Async::Ready(
+                                MutexGuard{mutex: self.mutex.clone()}));
+                        (result, state)
+                    }  //LCOV_EXCL_LINE    kcov false negative
+                }
+            },
+            FutState::Granted => unreachable!("MutexFut is never Granted"),
+            FutState::Acquired => panic!("Double-poll of ready Future")
+        };
+        self.state = new_state;
+        if retry {
+            return self.poll();
+        }
+        match result {
+            Ok(Async::Ready(guard))
+                if !self.checked && guard.mutex.is_poisoned() =>
+            {
+                // Poisoned while we waited.  Pass the Mutex on.
+                drop(guard);
+                Err(AcquireError::Panicked)
+            },
+            result => result
+        }
+    }
+}
+
+impl MutexData {
+    /// The number of waiters, not counting those that were dropped while
+    /// queued.
+    fn live_waiters(&self) -> usize {
+        self.waiters.iter().filter(|w| !w.tx.is_canceled()).count()
+    }
+
+    /// Report `event` to every subscriber, forgetting those that have gone.
+    fn emit(&mut self, event: LockEvent) {
+        #[cfg(feature = "tracing")]
+        trace!(target: "futures_locks::mutex",
+               name = self.name.as_ref().map(|n| &n[..]), ?event);
+        if !self.subscribers.is_empty() {
+            self.subscribers.retain(|tx| tx.unbounded_send(event).is_ok());
+        }
+    }
+
+    /// Wake the next live waiter, without granting it the `Mutex`.  Used by
+    /// `Fairness::Barging`.  Returns false if nobody was waiting.
+    fn wake_next(&mut self) -> bool {
+        while let Some(waiter) = pop_waiter(&mut self.waiters, Fairness::Fifo) {
+            if waiter.tx.send(()).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Notify the task in `MutexGuard::release_async`, if any, that the
+    /// `Mutex` has a new owner.
+    fn acknowledge(&mut self) {
+        self.ack_ticket = None;
+        if let Some(ack) = self.ack.take() {
+            // The releaser may have stopped waiting
+            let _ = ack.send(());
+        }
+    }
+
+    /// Notify the current task the next time the `Mutex` is released.
+    fn watch(&mut self) {
+        if !self.watchers.iter().any(|t| t.will_notify_current()) {
+            self.watchers.push(task::current());
+        }
+    }
+}
+
+/// A task waiting for ownership of a `Mutex`
+#[derive(Debug)]
+struct Waiter {
+    ticket: u64,
+    /// Waiters with higher priorities are served first
+    priority: u8,
+    tx: oneshot::Sender<()>,
+}
+
+/// Remove the next waiter to be served from `waiters`: the one with the
+/// highest priority, with ties broken by `fairness`.
+fn pop_waiter(waiters: &mut VecDeque<Waiter>, fairness: Fairness)
+    -> Option<Waiter>
+{
+    let top = waiters.iter().map(|w| w.priority).max()?;
+    if top == 0 {
+        // The common case: nobody asked for a priority
+        return fairness.pop(waiters);
+    }
+    let mut level = waiters.iter()
+        .enumerate()
+        .filter(|&(_, w)| w.priority == top)
+        .map(|(i, _)| i);
+    let i = match fairness {
+        Fairness::Fifo | Fairness::Barging => level.next(),
+        Fairness::Lifo => level.next_back()
+    }?;
+    waiters.remove(i)
+}
+
+/// A `Future` representing a pending acquisition of a `Mutex` whose data
+/// satisfies a predicate.  Returned by
+/// [`Mutex::wait_until`](struct.Mutex.html#method.wait_until).
+pub struct WaitUntilFut<T: ?Sized, F> {
+    mutex: Mutex<T>,
+    fut: MutexFut<T>,
+    predicate: F,
+}
+
+impl<T: ?Sized, F: FnMut(&T) -> bool> Future for WaitUntilFut<T, F> {
+    type Item = MutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let guard = match self.fut.poll()? {
+                Async::Ready(guard) => guard,
+                Async::NotReady => return Ok(Async::NotReady)
+            };
+            if (self.predicate)(&guard) {
+                return Ok(Async::Ready(guard));
+            }
+            // Release it, and try again after somebody else releases it, too.
+            let releases = self.mutex.lock_state_infallible().releases;
+            drop(guard);
+            self.fut = self.mutex.lock();
+            let mut mtx_data = self.mutex.lock_state()?;
+            if mtx_data.releases == releases.wrapping_add(1) &&
+                !mtx_data.closed
+            {
+                mtx_data.watch();
+                return Ok(Async::NotReady);
+            }
+            // Somebody else released it after we did, or closed it.  Retry
+            // immediately.
+        }
+    }
+}
+
+type IsSomeFn<T> = fn(&Option<T>) -> bool;
+
+/// A `Future` representing a pending acquisition of a `Mutex<Option<T>>`
+/// that's populated.  Returned by
+/// [`Mutex::lock_some`](struct.Mutex.html#method.lock_some).
+pub struct LockSomeFut<T> {
+    fut: WaitUntilFut<Option<T>, IsSomeFn<T>>,
+}
+
+impl<T> Future for LockSomeFut<T> {
+    type Item = MappedMutexGuard<Option<T>, T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut guard = match self.fut.poll()? {
+            Async::Ready(guard) => guard,
+            Async::NotReady => return Ok(Async::NotReady)
+        };
+        let data = guard.as_mut().unwrap() as *mut T;
+        let mutex = MutexGuard::into_mutex(guard);
+        Ok(Async::Ready(MappedMutexGuard{mutex, data}))
+    }
+}
+
+/// A `Stream` that yields once for every time a `Mutex` is released.  Returned
+/// by [`Mutex::changes`](struct.Mutex.html#method.changes).
+pub struct MutexChanges<T: ?Sized> {
+    /// Weak, so the `Stream` can end once every `Mutex` handle is dropped
+    mutex: MutexWeak<T>,
+    /// The number of releases already reported
+    seen: u64,
+}
+
+impl<T: ?Sized> fmt::Debug for MutexChanges<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MutexChanges")
+            .field("mutex", &self.mutex.upgrade())
+            .field("seen", &self.seen)
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Stream for MutexChanges<T> {
+    type Item = ();
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Option<()>, AcquireError> {
+        let mutex = match self.mutex.upgrade() {
+            Some(mutex) => mutex,
+            None => return Ok(Async::Ready(None))
+        };
+        let mut mtx_data = mutex.lock_state()?;
+        if mtx_data.releases == self.seen {
+            mtx_data.watch();
+            Ok(Async::NotReady)
+        } else {
+            self.seen = mtx_data.releases;
+            Ok(Async::Ready(Some(())))
+        }
+    }
+}
+
+/// A `Stream` that acquires a `Mutex` anew for every item.  Returned by
+/// [`Mutex::lock_stream`](struct.Mutex.html#method.lock_stream).
+pub struct MutexLocks<T: ?Sized> {
+    mutex: Mutex<T>,
+    /// The acquisition for the next item, once it's been polled
+    fut: Option<MutexFut<T>>,
+}
+
+impl<T: ?Sized> Stream for MutexLocks<T> {
+    type Item = MutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Option<MutexGuard<T>>, AcquireError> {
+        let mutex = &self.mutex;
+        let r = self.fut.get_or_insert_with(|| mutex.lock()).poll();
+        match r {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(guard)) => {
+                self.fut = None;
+                Ok(Async::Ready(Some(guard)))
+            },
+            Err(AcquireError::Closed) => {
+                self.fut = None;
+                Ok(Async::Ready(None))
+            },
+            Err(e) => {
+                self.fut = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MutexData {
+    owned: bool,
+    /// Set by `Mutex::close`
+    closed: bool,
+    /// The ticket of the waiter that has been handed the `Mutex`, but hasn't
+    /// yet taken it
+    granted: Option<u64>,
+    // FIFO queue of waiting tasks.
+    waiters: VecDeque<Waiter>,
+    /// The ticket for the next waiter to join the queue
+    next_ticket: u64,
+    /// How many times the `Mutex` has been released
+    releases: u64,
+    /// Tasks to notify the next time the `Mutex` is released
+    watchers: Vec<Task>,
+    /// A task in `Mutex::into_inner`, to notify when the second-to-last handle
+    /// is dropped
+    unwrapper: Option<Task>,
+    /// Receivers of this `Mutex`'s `LockEvent`s
+    subscribers: Vec<mpsc::UnboundedSender<LockEvent>>,
+    /// Notifies a task in `MutexGuard::release_async` once the `Mutex`'s next
+    /// owner has taken it
+    ack: Option<oneshot::Sender<()>>,
+    /// The ticket of the waiter that must take the `Mutex` to fire `ack`
+    ack_ticket: Option<u64>,
+    /// Recent acquisitions that didn't have to wait
+    streak: Streak,
+    /// The current owner, for detecting self-deadlocks
+    #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+    holder: Option<Holder>,
+    #[cfg(feature = "stats")]
+    stats: LockStats,
+    /// The `Mutex`'s name, for tracing events
+    #[cfg(feature = "tracing")]
+    name: Option<Cow<'static, str>>,
+}
+
+#[derive(Debug)]
+struct Inner<T: ?Sized> {
+    mutex: sync::Mutex<MutexData>,
+    config: LockConfig,
+    /// A `PoisonPolicy`, stored as a `usize`
+    poison_policy: AtomicUsize,
+    /// Set if a guard was dropped during a panic, with `poison_on_panic`
+    poisoned: AtomicBool,
+    /// Mirrors `MutexData::owned`, so spinning waiters needn't lock `mutex`
+    locked: AtomicBool,
+    /// How many `Mutex` handles exist.  Unlike the `Arc`'s count, this is
+    /// decremented before a handle has finished dropping.
+    handles: AtomicUsize,
+    validator: Validator,
+    data: UnsafeCell<T>,
+}
+
+/// One `Mutex` handle's reference to the shared state, counted in
+/// `Inner::handles` for as long as it lives.
+struct Handle<T: ?Sized>(Option<sync::Arc<Inner<T>>>);
+
+impl<T: ?Sized> Handle<T> {
+    fn new(inner: sync::Arc<Inner<T>>) -> Self {
+        inner.handles.fetch_add(1, Ordering::Relaxed);
+        Handle(Some(inner))
+    }
+
+    /// Moves out the shared state without changing the handle count.
+    fn into_arc(mut self) -> sync::Arc<Inner<T>> {
+        self.0.take().unwrap()
+    }
+}
+
+impl<T: ?Sized> Deref for Handle<T> {
+    type Target = sync::Arc<Inner<T>>;
+
+    fn deref(&self) -> &sync::Arc<Inner<T>> {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl<T: ?Sized> DerefMut for Handle<T> {
+    fn deref_mut(&mut self) -> &mut sync::Arc<Inner<T>> {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl<T: ?Sized> Drop for Handle<T> {
+    fn drop(&mut self) {
+        let inner = match self.0 {
+            Some(ref inner) => inner,
+            None => return
+        };
+        match inner.handles.fetch_sub(1, Ordering::AcqRel) {
+            1 => {
+                // Wake any MutexChanges, so they can see that they've ended.
+                // Never panic here, even if the PoisonPolicy says to.
+                let watchers = mem::replace(
+                    &mut inner.mutex.lock()
+                        .unwrap_or_else(sync::PoisonError::into_inner)
+                        .watchers,
+                    Vec::new());
+                for task in watchers {
+                    task.notify();
+                }
+            },
+            2 => {
+                // Only one handle remains, which may be waiting in into_inner.
+                let unwrapper = inner.mutex.lock()
+                    .unwrap_or_else(sync::PoisonError::into_inner)
+                    .unwrapper.take();
+                if let Some(task) = unwrapper {
+                    task.notify();
+                }
+            },
+            _ => ()
+        }
+    }
+}
+
+/// Creates a [`Mutex`] with non-default settings.  Returned by
+/// [`Mutex::builder`].
+///
+/// [`Mutex`]: struct.Mutex.html
+/// [`Mutex::builder`]: struct.Mutex.html#method.builder
+pub struct MutexBuilder<T> {
+    config: LockConfig,
+    poison_policy: PoisonPolicy,
+    _data: PhantomData<fn(T)>,
+}
+
+impl<T> MutexBuilder<T> {
+    /// Names the `Mutex`, to identify it in diagnostics.
+    pub fn name<N: Into<Cow<'static, str>>>(mut self, name: N) -> Self {
+        self.config.name = Some(name.into());
+        self
+    }
+
+    /// Chooses which waiter gets the `Mutex` whenever it's released.  The
+    /// default is [`Fairness::Fifo`](enum.Fairness.html#variant.Fifo).
+    pub fn fairness(mut self, fairness: Fairness) -> Self {
+        self.config.fairness = fairness;
+        self
+    }
+
+    /// Limits how many tasks may wait for the `Mutex` at once.  Beyond that,
+    /// acquisitions will fail with
+    /// [`AcquireError::QueueFull`](enum.AcquireError.html#variant.QueueFull)
+    /// instead of waiting.  By default, there's no limit.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::builder().max_waiters(0).build(0u32);
+    /// let guard = mtx.try_lock().unwrap();
+    /// assert_eq!(mtx.lock().wait().err(), Some(AcquireError::QueueFull));
+    /// # }
+    /// ```
+    pub fn max_waiters(mut self, max: usize) -> Self {
+        self.config.max_waiters = Some(max);
+        self
+    }
+
+    /// Poisons the `Mutex` if a guard is dropped while its thread is
+    /// panicking, like `std::sync::Mutex`.  Until the poison is cleared with
+    /// [`Mutex::clear_poison`], ordinary acquisitions like `lock` and
+    /// `try_lock` fail with `Panicked`.  Only [`Mutex::lock_checked`] and
+    /// [`BlockingAdapter::lock`] can still acquire it, and they report the
+    /// poison with a recoverable `PoisonError`.  Disabled by default.
+    ///
+    /// This is unrelated to [`poison_policy`](#method.poison_policy), which
+    /// covers the `Mutex`'s internal bookkeeping.
+    ///
+    /// [`Mutex::clear_poison`]: struct.Mutex.html#method.clear_poison
+    /// [`Mutex::lock_checked`]: struct.Mutex.html#method.lock_checked
+    /// [`BlockingAdapter::lock`]: struct.BlockingAdapter.html#method.lock
+    pub fn poison_on_panic(mut self, enabled: bool) -> Self {
+        self.config.poison_on_panic = enabled;
+        self
+    }
+
+    /// Limits how many times in a row a single task may acquire the `Mutex`
+    /// without waiting.  After `budget` such acquisitions, the next one will
+    /// yield to the executor first.  That keeps a task that locks an
+    /// uncontended `Mutex` in a loop from starving other tasks on a
+    /// single-threaded runtime.  By default, there's no limit.
+    ///
+    /// [`MutexFut::poll_once`](struct.MutexFut.html#method.poll_once) will
+    /// count a yield as not ready.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `budget` is 0.
+    pub fn yield_budget(mut self, budget: u32) -> Self {
+        assert!(budget > 0, "a yield budget must be at least 1");
+        self.config.yield_budget = Some(budget);
+        self
+    }
+
+    /// Spins up to `limit` times, waiting for the `Mutex` to be released,
+    /// before an acquisition joins the wait queue.  Each spin only checks an
+    /// atomic flag, which is much cheaper than parking and waking a task when
+    /// critical sections are very short.  But spinning blocks the executor's
+    /// thread, so it's only worthwhile when the `Mutex` is held by tasks on
+    /// other threads.  By default, acquisitions don't spin.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::builder().spin_limit(100).build(0u32);
+    /// *mtx.lock().wait().unwrap() += 1;
+    /// # }
+    /// ```
+    pub fn spin_limit(mut self, limit: u32) -> Self {
+        self.config.spin_limit = limit;
+        self
+    }
+
+    /// Sets the initial policy for handling a poisoned `Mutex`.  See
+    /// [`Mutex::set_poison_policy`](struct.Mutex.html#method.set_poison_policy).
+    pub fn poison_policy(mut self, policy: PoisonPolicy) -> Self {
+        self.poison_policy = policy;
+        self
+    }
+
+    /// Enables or disables collecting statistics for this `Mutex`, including
+    /// wait times with the `metrics-histogram` feature.  Statistics are
+    /// enabled by default.
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn stats(mut self, enabled: bool) -> Self {
+        self.config.stats = enabled;
+        self
+    }
+
+    /// Creates the `Mutex`, in the unlocked state.
+    pub fn build(self, t: T) -> Mutex<T> {
+        let mutex_data = MutexData {
+            owned: false,
+            closed: false,
+            granted: None,
+            waiters: VecDeque::new(),
+            next_ticket: 0,
+            releases: 0,
+            watchers: Vec::new(),
+            unwrapper: None,
+            subscribers: Vec::new(),
+            ack: None,
+            ack_ticket: None,
+            streak: Streak::default(),
+            #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+            holder: None,
+            #[cfg(feature = "stats")]
+            stats: LockStats::default(),
+            #[cfg(feature = "tracing")]
+            name: self.config.name.clone(),
+        };
+        let inner = Inner {
+            mutex: sync::Mutex::new(mutex_data),
+            config: self.config,
+            poison_policy: AtomicUsize::new(self.poison_policy.to_usize()),
+            poisoned: AtomicBool::new(false),
+            locked: AtomicBool::new(false),
+            handles: AtomicUsize::new(0),
+            validator: Validator::default(),
+            data: UnsafeCell::new(t)
+        };  //LCOV_EXCL_LINE    kcov false negative
+        Mutex { inner: Handle::new(sync::Arc::new(inner))}
+    }
+}
+
+/// `MutexWeak` is a non-owning reference to a [`Mutex`].  `MutexWeak` is to 
+/// [`Mutex`] as [`std::sync::Weak`] is to [`std::sync::Arc`].
+/// 
+/// # Examples
+/// ```
+/// # use futures_locks::{Mutex,MutexGuard};
+/// # fn main() {
+/// let mutex = Mutex::<u32>::new(0);
+/// let mutex_weak = Mutex::downgrade(&mutex);
+/// let mutex_new = mutex_weak.upgrade().unwrap();
+/// # }
+/// ```
+///
+/// [`Mutex`]: struct.Mutex.html
+/// [`std::sync::Weak`]: https://doc.rust-lang.org/std/sync/struct.Weak.html
+/// [`std::sync::Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+#[derive(Debug)]
+pub struct MutexWeak<T: ?Sized> {
+    inner: sync::Weak<Inner<T>>,
+}
+
+impl<T: ?Sized> MutexWeak<T> {
+    /// Tries to upgrade the `MutexWeak` to `Mutex`. If the `Mutex` was dropped 
+    /// then the function return `None`.
+    pub fn upgrade(&self) -> Option<Mutex<T>> {
+        if let Some(inner) = self.inner.upgrade() {
+            return Some(Mutex{inner: Handle::new(inner)})
+        }
+        None
+    }
+}
+
+impl<T: ?Sized> Clone for MutexWeak<T> {
+    fn clone(&self) -> MutexWeak<T> {
+        MutexWeak {inner: self.inner.clone()}
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for MutexWeak<T> {}
+unsafe impl<T: ?Sized + Send> Sync for MutexWeak<T> {}
+
+/// A Futures-aware Mutex.
+///
+/// `std::sync::Mutex` cannot be used in an asynchronous environment like Tokio,
+/// because a mutex acquisition can block an entire reactor.  This class can be
+/// used instead.  It functions much like `std::sync::Mutex`.  Unlike that
+/// class, it also has a builtin `Arc`, making it accessible from multiple
+/// threads.  It's also safe to `clone`.  Also unlike `std::sync::Mutex`, this
+/// class only detects lock poisoning if it's built with
+/// `MutexBuilder::poison_on_panic`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use futures::executor::{Spawn, spawn};
+/// # use futures::Future;
+/// # fn main() {
+/// let mtx = Mutex::<u32>::new(0);
+/// let fut = mtx.lock().map(|mut guard| { *guard += 5; });
+/// spawn(fut).wait_future();
+/// assert_eq!(mtx.try_unwrap().unwrap(), 5);
+/// # }
+/// ```
+pub struct Mutex<T: ?Sized> {
+    inner: Handle<T>,
+}
+
+impl<T: ?Sized> Clone for Mutex<T> {
+    fn clone(&self) -> Mutex<T> {
+        Mutex { inner: Handle::new(self.inner.clone())}
+    }
+}
+
+/// Creates a `Mutex` of the data's default value.
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T> Mutex<T> {
+    /// Create a new `Mutex` in the unlocked state.
+    pub fn new(t: T) -> Mutex<T> {
+        Mutex::builder().build(t)
+    }
+
+    /// Returns a builder, for creating a `Mutex` with non-default settings.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::builder()
+    ///     .name("connection pool")
+    ///     .fairness(Fairness::Lifo)
+    ///     .max_waiters(100)
+    ///     .build(Vec::<u32>::new());
+    /// assert_eq!(mtx.name(), Some("connection pool"));
+    /// # }
+    /// ```
+    pub fn builder() -> MutexBuilder<T> {
+        MutexBuilder {
+            config: LockConfig::default(),
+            poison_policy: PoisonPolicy::default(),
+            _data: PhantomData
+        }
+    }
+
+    /// Installs an invariant check that runs whenever a guard is released.  If
+    /// it returns `false`, dropping the guard will panic, after releasing the
+    /// `Mutex`.  That pinpoints the critical section that broke the invariant.
+    ///
+    /// The check only runs in builds with debug assertions.  It replaces any
+    /// previous check, and applies to every clone of this `Mutex`.
+    ///
+    /// # Examples
+    /// ```should_panic
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// mtx.debug_validate(|x| x % 2 == 0);
+    /// *mtx.try_lock().unwrap() += 2;     // Ok
+    /// *mtx.try_lock().unwrap() += 1;     // Panics
+    /// # }
+    /// ```
+    pub fn debug_validate<F>(&self, f: F)
+        where F: Fn(&T) -> bool + Send + Sync + 'static,
+              T: 'static
+    {
+        self.inner.validator.set(f);
+    }
+
+    /// Consumes the `Mutex` and returns the wrapped data.  If the `Mutex` still
+    /// has multiple references (not necessarily locked), returns a copy of
+    /// `self` instead, along with a description of who else is using it.
+    pub fn try_unwrap(self) -> Result<T, TryUnwrapError<Mutex<T>>> {
+        // Don't count the handle as dropped, since we may have to return a
+        // copy of self.
+        match sync::Arc::try_unwrap(self.into_arc()) {
+            Ok(inner) => Ok({
+                // `unsafe` is no longer needed as of somewhere around 1.25.0.
+                // https://github.com/rust-lang/rust/issues/35067
+                #[allow(unused_unsafe)]
+                unsafe { inner.data.into_inner() }
+            }),
+            Err(arc) => {
+                let mutex = Mutex {inner: Handle(Some(arc))};
+                let (locked, waiters) = {
+                    let mtx_data = mutex.lock_state_infallible();
+                    (mtx_data.owned, mtx_data.live_waiters())
+                };
+                let handles = sync::Arc::strong_count(&mutex.inner);
+                Err(TryUnwrapError{lock: mutex, handles, locked, waiters})
+            }
+        }
+    }
+
+    /// Consumes the `Mutex` and returns a `Future` of the wrapped data.  The
+    /// `Future` completes once every other handle to the `Mutex`, including
+    /// those held by guards, has been dropped.  Clones that are never dropped
+    /// will make it wait forever, as will any [`MutexWeak`] that gets
+    /// upgraded and kept.  See also [`try_unwrap`](#method.try_unwrap).
+    ///
+    /// [`MutexWeak`]: struct.MutexWeak.html
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use std::thread;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let handles = (0..4).map(|_| {
+    ///     let mtx = mtx.clone();
+    ///     thread::spawn(move || *mtx.lock().wait().unwrap() += 1)
+    /// }).collect::<Vec<_>>();
+    /// assert_eq!(mtx.into_inner().wait().unwrap(), 4);
+    /// # for h in handles { h.join().unwrap(); }
+    /// # }
+    /// ```
+    pub fn into_inner(self) -> impl Future<Item = T, Error = AcquireError> {
+        let mut mutex = Some(self);
+        future::poll_fn(move || {
+            let this = mutex.take()
+                .expect("Mutex::into_inner polled after completion");
+            // Register before checking, so a handle that drops in between
+            // will notify us.
+            this.lock_state_infallible().unwrapper = Some(task::current());
+            match this.try_unwrap() {
+                Ok(data) => Ok(Async::Ready(data)),
+                Err(e) => {
+                    let this = e.lock;
+                    if this.inner.handles.load(Ordering::Acquire) == 1 {
+                        // Every other handle has started dropping, but hasn't
+                        // yet released its reference.  Try again shortly.
+                        task::current().notify();
+                    }
+                    mutex = Some(this);
+                    Ok(Async::NotReady)
+                }
+            }
+        })
+    }
+
+    /// Consumes the `Mutex` and returns the wrapped data, like
+    /// [`try_unwrap`](#method.try_unwrap).  But if other references remain,
+    /// acquires the `Mutex` and returns a clone of the data instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<String>::new("data".to_owned());
+    /// let other = mtx.clone();
+    /// assert_eq!(mtx.try_unwrap_or_clone().wait().unwrap(), "data");
+    /// assert_eq!(other.try_unwrap_or_clone().wait().unwrap(), "data");
+    /// # }
+    /// ```
+    pub fn try_unwrap_or_clone(self)
+        -> impl Future<Item = T, Error = AcquireError>
+        where T: Clone
+    {
+        match self.try_unwrap() {
+            Ok(data) => Either::A(Ok(data).into_future()),
+            Err(e) => Either::B(e.lock.get_cloned())
+        }
+    }
+
+    /// Acquires the `Mutex` for a transaction, and snapshots the protected
+    /// data.  Changes made through the returned guard apply directly.  They
+    /// can be kept with [`TransactionGuard::commit`], or undone with
+    /// [`TransactionGuard::rollback`].  If the guard is simply dropped, `mode`
+    /// decides.  But if it's dropped during a panic, the snapshot is always
+    /// restored, and the `Mutex` isn't poisoned.
+    ///
+    /// [`TransactionGuard::commit`]: struct.TransactionGuard.html#method.commit
+    /// [`TransactionGuard::rollback`]: struct.TransactionGuard.html#method.rollback
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use std::panic;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![1]);
+    /// let mut txn = mtx.lock_transactional(TransactionMode::Rollback)
+    ///     .wait().unwrap();
+    /// txn.push(2);
+    /// drop(txn);
+    /// assert_eq!(*mtx.try_lock().unwrap(), vec![1]);
+    ///
+    /// let mut txn = mtx.lock_transactional(TransactionMode::Rollback)
+    ///     .wait().unwrap();
+    /// txn.push(3);
+    /// TransactionGuard::commit(txn);
+    /// assert_eq!(*mtx.try_lock().unwrap(), vec![1, 3]);
+    ///
+    /// let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+    ///     let mut txn = mtx.lock_transactional(TransactionMode::Commit)
+    ///         .wait().unwrap();
+    ///     txn.push(4);
+    ///     panic!("halfway through");
+    /// }));
+    /// assert!(r.is_err());
+    /// assert_eq!(*mtx.try_lock().unwrap(), vec![1, 3]);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_transactional(&self, mode: TransactionMode)
+        -> impl Future<Item = TransactionGuard<T>, Error = AcquireError>
+        where T: Clone
+    {
+        self.lock().map(move |guard| {
+            let snapshot = Some((*guard).clone());
+            TransactionGuard{guard: Some(guard), snapshot, mode}
+        })
+    }
+
+    /// Acquires the `Mutex`, replaces the protected data with `value`, and
+    /// releases the `Mutex`.  Returns a `Future` of the old value.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(1);
+    /// assert_eq!(mtx.replace(2).wait(), Ok(1));
+    /// assert_eq!(*mtx.try_lock().unwrap(), 2);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn replace(&self, value: T)
+        -> impl Future<Item = T, Error = AcquireError>
+    {
+        self.lock().map(move |mut guard| MutexGuard::replace(&mut guard, value))
+    }
+
+    /// Acquires the `Mutex`, takes the protected data and leaves
+    /// `T::default()` in its place, and releases the `Mutex`.  Returns a
+    /// `Future` of the data.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![1, 2]);
+    /// assert_eq!(mtx.take().wait(), Ok(vec![1, 2]));
+    /// assert!(mtx.try_lock().unwrap().is_empty());
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn take(&self) -> impl Future<Item = T, Error = AcquireError>
+        where T: Default
+    {
+        self.lock().map(|mut guard| MutexGuard::take(&mut guard))
+    }
+
+    /// Acquires the `Mutex`, clones the protected data, and releases the
+    /// `Mutex`.  Returns a `Future` of the copy.  Since no guard escapes,
+    /// the `Mutex` can't accidentally be held across later steps of a chain.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<String>::new("config".to_owned());
+    /// let copy = mtx.get_cloned().wait().unwrap();
+    /// assert!(mtx.try_lock().is_ok());
+    /// assert_eq!(copy, "config");
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn get_cloned(&self) -> impl Future<Item = T, Error = AcquireError>
+        where T: Clone
+    {
+        self.lock().map(|guard| (*guard).clone())
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Create a [`MutexWeak`] reference to this `Mutex`.
+    ///
+    /// [`MutexWeak`]: struct.MutexWeak.html
+    pub fn downgrade(this: &Mutex<T>) -> MutexWeak<T> {
+        MutexWeak {inner: sync::Arc::<Inner<T>>::downgrade(&this.inner)}
+    }
+
+    /// Returns a reference to the underlying data, if there are no other
+    /// clones of the `Mutex`.
+    ///
+    /// Since this call borrows the `Mutex` mutably, no actual locking takes
+    /// place -- the mutable borrow statically guarantees no locks exist.
+    /// However, if the `Mutex` has already been cloned, then `None` will be
+    /// returned instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mut mtx = Mutex::<u32>::new(0);
+    /// *mtx.get_mut().unwrap() += 5;
+    /// assert_eq!(mtx.try_unwrap().unwrap(), 5);
+    /// # }
+    /// ```
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if let Some(inner) = sync::Arc::get_mut(&mut self.inner) {
+            let policy =
+                PoisonPolicy::from_usize(*inner.poison_policy.get_mut());
+            let lock_data = match inner.mutex.get_mut() {
+                Ok(lock_data) => lock_data,
+                Err(e) if policy != PoisonPolicy::Panic => e.into_inner(),
+                Err(e) => panic!("sync::Mutex::get_mut: {:?}", e)
+            };
+            let data = unsafe { inner.data.get().as_mut() }.unwrap();
+            debug_assert!(!lock_data.owned);
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    /// Acquires a `Mutex`, blocking the task in the meantime.  When the
+    /// returned `Future` is ready, this task will have sole access to the
+    /// protected data.
+    ///
+    /// # Panics
+    ///
+    /// With the `deadlock-detection` feature and debug assertions enabled,
+    /// the returned `Future` will panic if it was created by the task that
+    /// already holds this `Mutex`, and that task gets polled again without
+    /// releasing it.  The panic reports where both acquisitions were made.
+    /// A task that drives several futures at once, as with `Future::join`,
+    /// may still trip it if the holding future doesn't release the `Mutex`
+    /// by the task's next poll.
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock(&self) -> MutexFut<T> {
+        MutexFut::new(FutState::New, self.clone())
+    }
+
+    /// Acquires the `Mutex` like [`lock`](#method.lock), but if it has to
+    /// wait, it's served ahead of every waiter with a lower `priority`.
+    /// Among waiters of equal priority, the `Mutex`'s
+    /// [`Fairness`](enum.Fairness.html) applies.  Plain `lock` uses priority
+    /// 0, the lowest.
+    ///
+    /// Priorities only order the queue; they don't preempt the current owner,
+    /// and a steady stream of high-priority waiters can starve the others.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<&str>>::new(Vec::new());
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut batch = mtx.lock();
+    /// let mut urgent = mtx.lock_with_priority(10);
+    /// assert!(batch.poll_once().unwrap().is_not_ready());
+    /// assert!(urgent.poll_once().unwrap().is_not_ready());
+    /// assert_eq!(urgent.queue_position(), Some(0));
+    /// drop(guard);
+    /// urgent.wait().unwrap().push("urgent");
+    /// batch.wait().unwrap().push("batch");
+    /// assert_eq!(mtx.try_unwrap().unwrap(), vec!["urgent", "batch"]);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_with_priority(&self, priority: u8) -> MutexFut<T> {
+        let mut fut = MutexFut::new(FutState::New, self.clone());
+        fut.priority = priority;
+        fut
+    }
+
+    /// Blocks the current thread until the `Mutex` is acquired.  That lets
+    /// synchronous code share a `Mutex` with asynchronous tasks.  See also
+    /// [`BlockingAdapter`](struct.BlockingAdapter.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within a task, since blocking there could stall
+    /// the executor, or even deadlock if the `Mutex`'s owner runs on the same
+    /// thread.  Also panics if the acquisition fails; see
+    /// [`PoisonPolicy`](enum.PoisonPolicy.html).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use std::thread;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let mtx2 = mtx.clone();
+    /// thread::spawn(move || {
+    ///     *mtx2.blocking_lock() += 1;
+    /// }).join().unwrap();
+    /// assert_eq!(*mtx.blocking_lock(), 1);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn blocking_lock(&self) -> MutexGuard<T> {
+        assert!(!task::is_in_task(),
+                "Mutex::blocking_lock called from within a task");
+        match self.lock().wait() {
+            Ok(guard) => guard,
+            Err(e) => panic!("Mutex::blocking_lock: {}", e)
+        }
+    }
+
+    /// Acquires a `Mutex`, like [`lock`](#method.lock), but gives up if it
+    /// can't be acquired within `timeout`.  Then, the returned `Future` fails
+    /// with [`AcquireError::TimedOut`], and stops waiting for the `Mutex`.
+    ///
+    /// Requires a Tokio timer, as provided by Tokio's runtimes.  Without one,
+    /// the `Future` times out the first time that it would have to wait.  See
+    /// [Timers](index.html#timers).
+    ///
+    /// [`AcquireError::TimedOut`]: enum.AcquireError.html#variant.TimedOut
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use std::time::Duration;
+    /// # use tokio::runtime::current_thread;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let _guard = mtx.try_lock().unwrap();
+    /// let mut rt = current_thread::Runtime::new().unwrap();
+    /// let r = rt.block_on(mtx.lock_timeout(Duration::from_millis(10)));
+    /// assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    /// # }
+    /// ```
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_timeout(&self, timeout: Duration) -> LockTimeoutFut<T> {
+        self.lock_until(Instant::now() + timeout)
+    }
+
+    /// Like [`lock_timeout`](#method.lock_timeout), but gives up at an
+    /// absolute `deadline` instead of after a relative timeout.  That's
+    /// convenient for propagating one deadline through several steps of a
+    /// request.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use std::time::{Duration, Instant};
+    /// # use tokio::runtime::current_thread;
+    /// # fn main() {
+    /// let a = Mutex::<u32>::new(0);
+    /// let b = Mutex::<u32>::new(0);
+    /// let _guard = b.try_lock().unwrap();
+    /// let deadline = Instant::now() + Duration::from_millis(10);
+    /// let mut rt = current_thread::Runtime::new().unwrap();
+    /// let both = a.lock_until(deadline).join(b.lock_until(deadline));
+    /// let r = rt.block_on(both);
+    /// assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    /// # }
+    /// ```
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_until(&self, deadline: Instant) -> LockTimeoutFut<T> {
+        LockTimeoutFut{fut: Some(self.lock()), delay: Delay::new(deadline)}
+    }
+
+    /// Acquires the `Mutex` without joining its wait queue.  Instead, the
+    /// returned `Future` retries [`try_lock`](#method.try_lock) with
+    /// exponential backoff, from 1 ms up to 100 ms between attempts.  That
+    /// suits low-priority work, which shouldn't delay the tasks that do queue,
+    /// at the cost of latency.  It may also wait forever, if the `Mutex` is
+    /// never free when it looks.
+    ///
+    /// Requires a Tokio timer, as provided by Tokio's runtimes.  Without one,
+    /// the `Future` joins the queue after all, like [`lock`](#method.lock).
+    /// See [Timers](index.html#timers).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, lazy};
+    /// # use tokio::runtime::current_thread;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let mut rt = current_thread::Runtime::new().unwrap();
+    /// rt.block_on(lazy(|| {
+    ///     let mut fut = mtx.lock_eventually();
+    ///     assert!(fut.poll().unwrap().is_not_ready());
+    ///     assert_eq!(mtx.waiter_count(), 0);
+    ///     drop(guard);
+    ///     fut.map(|mut guard| *guard += 1)
+    /// })).unwrap();
+    /// assert_eq!(mtx.try_unwrap().unwrap(), 1);
+    /// # }
+    /// ```
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    pub fn lock_eventually(&self) -> LockEventuallyFut<T> {
+        LockEventuallyFut {
+            mutex: self.clone(),
+            delay: None,
+            backoff: Duration::from_millis(
+                LockEventuallyFut::<T>::MIN_BACKOFF_MS),
+            fallback: None,
+        }
+    }
+
+    /// Acquires the `Mutex` like [`lock`](#method.lock), but gives up as soon
+    /// as `token` completes, successfully or not.  Then the returned `Future`
+    /// fails with [`AcquireError::Cancelled`], and stops waiting for the
+    /// `Mutex`.  Any `Future` may serve as the token, for example a
+    /// `oneshot::Receiver` or a `Shared` one for cancelling many acquisitions
+    /// at once.
+    ///
+    /// [`AcquireError::Cancelled`]: enum.AcquireError.html#variant.Cancelled
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use futures::sync::oneshot;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let _guard = mtx.try_lock().unwrap();
+    /// let (cancel, token) = oneshot::channel::<()>();
+    /// let fut = mtx.lock_cancellable(token);
+    /// cancel.send(()).unwrap();
+    /// assert_eq!(fut.wait().err(), Some(AcquireError::Cancelled));
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_cancellable<C>(&self, token: C)
+        -> CancellableFut<MutexFut<T>, C>
+        where C: Future
+    {
+        CancellableFut::new(self.lock(), token)
+    }
+
+    /// Acquires the `Mutex` like [`lock`](#method.lock), but reports whether
+    /// it's poisoned, like `std::sync::Mutex::lock`.  Unlike `lock`, it can
+    /// acquire a poisoned `Mutex`, whose guard may be recovered from the
+    /// `PoisonError`.  Only a `Mutex`
+    /// built with [`MutexBuilder::poison_on_panic`] can be poisoned.
+    ///
+    /// [`MutexBuilder::poison_on_panic`]: struct.MutexBuilder.html#method.poison_on_panic
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use std::thread;
+    /// # fn main() {
+    /// let mtx = Mutex::builder().poison_on_panic(true).build(0u32);
+    /// let mtx2 = mtx.clone();
+    /// thread::spawn(move || {
+    ///     let _guard = mtx2.try_lock().unwrap();
+    ///     panic!("oops");
+    /// }).join().unwrap_err();
+    /// assert!(mtx.is_poisoned());
+    /// let r = mtx.lock_checked().wait().unwrap();
+    /// let guard = r.err().unwrap().into_inner();
+    /// assert_eq!(*guard, 0);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_checked(&self)
+        -> impl Future<Item = sync::LockResult<MutexGuard<T>>,
+                       Error = AcquireError>
+    {
+        let mut fut = self.lock();
+        fut.checked = true;
+        fut.map(|guard| {
+            if guard.mutex.is_poisoned() {
+                Err(sync::PoisonError::new(guard))
+            } else {
+                Ok(guard)
+            }
+        })
+    }
+
+    /// Returns `true` if a guard of this `Mutex` was dropped during a panic.
+    /// See [`MutexBuilder::poison_on_panic`].
+    ///
+    /// [`MutexBuilder::poison_on_panic`]: struct.MutexBuilder.html#method.poison_on_panic
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clears the `Mutex`'s poisoned state, for example after restoring its
+    /// data to a consistent state.
+    pub fn clear_poison(&self) {
+        self.inner.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Attempts to acquire the lock.
+    ///
+    /// If the operation would block, returns `TryLockError::WouldBlock`
+    /// instead.  Otherwise, returns a guard (not a `Future`).  With the `chaos`
+    /// feature, it may also fail spuriously; see [`chaos`](chaos/index.html).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mut mtx = Mutex::<u32>::new(0);
+    /// match mtx.try_lock() {
+    ///     Ok(mut guard) => *guard += 5,
+    ///     Err(TryLockError::WouldBlock) => println!("Better luck next time!"),
+    ///     Err(e) => panic!("{}", e)
+    /// };
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn try_lock(&self) -> Result<MutexGuard<T>, TryLockError> {
+        #[cfg(feature = "chaos")]
+        {
+            if ::chaos::spurious_failure() {
+                return Err(TryLockError::WouldBlock);
+            }
+        }
+        self.try_lock_unperturbed()
+    }
+
+    /// Like [`try_lock`](#method.try_lock), but never fails spuriously, even
+    /// with the `chaos` feature.  For internal callers, whose own callers
+    /// didn't ask for a `try_*` method.
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    fn try_lock_unperturbed(&self) -> Result<MutexGuard<T>, TryLockError> {
+        if self.is_poisoned() {
+            return Err(TryLockError::Panicked);
+        }
+        self.try_lock_unchecked()
+    }
+
+    /// Like [`try_lock_unperturbed`](#method.try_lock_unperturbed), but
+    /// acquires a poisoned `Mutex` too.
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    fn try_lock_unchecked(&self) -> Result<MutexGuard<T>, TryLockError> {
+        let mut mtx_data = self.lock_state()
+            .map_err(|_| TryLockError::Poisoned)?;
+        if mtx_data.closed {
+            Err(TryLockError::Closed)
+        } else if mtx_data.owned {
+            Err(TryLockError::WouldBlock)
+        } else {
+            mtx_data.owned = true;
+            self.inner.locked.store(true, Ordering::Relaxed);
+            mtx_data.emit(LockEvent::Acquired);
+            mtx_data.acknowledge();
+            #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+            #[allow(clippy::incompatible_msrv)]
+            {
+                mtx_data.holder = Some(Holder::detached(Location::caller()));
+            }
+            Ok(MutexGuard{mutex: self.clone()})
+        }
+    }
+
+    /// Acquires the `Mutex`, replaces the protected data with the result of
+    /// `f`, and releases the `Mutex` again, all without exposing a guard.
+    ///
+    /// `f` takes the data by value.  If it panics, the data is left as
+    /// `T::default()`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![3, 1, 2]);
+    /// let fut = mtx.update(|mut v| { v.sort(); v });
+    /// fut.wait().unwrap();
+    /// assert_eq!(mtx.try_unwrap().unwrap(), vec![1, 2, 3]);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn update<F>(&self, f: F)
+        -> impl Future<Item = (), Error = AcquireError>
+        where F: FnOnce(T) -> T,
+              T: Default
+    {
+        self.update_and(|data| {
+            let old = mem::replace(data, T::default());
+            *data = f(old);
+        })
+    }
+
+    /// Acquires the `Mutex`, applies `f` to the protected data, and releases
+    /// the `Mutex` again, all without exposing a guard.  The returned `Future`
+    /// yields `f`'s result.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(5);
+    /// let fut = mtx.update_and(|x| {
+    ///     let old = *x;
+    ///     *x += 1;
+    ///     old
+    /// });
+    /// assert_eq!(fut.wait(), Ok(5));
+    /// assert_eq!(*mtx.try_lock().unwrap(), 6);
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn update_and<F, R>(&self, f: F)
+        -> impl Future<Item = R, Error = AcquireError>
+        where F: FnOnce(&mut T) -> R
+    {
+        self.lock().map(move |mut guard| f(&mut *guard))
+    }
+
+    /// Acquires the `Mutex`, passes the protected data to `f`, and holds the
+    /// `Mutex` until the `Future` returned by `f` completes.  Then releases
+    /// it, and yields that `Future`'s result.
+    ///
+    /// `f` only gets a reference to the data, whose lifetime ends when `f`
+    /// returns.  So no guard can escape the critical section, and the `Mutex`
+    /// is always released when the returned `Future` completes or is dropped.
+    /// But for the same reason, the `Future` that `f` returns can't borrow
+    /// the data; it can only keep other tasks out while it runs.  To work on
+    /// the data from within that `Future`, chain it onto the guard returned by
+    /// [`lock`](#method.lock) instead.  If an acquisition fails, the
+    /// `AcquireError` is converted into `f`'s error type.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, future};
+    /// # use std::io;
+    /// # fn main() {
+    /// let mtx = Mutex::<Vec<u32>>::new(vec![1, 2, 3]);
+    /// let fut = mtx.lock_during(|v| {
+    ///     let sum: u32 = v.iter().sum();
+    ///     v.clear();
+    ///     // The Mutex is still held while this Future runs
+    ///     future::ok::<u32, io::Error>(sum)
+    /// });
+    /// assert_eq!(fut.wait().unwrap(), 6);
+    /// assert!(mtx.try_lock().unwrap().is_empty());
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_during<F, B>(&self, f: F)
+        -> impl Future<Item = B::Item, Error = B::Error>
+        where F: FnOnce(&mut T) -> B,
+              B: IntoFuture,
+              B::Error: From<AcquireError>
+    {
+        self.lock()
+            .map_err(B::Error::from)
+            .and_then(move |mut guard| {
+                f(&mut *guard).into_future()
+                    .then(move |result| {
+                        drop(guard);
+                        result
+                    })
+            })
+    }
+
+    /// Creates a handle to part of the protected data, for example to one
+    /// field of a struct.  It shares this `Mutex`'s lock, but its guards only
+    /// expose whatever `f` returns.  That lets subsystems be handed a narrower
+    /// lock without splitting the data into several `Mutex`es.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// struct State {
+    ///     hits: u32,
+    ///     misses: u32,
+    /// }
+    /// let mtx = Mutex::new(State{hits: 0, misses: 0});
+    /// let hits = mtx.project(|state| &mut state.hits);
+    /// *hits.lock().wait().unwrap() += 1;
+    /// // The projection shares the Mutex's lock
+    /// let guard = mtx.try_lock().unwrap();
+    /// assert!(hits.try_lock().is_err());
+    /// assert_eq!(guard.hits, 1);
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "nightly-docs", doc(alias = "map"))]
+    pub fn project<U, F>(&self, f: F) -> MappedMutex<T, U>
+        where U: ?Sized,
+              F: Fn(&mut T) -> &mut U + Send + Sync + 'static
+    {
+        MappedMutex{mutex: self.clone(), f: Arc::new(f)}
+    }
+
+    /// Acquires the `Mutex` once `predicate` holds for the protected data.
+    /// Whenever the `Mutex` is acquired but the predicate fails, the returned
+    /// `Future` releases it again, and waits for some other guard to be
+    /// released before retrying.  For simple conditions, that avoids pairing
+    /// the `Mutex` with a separate condition variable.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use std::thread;
+    /// # fn main() {
+    /// let count = Mutex::<u32>::new(0);
+    /// let count2 = count.clone();
+    /// let incrementer = thread::spawn(move || {
+    ///     for _ in 0..10 {
+    ///         *count2.lock().wait().unwrap() += 1;
+    ///     }
+    /// });
+    /// let guard = count.wait_until(|x| *x >= 5).wait().unwrap();
+    /// assert!(*guard >= 5);
+    /// # drop(guard);
+    /// # incrementer.join().unwrap();
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "nightly-docs", doc(alias = "lock_when"))]
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn wait_until<F>(&self, predicate: F) -> WaitUntilFut<T, F>
+        where F: FnMut(&T) -> bool
+    {
+        WaitUntilFut{mutex: self.clone(), fut: self.lock(), predicate}
+    }
+
+    /// Returns a `Stream` that yields whenever a guard of this `Mutex` is
+    /// released, starting from now.  Like a watch channel, several releases
+    /// between polls are reported only once, so subscribers see that the
+    /// data may have changed, but not every intermediate value.
+    ///
+    /// Every release counts, even of a guard that never touched the data.
+    /// The `Stream` doesn't keep the `Mutex` alive, and ends once every
+    /// clone of the `Mutex` has been dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, Stream};
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let changes = mtx.changes();
+    /// *mtx.try_lock().unwrap() += 1;
+    /// *mtx.try_lock().unwrap() += 1;
+    /// let (change, _changes) = changes.into_future().wait().ok().unwrap();
+    /// assert_eq!(change, Some(()));
+    /// # }
+    /// ```
+    pub fn changes(&self) -> MutexChanges<T> {
+        let seen = self.lock_state_infallible().releases;
+        MutexChanges{mutex: Mutex::downgrade(self), seen}
+    }
+
+    /// Returns a `Stream` that acquires the `Mutex` once for every item,
+    /// yielding the guards.  Each acquisition begins when the `Stream` is
+    /// polled for its next item, and waits its turn like
+    /// [`lock`](#method.lock).  So each guard must be dropped before polling
+    /// for the next, or the `Stream` will wait for itself forever.
+    ///
+    /// The `Stream` ends once the `Mutex` is [closed](#method.close).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, Stream};
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let closer = mtx.clone();
+    /// mtx.lock_stream()
+    ///     .for_each(move |mut guard| {
+    ///         *guard += 1;
+    ///         if *guard == 3 {
+    ///             closer.close();
+    ///         }
+    ///         Ok(())
+    ///     }).wait().unwrap();
+    /// assert_eq!(mtx.try_unwrap().ok(), Some(3));
+    /// # }
+    /// ```
+    pub fn lock_stream(&self) -> MutexLocks<T> {
+        MutexLocks{mutex: self.clone(), fut: None}
+    }
+
+    /// Returns a `Stream` of everything that happens to this `Mutex` from now
+    /// on, for building custom contention metrics.  A waiter is reported as
+    /// `Acquired` as soon as the `Mutex` is handed to it, even if its task
+    /// hasn't run yet.
+    ///
+    /// The `Stream` is unbounded, so it should be drained promptly.  It never
+    /// fails, and ends once every clone of the `Mutex` has been dropped.
+    ///
+    /// With the `tracing` feature, every event is also emitted as a
+    /// TRACE-level `tracing` event, whether or not anybody has subscribed.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, Stream};
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let events = mtx.events();
+    /// drop(mtx.try_lock().unwrap());
+    /// drop(mtx);
+    /// assert_eq!(events.collect().wait().unwrap(),
+    ///            vec![LockEvent::Acquired, LockEvent::Released]);
+    /// # }
+    /// ```
+    pub fn events(&self) -> impl Stream<Item = LockEvent, Error = ()> {
+        let (tx, rx) = mpsc::unbounded();
+        self.lock_state_infallible().subscribers.push(tx);
+        rx
+    }
+
+    /// Returns a `Future` that completes the next time a guard of this
+    /// `Mutex` is released, or once every clone of the `Mutex` has been
+    /// dropped.
+    pub fn changed(&self) -> impl Future<Item = (), Error = AcquireError> {
+        self.changes()
+            .into_future()
+            .map(|_| ())
+            .map_err(|(e, _)| e)
+    }
+
+    /// Returns the name given to this `Mutex` by
+    /// [`MutexBuilder::name`](struct.MutexBuilder.html#method.name), if any.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.config.name.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns `true` if the `Mutex` is currently owned, either by a guard or
+    /// by a waiter that hasn't yet been polled.  Unlike `try_lock`, this never
+    /// acquires the `Mutex` nor disturbs its waiters.  The answer may be stale
+    /// by the time it's used, so it's best suited for metrics and assertions.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// assert!(!mtx.is_locked());
+    /// let guard = mtx.try_lock().unwrap();
+    /// assert!(mtx.is_locked());
+    /// drop(guard);
+    /// assert!(!mtx.is_locked());
+    /// # }
+    /// ```
+    pub fn is_locked(&self) -> bool {
+        self.lock_state_infallible().owned
+    }
+
+    /// Returns how many tasks are waiting for the `Mutex`, not counting
+    /// waiters that have already given up.  That can guide backpressure, for
+    /// example shedding work when the queue grows too long.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let _guard = mtx.try_lock().unwrap();
+    /// let mut fut = mtx.lock();
+    /// assert!(fut.poll_once().unwrap().is_not_ready());
+    /// assert_eq!(mtx.waiter_count(), 1);
+    /// drop(fut);
+    /// assert_eq!(mtx.waiter_count(), 0);
+    /// # }
+    /// ```
+    pub fn waiter_count(&self) -> usize {
+        self.lock_state_infallible().live_waiters()
+    }
+
+    /// Closes the `Mutex`.  Every queued acquisition fails with
+    /// [`AcquireError::Closed`], as will every later one, including those of
+    /// [`wait_until`](#method.wait_until) and
+    /// [`lock_some`](#method.lock_some).  Existing guards are
+    /// unaffected, and release the `Mutex` as usual.  Closing can't be undone.
+    ///
+    /// [`AcquireError::Closed`]: enum.AcquireError.html#variant.Closed
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let mut guard = mtx.try_lock().unwrap();
+    /// let mut fut = mtx.lock();
+    /// assert!(fut.poll_once().unwrap().is_not_ready());
+    /// mtx.close();
+    /// assert_eq!(fut.wait().err(), Some(AcquireError::Closed));
+    /// *guard += 1;
+    /// drop(guard);
+    /// assert_eq!(mtx.lock().wait().err(), Some(AcquireError::Closed));
+    /// assert_eq!(mtx.try_lock().err(), Some(TryLockError::Closed));
+    /// # }
+    /// ```
+    pub fn close(&self) {
+        let waiters = {
+            let mut mtx_data = self.lock_state_infallible();
+            mtx_data.closed = true;
+            // Nobody in the queue will take the Mutex now
+            mtx_data.acknowledge();
+            // Tasks waiting for a release, like wait_until's, must learn that
+            // they'll never get the Mutex.
+            for watcher in mtx_data.watchers.drain(..) {
+                watcher.notify();
+            }
+            mem::replace(&mut mtx_data.waiters, VecDeque::new())
+        };
+        // Dropping the senders wakes the waiters
+        drop(waiters);
+    }
+
+    /// Returns `true` if the `Mutex` has been [closed](#method.close).
+    pub fn is_closed(&self) -> bool {
+        self.lock_state_infallible().closed
+    }
+
+    /// Returns `true` if this is the only handle to the `Mutex`, and it isn't
+    /// locked.  Then [`try_unwrap`](#method.try_unwrap) will succeed, unless
+    /// a [`MutexWeak`](struct.MutexWeak.html) gets upgraded in the meantime.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let other = mtx.clone();
+    /// assert!(!mtx.is_unique());
+    /// drop(other);
+    /// assert!(mtx.is_unique());
+    /// assert_eq!(mtx.try_unwrap().unwrap(), 0);
+    /// # }
+    /// ```
+    pub fn is_unique(&self) -> bool {
+        sync::Arc::strong_count(&self.inner) == 1 && !self.is_locked()
+    }
+
+    /// Returns how many times the `Mutex` has been acquired.  While it's
+    /// locked, that's the [generation](struct.MutexGuard.html#method.generation)
+    /// of its current guard.  It only ever increases, so it can detect
+    /// whether anybody else acquired the `Mutex` between two of the caller's
+    /// own acquisitions, for optimistic concurrency.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let seen = MutexGuard::generation(&mtx.try_lock().unwrap());
+    /// // Nobody else has locked it since
+    /// assert_eq!(mtx.current_generation(), seen);
+    /// let other = mtx.clone();
+    /// *other.try_lock().unwrap() += 1;
+    /// let guard = mtx.try_lock().unwrap();
+    /// assert_eq!(MutexGuard::generation(&guard), seen + 2);
+    /// # }
+    /// ```
+    pub fn current_generation(&self) -> u64 {
+        let mtx_data = self.lock_state_infallible();
+        mtx_data.releases.wrapping_add(mtx_data.owned as u64)
+    }
+
+    /// Returns a snapshot of the `Mutex`'s state and settings.
     ///
     /// # Examples
-    ///
     /// ```
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
     /// # fn main() {
-    /// let mut mtx = Mutex::<u32>::new(0);
-    /// *mtx.get_mut().unwrap() += 5;
-    /// assert_eq!(mtx.try_unwrap().unwrap(), 5);
+    /// let mtx = Mutex::builder().name("jobs").max_waiters(8).build(0u32);
+    /// let _guard = mtx.try_lock().unwrap();
+    /// let info = mtx.info();
+    /// assert_eq!(info.name(), Some("jobs"));
+    /// assert_eq!(info.state(), LockState::Exclusive);
+    /// assert_eq!(info.waiters(), 0);
+    /// assert_eq!(info.max_waiters(), Some(8));
     /// # }
     /// ```
-    pub fn get_mut(&mut self) -> Option<&mut T> {
-        if let Some(inner) = sync::Arc::get_mut(&mut self.inner) {
-            let lock_data = inner.mutex.get_mut().unwrap();
-            let data = unsafe { inner.data.get().as_mut() }.unwrap();
-            debug_assert!(!lock_data.owned);
-            Some(data)
-        } else {
-            None
+    pub fn info(&self) -> LockInfo {
+        let config = &self.inner.config;
+        let mtx_data = self.lock_state_infallible();
+        LockInfo {
+            name: config.name.clone(),
+            state: if mtx_data.owned {
+                LockState::Exclusive
+            } else {
+                LockState::Unlocked
+            },
+            readers: 0,
+            waiters: mtx_data.live_waiters(),
+            fairness: config.fairness,
+            max_waiters: config.max_waiters,
+            yield_budget: config.yield_budget,
+            spin_limit: config.spin_limit,
+            #[cfg(feature = "rwlock")]
+            preference: None,
+            max_readers: None,
+            poison_policy: self.poison_policy(),
+            poison_on_panic: config.poison_on_panic,
+            closed: mtx_data.closed,
+            #[cfg(feature = "stats")]
+            stats: if config.stats {Some(mtx_data.stats.clone())} else {None},
         }
     }
 
-    /// Acquires a `Mutex`, blocking the task in the meantime.  When the
-    /// returned `Future` is ready, this task will have sole access to the
-    /// protected data.
-    pub fn lock(&self) -> MutexFut<T> {
-        MutexFut::new(FutState::New, self.clone())
+    /// Returns the policy for handling a poisoned `Mutex`.
+    ///
+    /// See [`PoisonPolicy`](enum.PoisonPolicy.html).
+    pub fn poison_policy(&self) -> PoisonPolicy {
+        PoisonPolicy::from_usize(self.inner.poison_policy.load(Ordering::Relaxed))
     }
 
-    /// Attempts to acquire the lock.
+    /// Sets the policy for handling a poisoned `Mutex`.  It applies to every
+    /// clone of this `Mutex`.
     ///
-    /// If the operation would block, returns `Err` instead.  Otherwise, returns
-    /// a guard (not a `Future`).
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// mtx.set_poison_policy(PoisonPolicy::Error);
+    /// assert_eq!(mtx.clone().poison_policy(), PoisonPolicy::Error);
+    /// # }
+    /// ```
+    pub fn set_poison_policy(&self, policy: PoisonPolicy) {
+        self.inner.poison_policy.store(policy.to_usize(), Ordering::Relaxed);
+    }
+
+    /// Returns usage statistics for this `Mutex`.
     ///
     /// # Examples
     /// ```
+    /// # extern crate futures;
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
+    /// # use futures::{Future, future};
+    /// # use futures::executor::spawn;
     /// # fn main() {
-    /// let mut mtx = Mutex::<u32>::new(0);
-    /// match mtx.try_lock() {
-    ///     Ok(mut guard) => *guard += 5,
-    ///     Err(()) => println!("Better luck next time!")
-    /// };
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let guard = mtx.try_lock().unwrap();
+    /// let fut = spawn(future::lazy(|| {
+    ///     let mut fut = mtx.lock();
+    ///     assert!(fut.poll().unwrap().is_not_ready());
+    ///     future::ok::<_, ()>(fut)
+    /// })).wait_future().unwrap();
+    /// assert_eq!(mtx.stats().max_queue_depth, 1);
+    /// drop(guard);
+    /// spawn(fut).wait_future().unwrap();
     /// # }
     /// ```
-    pub fn try_lock(&self) -> Result<MutexGuard<T>, ()> {
-        let mut mtx_data = self.inner.mutex.lock().expect("sync::Mutex::lock");
-        if mtx_data.owned {
-            Err(())
-        } else {
-            mtx_data.owned = true;
-            Ok(MutexGuard{mutex: self.clone()})
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn stats(&self) -> LockStats {
+        self.lock_state_infallible().stats.clone()
+    }
+
+    /// Resets all of this `Mutex`'s usage statistics.
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn reset_stats(&self) {
+        self.lock_state_infallible().stats = LockStats::default();
+    }
+
+    fn lock_state(&self)
+        -> Result<sync::MutexGuard<'_, MutexData>, AcquireError>
+    {
+        lock_state(&self.inner.mutex, self.poison_policy())
+    }
+
+    fn lock_state_infallible(&self) -> sync::MutexGuard<'_, MutexData> {
+        lock_state_infallible(&self.inner.mutex, self.poison_policy())
+    }
+
+    /// Release a guard: check the invariant, then unlock.
+    fn release(&self) {
+        self.release_with(self.inner.config.fairness);
+    }
+
+    /// Like `release`, but chooses the next owner by `fairness`.
+    fn release_with(&self, fairness: Fairness) {
+        if self.inner.config.poison_on_panic && thread::panicking() {
+            self.inner.poisoned.store(true, Ordering::Relaxed);
+        }
+        let valid = self.inner.validator.check(self.inner.data.get());
+        self.unlock_with(fairness);
+        // Panicking again while unwinding would abort
+        if !valid && !thread::panicking() {
+            panic!("Mutex invariant violated on release");
         }
     }
 
-    /// Release the `Mutex`
     fn unlock(&self) {
-        let mut mtx_data = self.inner.mutex.lock().expect("sync::Mutex::lock");
+        self.unlock_with(self.inner.config.fairness);
+    }
+
+    fn unlock_with(&self, fairness: Fairness) {
+        let mut mtx_data = self.lock_state_infallible();
         assert!(mtx_data.owned);
-        if let Some(tx) = mtx_data.waiters.pop_front() {
-            // Send ownership to the waiter
-            tx.send(()).expect("Sender::send");
+        #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+        {
+            mtx_data.holder = None;
+        }
+        mtx_data.releases = mtx_data.releases.wrapping_add(1);
+        mtx_data.emit(LockEvent::Released);
+        for watcher in mtx_data.watchers.drain(..) {
+            watcher.notify();
+        }
+        if fairness == Fairness::Barging {
+            // Let the longest waiter compete for the Mutex.  Any ack waits for
+            // whoever takes it.
+            if !mtx_data.wake_next() {
+                mtx_data.acknowledge();
+            }
         } else {
-            // Relinquish ownership
-            mtx_data.owned = false;
+            while let Some(waiter) = pop_waiter(&mut mtx_data.waiters, fairness)
+            {
+                // Send ownership to the waiter, unless it was already
+                // cancelled
+                if waiter.tx.send(()).is_ok() {
+                    mtx_data.granted = Some(waiter.ticket);
+                    mtx_data.emit(LockEvent::Acquired);
+                    if mtx_data.ack.is_some() {
+                        mtx_data.ack_ticket = Some(waiter.ticket);
+                    }
+                    return;
+                }
+            }
+            // Nobody is left to take it
+            mtx_data.acknowledge();
+        }
+        // Relinquish ownership
+        mtx_data.owned = false;
+        self.inner.locked.store(false, Ordering::Relaxed);
+    }
+
+    /// Waits for the `Mutex` to be released, for up to the configured number
+    /// of spins.  The result is only a hint.
+    fn spin(&self) {
+        for _ in 0..self.inner.config.spin_limit {
+            if !self.inner.locked.load(Ordering::Relaxed) {
+                return;
+            }
+            // std::hint::spin_loop is too new
+            #[allow(deprecated)]
+            sync::atomic::spin_loop_hint();
         }
     }
 
+    /// The address of the shared state, which identifies this `Mutex` and all
+    /// of its clones.
+    pub(crate) fn addr(&self) -> usize {
+        &**self.inner as *const Inner<T> as *const u8 as usize
+    }
+
     /// Returns true if the two `Mutex` point to the same data else false.
     pub fn ptr_eq(this: &Mutex<T>, other: &Mutex<T>) -> bool {
         sync::Arc::ptr_eq(&this.inner, &other.inner)
     }
+
+    /// Moves out the shared state without changing the handle count.
+    fn into_arc(self) -> sync::Arc<Inner<T>> {
+        self.inner.into_arc()
+    }
+
+    /// Acquires two `Mutex`es at once, returning a `Future` of both guards,
+    /// in argument order.  The `Mutex`es are acquired in order of their
+    /// addresses, so two tasks that `lock_both` the same pair can't deadlock
+    /// each other, even if they pass them in opposite orders.  See also
+    /// [`multi_lock!`](macro.multi_lock.html) for more than two `Mutex`es.
+    ///
+    /// # Panics
+    ///
+    /// Polling the returned `Future` will panic if `this` and `other` are the
+    /// same `Mutex`, since that could never succeed.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use futures::executor::spawn;
+    /// # fn main() {
+    /// let checking = Mutex::<u32>::new(100);
+    /// let savings = Mutex::<u32>::new(0);
+    /// let fut = Mutex::lock_both(&checking, &savings)
+    ///     .map(|(mut from, mut to)| {
+    ///         *from -= 30;
+    ///         *to += 30;
+    ///     });
+    /// spawn(fut).wait_future().unwrap();
+    /// assert_eq!(*savings.try_lock().unwrap(), 30);
+    /// # }
+    /// ```
+    pub fn lock_both<U>(this: &Mutex<T>, other: &Mutex<U>)
+        -> impl Future<Item = (MutexGuard<T>, MutexGuard<U>),
+                       Error = AcquireError>
+        where U: ?Sized
+    {
+        let mut s0 = Slot::new(this);
+        let mut s1 = Slot::new(other);
+        let mut state = State::default();
+        multi_lock::poll_fn(move || {
+            if state.poll(&mut [&mut s0, &mut s1])?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready((s0.take(), s1.take())))
+        })
+    }
+
+    /// Acquires every `Mutex` in `mutexes`, returning a `Future` of their
+    /// guards, in slice order.  Like [`lock_both`](#method.lock_both), the
+    /// `Mutex`es are acquired in order of their addresses, so overlapping
+    /// `lock_all`s can't deadlock each other.  If the `Future` is dropped
+    /// before it completes, then any `Mutex`es that it already acquired are
+    /// released.
+    ///
+    /// # Panics
+    ///
+    /// Polling the returned `Future` will panic if the same `Mutex` (or two
+    /// clones of it) appears twice.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use futures::executor::spawn;
+    /// # fn main() {
+    /// let shards = (0..4).map(Mutex::<u32>::new).collect::<Vec<_>>();
+    /// let fut = Mutex::lock_all(&shards)
+    ///     .map(|guards| guards.iter().map(|g| **g).sum::<u32>());
+    /// assert_eq!(spawn(fut).wait_future().unwrap(), 6);
+    /// # }
+    /// ```
+    pub fn lock_all(mutexes: &[Mutex<T>])
+        -> impl Future<Item = Vec<MutexGuard<T>>, Error = AcquireError>
+    {
+        let mut slots = mutexes.iter().map(Slot::new).collect::<Vec<_>>();
+        let mut state = State::default();
+        multi_lock::poll_fn(move || {
+            let ready = {
+                let mut acquires = slots.iter_mut()
+                    .map(|s| s as &mut dyn Acquire)
+                    .collect::<Vec<_>>();
+                state.poll(&mut acquires)?
+            };
+            if ready.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(slots.iter_mut().map(Slot::take).collect()))
+        })
+    }
 }
 
 impl<T: 'static + ?Sized> Mutex<T> {
@@ -350,6 +3661,16 @@ impl<T: 'static + ?Sized> Mutex<T> {
     /// own tasks, lest their continuations get blocked by slow stacked
     /// combinators.
     ///
+    /// # Errors
+    ///
+    /// If the `Mutex` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
     /// # Examples
     ///
     /// ```
@@ -365,7 +3686,7 @@ impl<T: 'static + ?Sized> Mutex<T> {
     /// let r = rt.block_on(lazy(|| {
     ///     mtx.with(|mut guard| {
     ///         *guard += 5;
-    ///         Ok(()) as Result<(), ()>
+    ///         Ok(()) as Result<(), AcquireError>
     ///     }).unwrap()
     /// }));
     /// assert!(r.is_ok());
@@ -380,28 +3701,107 @@ impl<T: 'static + ?Sized> Mutex<T> {
               B: IntoFuture<Item = R, Error = E> + 'static,
               <B as IntoFuture>::Future: Send,
               R: Send + 'static,
-              E: Send + 'static,
+              E: From<AcquireError> + Send + 'static,
               T: Send
     {
         let (tx, rx) = oneshot::channel::<Result<R, E>>();
         tokio_executor::DefaultExecutor::current().spawn(Box::new(self.lock()
-            .and_then(move |data| {
-                f(data).into_future()
-                       .then(move |result| {
-                           // Swallow errors; there's nothing to do if the
-                           // receiver got cancelled
-                           let _ = tx.send(result);
-                           future::ok::<(), ()>(())
-                       })
+            .map_err(E::from)
+            .and_then(f)
+            .then(move |result| {
+                // Swallow errors; there's nothing to do if the receiver got
+                // cancelled
+                let _ = tx.send(result);
+                future::ok::<(), ()>(())
+            })
+            // We control the sender, so it will only be dropped before
+            // sending if the task was.
+        )).map(|_| rx.then(|r| r.expect("Mutex critical section was dropped")))
+    }
+
+    /// Like [`with`](#method.with), but catches any panic in the critical
+    /// section, whether in `f` or in the `Future` that it returns.  The
+    /// returned `Future` resolves to the critical section's result, or fails
+    /// with the panic's payload, like `std::thread::JoinHandle::join`.
+    ///
+    /// Either way, the `Mutex` is released.  A panic will still poison it if
+    /// it was built with
+    /// [`poison_on_panic`](struct.MutexBuilder.html#method.poison_on_panic).
+    ///
+    /// # Errors
+    ///
+    /// If the `Mutex` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, lazy};
+    /// # use tokio::runtime::current_thread::Runtime;
+    /// # fn main() {
+    /// let mtx = Mutex::<u32>::new(0);
+    /// let mut rt = Runtime::new().unwrap();
+    /// let r = rt.block_on(lazy(|| {
+    ///     mtx.with_catch(|_guard| -> Result<(), AcquireError> {
+    ///         panic!("user callback failed")
+    ///     }).unwrap()
+    /// }));
+    /// let payload = r.unwrap_err();
+    /// assert_eq!(payload.downcast_ref(), Some(&"user callback failed"));
+    /// assert!(mtx.try_lock().is_ok());
+    /// # }
+    /// ```
+    #[cfg(any(feature = "tokio", all(feature = "nightly-docs", rustdoc)))]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "tokio")))]
+    pub fn with_catch<F, B, R, E>(&self, f: F)
+        -> Result<impl Future<Item = Result<R, E>, Error = Box<dyn Any + Send>>,
+                  SpawnError>
+        where F: FnOnce(MutexGuard<T>) -> B + Send + 'static,
+              B: IntoFuture<Item = R, Error = E> + 'static,
+              <B as IntoFuture>::Future: Send,
+              R: Send + 'static,
+              E: From<AcquireError> + Send + 'static,
+              T: Send
+    {
+        let (tx, rx) = oneshot::channel::<thread::Result<Result<R, E>>>();
+        tokio_executor::DefaultExecutor::current().spawn(Box::new(self.lock()
+            .then(move |r| match r {
+                // Like thread::spawn, don't require UnwindSafe.  With
+                // poison_on_panic, later owners learn of a half-finished
+                // update.
+                Ok(data) => Either::A(
+                    AssertUnwindSafe(future::lazy(move || f(data)))
+                        .catch_unwind()),
+                Err(e) => Either::B(future::ok(Err(E::from(e))))
+            }).then(move |result| {
+                let _ = tx.send(result);
+                future::ok::<(), ()>(())
             })
-            // We control the sender so we're sure it won't be dropped before
-            // sending so we can unwrap safely
-        )).map(|_| rx.then(Result::unwrap))
+        )).map(|_| rx.then(|r| r.expect("Mutex critical section was dropped")))
     }
 
     /// Like [`with`](#method.with) but for Futures that aren't `Send`.
     /// Spawns a new task on a single-threaded Runtime to complete the Future.
     ///
+    /// # Errors
+    ///
+    /// If the `Mutex` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
     /// # Examples
     ///
     /// ```
@@ -419,7 +3819,7 @@ impl<T: 'static + ?Sized> Mutex<T> {
     /// let r = rt.block_on(lazy(|| {
     ///     mtx.with_local(|mut guard| {
     ///         *Rc::get_mut(&mut *guard).unwrap() += 5;
-    ///         Ok(()) as Result<(), ()>
+    ///         Ok(()) as Result<(), AcquireError>
     ///     }).unwrap()
     /// }));
     /// assert!(r.is_ok());
@@ -433,23 +3833,224 @@ impl<T: 'static + ?Sized> Mutex<T> {
         where F: FnOnce(MutexGuard<T>) -> B + 'static,
               B: IntoFuture<Item = R, Error = E> + 'static,
               R: 'static,
-              E: 'static
+              E: From<AcquireError> + 'static
     {
         let (tx, rx) = oneshot::channel::<Result<R, E>>();
         current_thread::TaskExecutor::current().spawn_local(Box::new(
             self.lock()
-            .and_then(move |data| {
-                f(data).into_future()
-                       .then(move |result| {
-                           // Swallow errors; there's nothing to do if the
-                           // receiver got cancelled
-                           let _ = tx.send(result);
-                           future::ok::<(), ()>(())
-                       })
+            .map_err(E::from)
+            .and_then(f)
+            .then(move |result| {
+                // Swallow errors; there's nothing to do if the receiver got
+                // cancelled
+                let _ = tx.send(result);
+                future::ok::<(), ()>(())
             })
-            // We control the sender so we're sure it won't be dropped before
-            // sending so we can unwrap safely
-        )).map(|_| rx.then(Result::unwrap))
+            // We control the sender, so it will only be dropped before
+            // sending if the task was.
+        )).map(|_| rx.then(|r| r.expect("Mutex critical section was dropped")))
+    }
+}
+
+impl<T> Mutex<Option<T>> {
+    /// Acquires the `Mutex` once it's populated.  Whenever the `Mutex` is
+    /// acquired but holds `None`, the returned `Future` releases it again and
+    /// waits for some other guard to be released before retrying.  The
+    /// resulting guard dereferences directly to the `T`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use std::thread;
+    /// # fn main() {
+    /// let slot = Mutex::<Option<u32>>::new(None);
+    /// let slot2 = slot.clone();
+    /// let filler = thread::spawn(move || {
+    ///     *slot2.lock().wait().unwrap() = Some(42);
+    /// });
+    /// assert_eq!(*slot.lock_some().wait().unwrap(), 42);
+    /// filler.join().unwrap();
+    /// # }
+    /// ```
+    #[cfg_attr(all(feature = "deadlock-detection", debug_assertions),
+               track_caller)]
+    pub fn lock_some(&self) -> LockSomeFut<T> {
+        LockSomeFut{fut: self.wait_until(Option::is_some)}
+    }
+}
+
+/// Creates an unlocked `Mutex`, like [`Mutex::new`].  That lets generic code
+/// accept anything that's `Into<Mutex<T>>`.
+///
+/// [`Mutex::new`]: struct.Mutex.html#method.new
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// fn register<M: Into<Mutex<u32>>>(m: M) -> Mutex<u32> { m.into() }
+///
+/// let mtx = register(42);
+/// assert_eq!(*mtx.try_lock().unwrap(), 42);
+/// # }
+/// ```
+impl<T> From<T> for Mutex<T> {
+    fn from(t: T) -> Self {
+        Mutex::new(t)
+    }
+}
+
+/// Type-erases a `Mutex`'s protected data, so that locks of different types can
+/// be stored together.  The result shares its data with `mutex`'s clones.
+/// See [`MutexGuard::downcast`](struct.MutexGuard.html#method.downcast).
+impl<T: Any + Send> From<Mutex<T>> for Mutex<dyn Any + Send> {
+    fn from(mutex: Mutex<T>) -> Self {
+        Mutex{inner: Handle(Some(mutex.into_arc()))}
+    }
+}
+
+/// Erases a `Mutex`'s protected type down to its `Debug` impl, so that locks
+/// of different types can be stored together.  The result shares its data with
+/// `mutex`'s clones.
+///
+/// # Examples
+/// ```
+/// # use futures_locks::*;
+/// # use std::fmt::Debug;
+/// let mtxs: Vec<Mutex<dyn Debug + Send>> = vec![
+///     Mutex::new(42u32).into(),
+///     Mutex::new("hello").into(),
+/// ];
+/// let guard = mtxs[1].try_lock().unwrap();
+/// assert_eq!(format!("{:?}", &*guard), "\"hello\"");
+/// ```
+impl<T: fmt::Debug + Send + 'static> From<Mutex<T>>
+    for Mutex<dyn fmt::Debug + Send>
+{
+    fn from(mutex: Mutex<T>) -> Self {
+        Mutex{inner: Handle(Some(mutex.into_arc()))}
+    }
+}
+
+/// Erases a `Mutex`'s protected type down to its `Display` impl.  The result
+/// shares its data with `mutex`'s clones.
+impl<T: fmt::Display + Send + 'static> From<Mutex<T>>
+    for Mutex<dyn fmt::Display + Send>
+{
+    fn from(mutex: Mutex<T>) -> Self {
+        Mutex{inner: Handle(Some(mutex.into_arc()))}
+    }
+}
+
+/// Implements unsizing from `Mutex<[T; N]>` to `Mutex<[T]>` for each `N`.
+/// Without `CoerceUnsized` (which is unstable) this can't be generic over the
+/// length.
+macro_rules! unsize_array_mutex {
+    ($($n:expr),*) => {$(
+        impl<T: Send> From<Mutex<[T; $n]>> for Mutex<[T]> {
+            fn from(mutex: Mutex<[T; $n]>) -> Self {
+                Mutex{inner: Handle(Some(mutex.into_arc()))}
+            }
+        }
+    )*}
+}
+
+// Converts a `Mutex` of an array into a `Mutex` of a slice, for arrays of up
+// to 32 elements.  The result shares its data with the original's clones.
+unsize_array_mutex!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+    17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32);
+
+/// Two `Mutex`es are equal if they're clones of each other, regardless of the
+/// protected data.  See [`ptr_eq`](#method.ptr_eq).
+impl<T: ?Sized> PartialEq for Mutex<T> {
+    fn eq(&self, other: &Mutex<T>) -> bool {
+        Mutex::ptr_eq(self, other)
+    }
+}
+
+impl<T: ?Sized> Eq for Mutex<T> {}
+
+/// Hashes the `Mutex`'s identity, not its protected data, consistent with its
+/// `Eq` implementation.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use std::collections::HashSet;
+/// # fn main() {
+/// let a = Mutex::<u32>::new(0);
+/// let b = Mutex::<u32>::new(0);
+/// let set: HashSet<_> = vec![a.clone(), b.clone(), a.clone()]
+///     .into_iter()
+///     .collect();
+/// assert_eq!(set.len(), 2);
+/// # }
+/// ```
+impl<T: ?Sized> Hash for Mutex<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr().hash(state)
+    }
+}
+
+/// Formats the protected data, if the `Mutex` isn't currently locked.
+/// Otherwise, formats as `<locked>`.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// let mtx = Mutex::<u32>::new(42);
+/// assert_eq!(format!("{}", mtx), "42");
+/// let _guard = mtx.try_lock().unwrap();
+/// assert_eq!(format!("{}", mtx), "<locked>");
+/// # }
+/// ```
+impl<T: ?Sized + fmt::Display> fmt::Display for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peek at the data without acquiring the Mutex, so formatting it
+        // doesn't count as a release or notify anybody.  Nobody can acquire
+        // it while we hold its state.
+        match self.lock_state() {
+            Ok(ref mtx_data) if !mtx_data.owned => {
+                fmt::Display::fmt(unsafe {&*self.inner.data.get()}, f)
+            },
+            _ => f.write_str("<locked>")
+        }
+    }
+}
+
+/// Formats the `Mutex`'s name, its state, and the number of tasks waiting to
+/// acquire it.  The protected data is not formatted.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// let mtx = Mutex::builder().name("sessions").build(0u32);
+/// let _guard = mtx.try_lock().unwrap();
+/// assert_eq!(format!("{:?}", mtx),
+///            "Mutex { name: Some(\"sessions\"), state: \"Locked\", waiters: 0 }");
+/// # }
+/// ```
+impl<T: ?Sized> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut dbg = f.debug_struct("Mutex");
+        dbg.field("name", &self.name());
+        match self.lock_state() {
+            Ok(mtx_data) => {
+                let state = if mtx_data.owned {"Locked"} else {"Unlocked"};
+                dbg.field("state", &state)
+                    .field("waiters", &mtx_data.live_waiters())
+            },
+            Err(_) => dbg.field("state", &"Poisoned")
+        }.finish()
     }
 }
 
@@ -459,13 +4060,92 @@ unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
 // LCOV_EXCL_START
 #[cfg(test)]
 mod t {
+    use futures::executor::spawn;
+    use std::thread;
     use super::*;
 
+    /// Poison the `Mutex`'s internal bookkeeping
+    fn poison<T: ?Sized + Send + 'static>(m: &Mutex<T>) {
+        let m = m.clone();
+        thread::spawn(move || {
+            let _data = m.inner.mutex.lock().unwrap();
+            panic!("Poison!");
+        }).join().unwrap_err();
+    }
+
     /// Pet Kcov
     #[test]
     fn debug() {
         let m = Mutex::<u32>::new(0);
-        format!("{:?}", &m);
+        let _ = format!("{:?}", &m);
+    }
+
+    #[test]
+    #[should_panic(expected = "sync::Mutex::lock")]
+    fn poison_policy_panic() {
+        let m = Mutex::<u32>::new(0);
+        poison(&m);
+        let _ = spawn(m.lock()).wait_future();
+    }
+
+    #[test]
+    fn poison_policy_error() {
+        let m = Mutex::<u32>::new(0);
+        m.set_poison_policy(PoisonPolicy::Error);
+        poison(&m);
+        assert_eq!(spawn(m.lock()).wait_future().err(),
+                   Some(AcquireError::Poisoned));
+        assert!(m.try_lock_unperturbed().is_err());
+    }
+
+    #[test]
+    fn poison_policy_ignore() {
+        let m = Mutex::<u32>::new(0);
+        m.set_poison_policy(PoisonPolicy::Ignore);
+        poison(&m);
+        let guard = spawn(m.lock()).wait_future().unwrap();
+        assert!(m.try_lock_unperturbed().is_err());
+        drop(guard);
+        assert_eq!(*m.try_lock_unperturbed().unwrap(), 0);
+    }
+
+    // Writes through the guard should reach the protected writer
+    #[test]
+    fn test_io_write() {
+        use std::io::Write;
+
+        let m = Mutex::<Vec<u8>>::new(Vec::new());
+        {
+            let mut guard = m.try_lock_unperturbed().unwrap();
+            let w: &mut dyn Write = &mut guard;
+            w.write_all(b"abc").unwrap();
+            w.flush().unwrap();
+        }
+        assert_eq!(&m.try_unwrap().unwrap()[..], b"abc");
+    }
+
+    #[test]
+    fn test_eq() {
+        let m = Mutex::<u32>::new(0);
+        assert_eq!(m, m.clone());
+        assert_ne!(m, Mutex::<u32>::new(0));
+    }
+
+    #[test]
+    fn test_display() {
+        let m = Mutex::<f32>::new(1.5);
+        assert_eq!(format!("{:.3}", m), "1.500");
+        let _guard = m.try_lock_unperturbed().unwrap();
+        assert_eq!(format!("{:.3}", m), "<locked>");
+    }
+
+    // Formatting shouldn't count as acquiring or releasing the Mutex
+    #[test]
+    fn test_display_peeks() {
+        let m = Mutex::<u32>::new(42);
+        let generation = m.current_generation();
+        assert_eq!(format!("{}", m), "42");
+        assert_eq!(m.current_generation(), generation);
     }
 
     #[test]
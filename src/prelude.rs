@@ -0,0 +1,39 @@
+// vim: tw=80
+
+//! Everything needed for typical use of this crate, in one import.
+//!
+//! This includes the lock types, their guards, the error types, and the
+//! `Future` trait, whose combinators are the usual way to consume a lock's
+//! acquisition.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate futures_locks;
+//! use futures_locks::prelude::*;
+//! # #[cfg(not(feature = "mutex"))] fn main() {}
+//! # #[cfg(feature = "mutex")]
+//! # fn main() {
+//! let mtx = Mutex::<u32>::new(0);
+//! let fut = mtx.lock().map(|mut guard| { *guard += 1; });
+//! fut.wait().unwrap();
+//! assert_eq!(mtx.try_unwrap().unwrap(), 1);
+//! # }
+//! ```
+
+pub use futures::Future;
+
+pub use super::{AcquireError, Fairness, PoisonPolicy, TryLockError,
+                TryUnwrapError};
+#[cfg(feature = "mutex")]
+pub use super::{LocalMutex, LocalMutexGuard, MappedMutex, MappedMutexGuard,
+                Mutex, MutexChunkGuard, MutexGuard, MutexWeak, ReadOnlyGuard,
+                ReentrantMutex, ReentrantMutexGuard, ScopedMutex,
+                ScopedMutexGuard, TransactionGuard, TransactionMode};
+#[cfg(feature = "rwlock")]
+pub use super::{LocalRwLock, LocalRwLockReadGuard, LocalRwLockWriteGuard,
+                MappedRwLockReadGuard, MappedRwLockWriteGuard, Preference,
+                RwLock, RwLockCowGuard, RwLockReadGuard,
+                RwLockUpgradableReadGuard, RwLockWeak, RwLockWriteGuard};
+#[cfg(feature = "derive")]
+pub use super::AsyncLocked;
@@ -0,0 +1,179 @@
+// vim: tw=80
+
+//! A `Mutex` that its owning task may lock again without deadlocking.
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+use mutex::{Mutex, MutexFut, MutexGuard};
+use std::ops::Deref;
+use std::sync;
+use std::sync::Arc;
+use super::AcquireError;
+
+/// Who owns a `ReentrantMutex`, and how many times they've locked it.
+#[derive(Default)]
+struct Owner {
+    task: Option<Task>,
+    depth: usize,
+    /// Keeps the underlying `Mutex` locked until the outermost guard is gone
+    guard: Option<MutexGuard<()>>,
+}
+
+struct Inner<T: ?Sized> {
+    mutex: Mutex<()>,
+    owner: sync::Mutex<Owner>,
+    data: T,
+}
+
+/// A `Futures`-aware mutex that may be locked again by the task that already
+/// holds it, like `parking_lot::ReentrantMutex`.  The lock is only released
+/// when the outermost guard is dropped.
+///
+/// Since one task may hold several guards at once, guards only give shared
+/// access to the protected data.  Use a `Cell`-like type, such as an atomic,
+/// for data that must change.
+///
+/// Ownership is tracked per task, so acquisitions must happen from within a
+/// task.  Other tasks wait in line, as for a [`Mutex`](struct.Mutex.html).
+///
+/// # Examples
+/// ```
+/// # extern crate futures;
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use futures::Future;
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # fn main() {
+/// let mtx = ReentrantMutex::new(AtomicUsize::new(0));
+/// let fut = mtx.lock().and_then(|outer| {
+///     // Would deadlock with a plain Mutex
+///     mtx.lock().map(move |inner| {
+///         inner.fetch_add(1, Ordering::Relaxed);
+///         outer.fetch_add(1, Ordering::Relaxed);
+///     })
+/// });
+/// fut.wait().unwrap();
+/// assert_eq!(mtx.lock().wait().unwrap().load(Ordering::Relaxed), 2);
+/// # }
+/// ```
+pub struct ReentrantMutex<T: ?Sized> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: ?Sized> Clone for ReentrantMutex<T> {
+    fn clone(&self) -> ReentrantMutex<T> {
+        ReentrantMutex{inner: self.inner.clone()}
+    }
+}
+
+impl<T: Default> Default for ReentrantMutex<T> {
+    fn default() -> Self {
+        ReentrantMutex::new(T::default())
+    }
+}
+
+impl<T> ReentrantMutex<T> {
+    /// Create a new `ReentrantMutex` in the unlocked state.
+    pub fn new(t: T) -> ReentrantMutex<T> {
+        let inner = Inner {
+            mutex: Mutex::new(()),
+            owner: sync::Mutex::new(Owner::default()),
+            data: t,
+        };
+        ReentrantMutex{inner: Arc::new(inner)}
+    }
+}
+
+impl<T: ?Sized> ReentrantMutex<T> {
+    /// Acquires the `ReentrantMutex`, returning a new `Future`.  The `Future`
+    /// completes immediately if the current task already holds the lock.
+    /// Otherwise, it waits like [`Mutex::lock`].
+    ///
+    /// The returned `Future` must be polled from within a task.
+    ///
+    /// [`Mutex::lock`]: struct.Mutex.html#method.lock
+    pub fn lock(&self) -> ReentrantMutexFut<T> {
+        ReentrantMutexFut{mutex: self.clone(), fut: None}
+    }
+
+    /// Returns `true` if the two `ReentrantMutex`es point to the same data.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.inner, &other.inner)
+    }
+
+    fn owner(&self) -> sync::MutexGuard<'_, Owner> {
+        // Owner's invariants hold even if a panic interrupted its last user
+        self.inner.owner.lock().unwrap_or_else(sync::PoisonError::into_inner)
+    }
+}
+
+/// A `Future` representing a pending `ReentrantMutex` acquisition.
+pub struct ReentrantMutexFut<T: ?Sized> {
+    mutex: ReentrantMutex<T>,
+    /// The pending acquisition of the underlying `Mutex`, if the current task
+    /// didn't already own it
+    fut: Option<MutexFut<()>>,
+}
+
+impl<T: ?Sized> Future for ReentrantMutexFut<T> {
+    type Item = ReentrantMutexGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.fut.is_none() {
+            let mut owner = self.mutex.owner();
+            let reentered = match owner.task {
+                Some(ref t) => t.will_notify_current(),
+                None => false
+            };
+            if reentered {
+                owner.depth += 1;
+                let mutex = self.mutex.clone();
+                return Ok(Async::Ready(ReentrantMutexGuard{mutex}));
+            }
+            drop(owner);
+            self.fut = Some(self.mutex.inner.mutex.lock());
+        }
+        let guard = match self.fut.as_mut().unwrap().poll()? {
+            Async::Ready(guard) => guard,
+            Async::NotReady => return Ok(Async::NotReady)
+        };
+        self.fut = None;
+        let mut owner = self.mutex.owner();
+        owner.task = Some(task::current());
+        owner.depth = 1;
+        owner.guard = Some(guard);
+        let mutex = self.mutex.clone();
+        Ok(Async::Ready(ReentrantMutexGuard{mutex}))
+    }
+}
+
+/// An RAII guard for a [`ReentrantMutex`](struct.ReentrantMutex.html).  The
+/// protected data can be read via its `Deref` implementation.  The lock is
+/// released once every guard held by its owning task has been dropped.
+pub struct ReentrantMutexGuard<T: ?Sized> {
+    mutex: ReentrantMutex<T>,
+}
+
+impl<T: ?Sized> Deref for ReentrantMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.mutex.inner.data
+    }
+}
+
+impl<T: ?Sized> Drop for ReentrantMutexGuard<T> {
+    fn drop(&mut self) {
+        let mut owner = self.mutex.owner();
+        owner.depth -= 1;
+        if owner.depth == 0 {
+            owner.task = None;
+            let guard = owner.guard.take();
+            // Release the underlying Mutex only after giving up ownership, so
+            // its next owner finds the Owner unlocked.
+            drop(owner);
+            drop(guard);
+        }
+    }
+}
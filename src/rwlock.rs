@@ -1,21 +1,37 @@
 // vim: tw=80
 
-use super::FutState;
-#[cfg(feature = "tokio")]
+#[cfg(feature = "stats")]
+use super::LockStats;
+use super::{
+    lock_state, lock_state_infallible, poll_detached, AcquireError,
+    CancellableFut, Fairness, FutState, LockConfig, LockInfo, LockState,
+    PoisonPolicy, Preference, Streak, TryLockError, TryUnwrapError, Validator,
+};
 use futures::future;
 #[cfg(feature = "tokio")]
 use futures::future::IntoFuture;
 use futures::sync::oneshot;
 use futures::{Async, Future, Poll};
+use std::borrow::Cow;
 use std::cell::UnsafeCell;
 use std::clone::Clone;
 use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::ptr;
 use std::sync;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 #[cfg(feature = "tokio")]
 use tokio_current_thread as current_thread;
 #[cfg(feature = "tokio")]
 use tokio_executor::{self, Executor, SpawnError};
+#[cfg(feature = "timer")]
+use tokio_timer::Delay;
 
 /// An RAII guard, much like `std::sync::RwLockReadGuard`.  The wrapped data can
 /// be accessed via its `Deref` implementation.
@@ -31,12 +47,223 @@ impl<T: ?Sized> Deref for RwLockReadGuard<T> {
     }
 }
 
+impl<T: ?Sized> RwLockReadGuard<T> {
+    /// Returns the `RwLock`'s version.  It can't change while any read guard
+    /// is alive.  See [`RwLock::version`].
+    ///
+    /// [`RwLock::version`]: struct.RwLock.html#method.version
+    pub fn version(this: &Self) -> u64 {
+        this.rwlock.version()
+    }
+
+    /// Narrows the guard to a part of the protected data, like
+    /// `parking_lot::RwLockReadGuard::map`.  The `RwLock` stays read-locked
+    /// until the returned guard is dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// struct State { count: u32, name: String }
+    ///
+    /// let rwlock = RwLock::new(State{count: 0, name: "foo".to_owned()});
+    /// let guard = rwlock.try_read().unwrap();
+    /// let name = RwLockReadGuard::map(guard, |s| &s.name);
+    /// assert_eq!(&*name, "foo");
+    /// assert!(rwlock.try_write().is_err());
+    /// drop(name);
+    /// assert!(rwlock.try_write().is_ok());
+    /// # }
+    /// ```
+    pub fn map<U, F>(this: Self, f: F) -> MappedRwLockReadGuard<T, U>
+    where
+        F: FnOnce(&T) -> &U,
+        U: ?Sized,
+    {
+        let data = f(&*this) as *const U;
+        MappedRwLockReadGuard {
+            rwlock: RwLockReadGuard::into_rwlock(this),
+            data,
+        }
+    }
+
+    /// Like [`map`](#method.map), but `f` may decline to narrow the guard, in
+    /// which case the original guard is returned.  Either way, the `RwLock`
+    /// stays read-locked.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<Option<u32>>::new(None);
+    /// let guard = rwlock.try_read().unwrap();
+    /// let guard = RwLockReadGuard::try_map(guard, Option::as_ref)
+    ///     .err().unwrap();
+    /// assert!(guard.is_none());
+    /// # }
+    /// ```
+    pub fn try_map<U, F>(
+        this: Self,
+        f: F,
+    ) -> Result<MappedRwLockReadGuard<T, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+        U: ?Sized,
+    {
+        let data = match f(&*this) {
+            Some(data) => data as *const U,
+            None => return Err(this),
+        };
+        Ok(MappedRwLockReadGuard {
+            rwlock: RwLockReadGuard::into_rwlock(this),
+            data,
+        })
+    }
+
+    /// Consume the guard without releasing the `RwLock`, returning the
+    /// `RwLock`.
+    fn into_rwlock(this: Self) -> RwLock<T> {
+        // Safe because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        rwlock
+    }
+}
+
+impl<T: Clone> RwLockReadGuard<T> {
+    /// Copies the protected data into a new `Arc`, so it can outlive the
+    /// guard.  See also [`RwLock::snapshot`].
+    ///
+    /// This is an associated function, rather than a method, so it won't
+    /// shadow a method of `T`.
+    ///
+    /// [`RwLock::snapshot`]: struct.RwLock.html#method.snapshot
+    pub fn to_arc(this: &Self) -> sync::Arc<T> {
+        sync::Arc::new((**this).clone())
+    }
+}
+
+/// Formats the protected data, like `std::sync::RwLockReadGuard`.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Formats the protected data, like `std::sync::RwLockReadGuard`.
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLockReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
 impl<T: ?Sized> Drop for RwLockReadGuard<T> {
     fn drop(&mut self) {
         self.rwlock.unlock_reader();
     }
 }
 
+/// An RAII guard for a part of an `RwLock`'s protected data, created by
+/// [`RwLockReadGuard::map`].  The `RwLock`'s read lock is released when the
+/// guard is dropped.
+///
+/// [`RwLockReadGuard::map`]: struct.RwLockReadGuard.html#method.map
+pub struct MappedRwLockReadGuard<T: ?Sized, U: ?Sized> {
+    rwlock: RwLock<T>,
+    data: *const U,
+}
+
+impl<T: ?Sized, U: ?Sized> Drop for MappedRwLockReadGuard<T, U> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_reader();
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> MappedRwLockReadGuard<T, U> {
+    /// Narrows the guard further, like [`RwLockReadGuard::map`].
+    ///
+    /// [`RwLockReadGuard::map`]: struct.RwLockReadGuard.html#method.map
+    pub fn map<V, F>(this: Self, f: F) -> MappedRwLockReadGuard<T, V>
+    where
+        F: FnOnce(&U) -> &V,
+        V: ?Sized,
+    {
+        let data = f(unsafe { &*this.data }) as *const V;
+        MappedRwLockReadGuard {
+            rwlock: MappedRwLockReadGuard::into_rwlock(this),
+            data,
+        }
+    }
+
+    /// Narrows the guard further, if `f` returns `Some`, like
+    /// [`RwLockReadGuard::try_map`].  Otherwise, returns the original guard.
+    ///
+    /// [`RwLockReadGuard::try_map`]: struct.RwLockReadGuard.html#method.try_map
+    pub fn try_map<V, F>(
+        this: Self,
+        f: F,
+    ) -> Result<MappedRwLockReadGuard<T, V>, Self>
+    where
+        F: FnOnce(&U) -> Option<&V>,
+        V: ?Sized,
+    {
+        let data = match f(unsafe { &*this.data }) {
+            Some(data) => data as *const V,
+            None => return Err(this),
+        };
+        Ok(MappedRwLockReadGuard {
+            rwlock: MappedRwLockReadGuard::into_rwlock(this),
+            data,
+        })
+    }
+
+    /// Consume the guard without releasing the `RwLock`, returning the
+    /// `RwLock`.
+    fn into_rwlock(this: Self) -> RwLock<T> {
+        // Safe because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        rwlock
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Deref for MappedRwLockReadGuard<T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { &*self.data }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug
+    for MappedRwLockReadGuard<T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + fmt::Display> fmt::Display
+    for MappedRwLockReadGuard<T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+unsafe impl<T: ?Sized + Send, U: ?Sized + Sync> Send
+    for MappedRwLockReadGuard<T, U>
+{
+}
+unsafe impl<T: ?Sized + Send, U: ?Sized + Sync> Sync
+    for MappedRwLockReadGuard<T, U>
+{
+}
+
 /// An RAII guard, much like `std::sync::RwLockWriteGuard`.  The wrapped data
 /// can be accessed via its `Deref`  and `DerefMut` implementations.
 pub struct RwLockWriteGuard<T: ?Sized> {
@@ -51,445 +278,2668 @@ impl<T: ?Sized> Deref for RwLockWriteGuard<T> {
     }
 }
 
+impl<T: ?Sized> RwLockWriteGuard<T> {
+    /// Returns the `RwLock`'s version from before this guard was acquired.  It
+    /// will be incremented when the guard is released.  See
+    /// [`RwLock::version`].
+    ///
+    /// [`RwLock::version`]: struct.RwLock.html#method.version
+    pub fn version(this: &Self) -> u64 {
+        this.rwlock.version()
+    }
+
+    /// Atomically converts the guard into a read guard, without letting any
+    /// writer in between.  Any waiting readers are admitted too.  Counts as a
+    /// release for the purpose of [`RwLock::version`].
+    ///
+    /// [`RwLock::version`]: struct.RwLock.html#method.version
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let cache = RwLock::<Vec<u32>>::new(Vec::new());
+    /// let mut guard = cache.write().wait().unwrap();
+    /// guard.push(42);
+    /// let guard = RwLockWriteGuard::downgrade(guard);
+    /// assert_eq!(*cache.try_read().unwrap(), vec![42]);
+    /// assert!(cache.try_write().is_err());
+    /// # drop(guard);
+    /// # }
+    /// ```
+    pub fn downgrade(this: Self) -> RwLockReadGuard<T> {
+        let valid = this
+            .rwlock
+            .inner
+            .validator
+            .check(this.rwlock.inner.data.get());
+        // Safe because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        {
+            let mut lock_data = rwlock.lock_state_infallible();
+            assert!(lock_data.exclusive);
+            lock_data.version = lock_data.version.wrapping_add(1);
+            lock_data.exclusive = false;
+            lock_data.num_readers = 1;
+            lock_data.grant_readers(&rwlock.inner.config);
+        }
+        let guard = RwLockReadGuard { rwlock };
+        if !valid {
+            panic!("RwLock invariant violated on release");
+        }
+        guard
+    }
+
+    /// Narrows the guard to a part of the protected data, like
+    /// `parking_lot::RwLockWriteGuard::map`.  The `RwLock` stays
+    /// write-locked until the returned guard is dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// struct State { count: u32, name: String }
+    ///
+    /// fn bump(count: &mut u32) { *count += 1; }
+    ///
+    /// let rwlock = RwLock::new(State{count: 0, name: "foo".to_owned()});
+    /// let guard = rwlock.try_write().unwrap();
+    /// let mut count = RwLockWriteGuard::map(guard, |s| &mut s.count);
+    /// bump(&mut count);
+    /// assert!(rwlock.try_read().is_err());
+    /// drop(count);
+    /// assert_eq!(rwlock.try_read().unwrap().count, 1);
+    /// # }
+    /// ```
+    pub fn map<U, F>(mut this: Self, f: F) -> MappedRwLockWriteGuard<T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+        U: ?Sized,
+    {
+        let data = f(&mut *this) as *mut U;
+        MappedRwLockWriteGuard {
+            rwlock: RwLockWriteGuard::into_rwlock(this),
+            data,
+        }
+    }
+
+    /// Like [`map`](#method.map), but `f` may decline to narrow the guard, in
+    /// which case the original guard is returned.  Either way, the `RwLock`
+    /// stays write-locked.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<Option<u32>>::new(None);
+    /// let guard = rwlock.try_write().unwrap();
+    /// let mut guard = RwLockWriteGuard::try_map(guard, Option::as_mut)
+    ///     .err().unwrap();
+    /// *guard = Some(1);
+    /// let inner = RwLockWriteGuard::try_map(guard, Option::as_mut)
+    ///     .ok().unwrap();
+    /// assert_eq!(*inner, 1);
+    /// # }
+    /// ```
+    pub fn try_map<U, F>(
+        mut this: Self,
+        f: F,
+    ) -> Result<MappedRwLockWriteGuard<T, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+        U: ?Sized,
+    {
+        let data = match f(&mut *this) {
+            Some(data) => data as *mut U,
+            None => return Err(this),
+        };
+        Ok(MappedRwLockWriteGuard {
+            rwlock: RwLockWriteGuard::into_rwlock(this),
+            data,
+        })
+    }
+
+    /// Consume the guard without releasing the `RwLock`, returning the
+    /// `RwLock`.
+    fn into_rwlock(this: Self) -> RwLock<T> {
+        // Safe because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        rwlock
+    }
+}
+
 impl<T: ?Sized> DerefMut for RwLockWriteGuard<T> {
     fn deref_mut(&mut self) -> &mut T {
         unsafe { &mut *self.rwlock.inner.data.get() }
     }
 }
 
+/// Formats the protected data, like `std::sync::RwLockWriteGuard`.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Formats the protected data, like `std::sync::RwLockWriteGuard`.
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLockWriteGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
 impl<T: ?Sized> Drop for RwLockWriteGuard<T> {
     fn drop(&mut self) {
-        self.rwlock.unlock_writer();
+        self.rwlock.release_writer();
     }
 }
 
-/// A `Future` representing a pending `RwLock` shared acquisition.
-pub struct RwLockReadFut<T: ?Sized> {
-    state: FutState,
+/// An RAII guard for a part of an `RwLock`'s protected data, created by
+/// [`RwLockWriteGuard::map`].  The `RwLock`'s write lock is released when the
+/// guard is dropped.
+///
+/// [`RwLockWriteGuard::map`]: struct.RwLockWriteGuard.html#method.map
+pub struct MappedRwLockWriteGuard<T: ?Sized, U: ?Sized> {
     rwlock: RwLock<T>,
+    data: *mut U,
 }
 
-impl<T: ?Sized> RwLockReadFut<T> {
-    fn new(state: FutState, rwlock: RwLock<T>) -> Self {
-        RwLockReadFut { state, rwlock }
+impl<T: ?Sized, U: ?Sized> Drop for MappedRwLockWriteGuard<T, U> {
+    fn drop(&mut self) {
+        self.rwlock.release_writer();
     }
 }
 
-impl<T: ?Sized> Drop for RwLockReadFut<T> {
-    fn drop(&mut self) {
-        match &mut self.state {
-            &mut FutState::New => {
-                // RwLock hasn't yet been modified; nothing to do
-            }
-            &mut FutState::Pending(ref mut rx) => {
-                rx.close();
-                match rx.try_recv() {
-                    Ok(Some(())) => {
-                        // This future received ownership of the lock, but got
-                        // dropped before it was ever polled.  Release the
-                        // lock.
-                        self.rwlock.unlock_reader()
-                    }
-                    Ok(None) => {
-                        // Dropping the Future before it acquires the lock is
-                        // equivalent to cancelling it.
-                    }
-                    Err(oneshot::Canceled) => {
-                        // Never received ownership of the lock
-                    }
-                }
-            }
-            &mut FutState::Acquired => {
-                // The RwLockReadGuard will take care of releasing the RwLock
-            }
+impl<T: ?Sized, U: ?Sized> MappedRwLockWriteGuard<T, U> {
+    /// Narrows the guard further, like [`RwLockWriteGuard::map`].
+    ///
+    /// [`RwLockWriteGuard::map`]: struct.RwLockWriteGuard.html#method.map
+    pub fn map<V, F>(this: Self, f: F) -> MappedRwLockWriteGuard<T, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+        V: ?Sized,
+    {
+        let data = f(unsafe { &mut *this.data }) as *mut V;
+        MappedRwLockWriteGuard {
+            rwlock: MappedRwLockWriteGuard::into_rwlock(this),
+            data,
         }
     }
+
+    /// Narrows the guard further, if `f` returns `Some`, like
+    /// [`RwLockWriteGuard::try_map`].  Otherwise, returns the original guard.
+    ///
+    /// [`RwLockWriteGuard::try_map`]: struct.RwLockWriteGuard.html#method.try_map
+    pub fn try_map<V, F>(
+        this: Self,
+        f: F,
+    ) -> Result<MappedRwLockWriteGuard<T, V>, Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut V>,
+        V: ?Sized,
+    {
+        let data = match f(unsafe { &mut *this.data }) {
+            Some(data) => data as *mut V,
+            None => return Err(this),
+        };
+        Ok(MappedRwLockWriteGuard {
+            rwlock: MappedRwLockWriteGuard::into_rwlock(this),
+            data,
+        })
+    }
+
+    /// Consume the guard without releasing the `RwLock`, returning the
+    /// `RwLock`.
+    fn into_rwlock(this: Self) -> RwLock<T> {
+        // Safe because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        rwlock
+    }
 }
 
-impl<T: ?Sized> Future for RwLockReadFut<T> {
-    type Item = RwLockReadGuard<T>;
-    type Error = ();
+impl<T: ?Sized, U: ?Sized> Deref for MappedRwLockWriteGuard<T, U> {
+    type Target = U;
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let (result, new_state) = match &mut self.state {
-            &mut FutState::New => {
-                let mut lock_data = self.rwlock.inner.mutex.lock().expect("sync::Mutex::lock");
-                if lock_data.exclusive {
-                    let (tx, mut rx) = oneshot::channel::<()>();
-                    lock_data.read_waiters.push_back(tx);
-                    // Even though we know it isn't ready, we need to poll the
-                    // receiver in order to register our task for notification.
-                    assert!(rx.poll().unwrap().is_not_ready());
-                    (Ok(Async::NotReady), FutState::Pending(rx))
-                } else {
-                    lock_data.num_readers += 1;
-                    let guard = RwLockReadGuard {
-                        rwlock: self.rwlock.clone(),
-                    };
-                    (Ok(Async::Ready(guard)), FutState::Acquired)
-                }
-            }
-            &mut FutState::Pending(ref mut rx) => {
-                match rx.poll() {
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    // It's impossible for receiver.poll() to return an error.
-                    // The only way that would happen is if the sender got
-                    // dropped.  But that can't happen because the RwLock owns
-                    // the sender, and the Fut retains a clone of the RwLock.
-                    Err(_) => unreachable!(),
-                    Ok(Async::Ready(_)) => {
-                        let state = FutState::Acquired;
-                        let result = Ok(Async::Ready(RwLockReadGuard {
-                            rwlock: self.rwlock.clone(),
-                        }));
-                        (result, state)
-                    } // LCOV_EXCL_LINE   kcov false negative
-                }
-            }
-            &mut FutState::Acquired => panic!("Double-poll of ready Future"),
-        };
-        self.state = new_state;
-        result
+    fn deref(&self) -> &U {
+        unsafe { &*self.data }
     }
 }
 
-/// A `Future` representing a pending `RwLock` exclusive acquisition.
-pub struct RwLockWriteFut<T: ?Sized> {
-    state: FutState,
-    rwlock: RwLock<T>,
+impl<T: ?Sized, U: ?Sized> DerefMut for MappedRwLockWriteGuard<T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.data }
+    }
 }
 
-impl<T: ?Sized> RwLockWriteFut<T> {
-    fn new(state: FutState, rwlock: RwLock<T>) -> Self {
-        RwLockWriteFut { state, rwlock }
+impl<T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug
+    for MappedRwLockWriteGuard<T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
     }
 }
 
-impl<T: ?Sized> Drop for RwLockWriteFut<T> {
-    fn drop(&mut self) {
-        match &mut self.state {
-            &mut FutState::New => {
-                // RwLock hasn't yet been modified; nothing to do
-            }
-            &mut FutState::Pending(ref mut rx) => {
-                rx.close();
-                match rx.try_recv() {
-                    Ok(Some(())) => {
-                        // This future received ownership of the lock, but got
-                        // dropped before it was ever polled.  Release the
-                        // lock.
-                        self.rwlock.unlock_writer()
-                    }
-                    Ok(None) => {
-                        // Dropping the Future before it acquires the lock is
-                        // equivalent to cancelling it.
-                    }
-                    Err(oneshot::Canceled) => {
-                        // Never received ownership of the lock
-                    }
-                }
-            }
-            &mut FutState::Acquired => {
-                // The RwLockWriteGuard will take care of releasing the RwLock
-            }
-        }
+impl<T: ?Sized, U: ?Sized + fmt::Display> fmt::Display
+    for MappedRwLockWriteGuard<T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
     }
 }
 
-impl<T: ?Sized> Future for RwLockWriteFut<T> {
-    type Item = RwLockWriteGuard<T>;
-    type Error = ();
+unsafe impl<T: ?Sized + Send, U: ?Sized + Send> Send
+    for MappedRwLockWriteGuard<T, U>
+{
+}
+unsafe impl<T: ?Sized + Send, U: ?Sized + Sync> Sync
+    for MappedRwLockWriteGuard<T, U>
+{
+}
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let (result, new_state) = match &mut self.state {
-            &mut FutState::New => {
-                let mut lock_data = self.rwlock.inner.mutex.lock().expect("sync::Mutex::lock");
-                if lock_data.exclusive || lock_data.num_readers > 0 {
-                    let (tx, mut rx) = oneshot::channel::<()>();
-                    lock_data.write_waiters.push_back(tx);
-                    // Even though we know it isn't ready, we need to poll the
-                    // receiver in order to register our task for notification.
-                    assert!(rx.poll().unwrap().is_not_ready());
-                    (Ok(Async::NotReady), FutState::Pending(rx))
-                } else {
-                    lock_data.exclusive = true;
-                    let guard = RwLockWriteGuard {
-                        rwlock: self.rwlock.clone(),
-                    };
-                    (Ok(Async::Ready(guard)), FutState::Acquired)
-                }
-            }
-            &mut FutState::Pending(ref mut rx) => {
-                match rx.poll() {
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    // It's impossible for receiver.poll() to return an error.
-                    // The only way that would happen is if the sender got
+/// An RAII guard for shared access that may later be upgraded to exclusive
+/// access, created by [`RwLock::upgradable_read`].  It coexists with ordinary
+/// read guards, but not with writers or other upgradable guards.  The wrapped
+/// data can be accessed via its `Deref` implementation.
+///
+/// [`RwLock::upgradable_read`]: struct.RwLock.html#method.upgradable_read
+pub struct RwLockUpgradableReadGuard<T: ?Sized> {
+    rwlock: RwLock<T>,
+}
+
+impl<T: ?Sized> RwLockUpgradableReadGuard<T> {
+    /// Upgrades the guard to exclusive access, once every other reader has
+    /// released the `RwLock`.  No writer can acquire the `RwLock` in the
+    /// meantime, so the data can't change between the read and the write.
+    /// While the upgrade is pending, new readers must wait.
+    ///
+    /// This is an associated function, rather than a method, so it won't
+    /// shadow a method of `T`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::<Option<u32>>::new(None);
+    /// let guard = rwlock.upgradable_read().wait().unwrap();
+    /// if guard.is_none() {
+    ///     let mut guard =
+    ///         RwLockUpgradableReadGuard::upgrade(guard).wait().unwrap();
+    ///     *guard = Some(42);
+    /// }
+    /// assert_eq!(*rwlock.try_read().unwrap(), Some(42));
+    /// # }
+    /// ```
+    pub fn upgrade(this: Self) -> RwLockUpgradeFut<T> {
+        // The guard's share of the RwLock now belongs to the future.  Safe
+        // because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        let mut lock_data = rwlock.lock_state_infallible();
+        debug_assert!(lock_data.upgradable);
+        let state = if lock_data.num_readers == 1 {
+            lock_data.upgrade();
+            FutState::Granted
+        } else {
+            let (tx, mut rx) = oneshot::channel::<()>();
+            lock_data.upgrader = Some(tx);
+            // Even though we know it isn't ready, we need to poll the
+            // receiver in order to register our task for notification.
+            assert!(rx.poll().unwrap().is_not_ready());
+            FutState::Pending(rx)
+        };
+        drop(lock_data);
+        RwLockUpgradeFut { state, rwlock }
+    }
+
+    /// Upgrades the guard to exclusive access, if no other readers hold the
+    /// `RwLock`.  Otherwise, returns the guard unchanged.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// let guard = rwlock.try_upgradable_read().unwrap();
+    /// let reader = rwlock.try_read().unwrap();
+    /// let guard = RwLockUpgradableReadGuard::try_upgrade(guard).unwrap_err();
+    /// drop(reader);
+    /// let mut guard = RwLockUpgradableReadGuard::try_upgrade(guard).unwrap();
+    /// *guard += 1;
+    /// # }
+    /// ```
+    pub fn try_upgrade(this: Self) -> Result<RwLockWriteGuard<T>, Self> {
+        let upgraded = {
+            let mut lock_data = this.rwlock.lock_state_infallible();
+            let alone = lock_data.num_readers == 1;
+            if alone {
+                lock_data.upgrade();
+            }
+            alone
+        };
+        if !upgraded {
+            return Err(this);
+        }
+        // Safe because the guard is forgotten, so its RwLock won't be dropped
+        // twice.
+        let rwlock = unsafe { ptr::read(&this.rwlock) };
+        mem::forget(this);
+        Ok(RwLockWriteGuard { rwlock })
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockUpgradableReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.rwlock.inner.data.get() }
+    }
+}
+
+/// Formats the protected data.
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockUpgradableReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Formats the protected data.
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLockUpgradableReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockUpgradableReadGuard<T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_upgradable();
+    }
+}
+
+enum CowState<T> {
+    /// Shared access, which keeps writers out
+    Upgradable(RwLockUpgradableReadGuard<T>),
+    /// Exclusive access, which keeps readers out too
+    Exclusive(RwLockWriteGuard<T>),
+    /// Only seen while switching between the other states
+    Upgrading,
+}
+
+/// A clone-on-write guard, created by [`RwLock::read_cow`].  It dereferences
+/// to the `RwLock`'s shared data until it's first mutably dereferenced.  At
+/// that point it clones the data, and from then on dereferences to the
+/// private copy.  It also tries to upgrade to exclusive access then, and
+/// otherwise upgrades when it's committed.  Either way, no writer can change
+/// the data for as long as the guard lives, so committing never overwrites
+/// anybody else's changes.
+///
+/// [`RwLock::read_cow`]: struct.RwLock.html#method.read_cow
+pub struct RwLockCowGuard<T: Clone> {
+    state: CowState<T>,
+    copy: Option<T>,
+}
+
+impl<T: Clone> RwLockCowGuard<T> {
+    /// Returns true if the guard has cloned the data.
+    pub fn is_owned(this: &Self) -> bool {
+        this.copy.is_some()
+    }
+
+    /// Publishes the changes, if any.  If the guard has cloned the data, this
+    /// waits for exclusive access, if it doesn't have it already, and
+    /// overwrites the data with the copy.  Otherwise, it simply releases the
+    /// `RwLock`.  Dropping the guard instead discards the changes.
+    pub fn commit(this: Self) -> impl Future<Item = (), Error = AcquireError> {
+        match (this.state, this.copy) {
+            (CowState::Exclusive(mut guard), Some(data)) => {
+                *guard = data;
+                future::Either::B(future::ok(()))
+            }
+            (CowState::Upgradable(guard), Some(data)) => {
+                let fut = RwLockUpgradableReadGuard::upgrade(guard);
+                future::Either::A(fut.map(move |mut guard| {
+                    *guard = data;
+                }))
+            }
+            _ => future::Either::B(future::ok(())),
+        }
+    }
+}
+
+impl<T: Clone> Deref for RwLockCowGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        if let Some(ref data) = self.copy {
+            return data;
+        }
+        match self.state {
+            CowState::Upgradable(ref guard) => guard,
+            CowState::Exclusive(ref guard) => guard,
+            CowState::Upgrading => unreachable!(),
+        }
+    }
+}
+
+impl<T: Clone> DerefMut for RwLockCowGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        if self.copy.is_none() {
+            let data = (**self).clone();
+            self.copy = Some(data);
+            let state = mem::replace(&mut self.state, CowState::Upgrading);
+            self.state = match state {
+                CowState::Upgradable(guard) => {
+                    match RwLockUpgradableReadGuard::try_upgrade(guard) {
+                        Ok(guard) => CowState::Exclusive(guard),
+                        Err(guard) => CowState::Upgradable(guard),
+                    }
+                }
+                state => state,
+            };
+        }
+        self.copy.as_mut().unwrap()
+    }
+}
+
+/// A `Future` representing a pending `RwLock` shared acquisition.
+pub struct RwLockReadFut<T: ?Sized> {
+    state: FutState,
+    rwlock: RwLock<T>,
+    /// Identifies this future's entry in the reader queue, once it's queued
+    ticket: u64,
+    /// Set by `RwLock::read_checked`, which returns the guard of a poisoned
+    /// `RwLock` instead of failing
+    checked: bool,
+    /// When this acquisition started waiting
+    #[cfg(feature = "metrics-histogram")]
+    since: Option<Instant>,
+}
+
+impl<T: ?Sized> RwLockReadFut<T> {
+    fn new(state: FutState, rwlock: RwLock<T>) -> Self {
+        RwLockReadFut {
+            state,
+            rwlock,
+            ticket: 0,
+            checked: false,
+            #[cfg(feature = "metrics-histogram")]
+            since: None,
+        }
+    }
+
+    /// Polls the `Future` once, without needing an executor.  See
+    /// [`MutexFut::poll_once`](struct.MutexFut.html#method.poll_once).
+    pub fn poll_once(&mut self) -> Poll<RwLockReadGuard<T>, AcquireError> {
+        poll_detached(self)
+    }
+
+    /// Acquires the `RwLock` if that's possible without waiting.  Otherwise,
+    /// gives up and returns `None`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let lock = RwLock::<u32>::new(0);
+    /// let guard = lock.read().now_or_never().unwrap().unwrap();
+    /// assert!(lock.write().now_or_never().unwrap().is_none());
+    /// # }
+    /// ```
+    pub fn now_or_never(
+        mut self,
+    ) -> Result<Option<RwLockReadGuard<T>>, AcquireError> {
+        match self.poll_once()? {
+            Async::Ready(guard) => Ok(Some(guard)),
+            Async::NotReady => Ok(None),
+        }
+    }
+}
+
+impl<T: ?Sized> RwLockReadFut<T> {
+    /// Returns the number of readers queued ahead of this `Future`, or `None`
+    /// if it isn't queued.  It's only queued after it's been polled once and
+    /// before it's been granted the `RwLock`.  Queued readers are granted the
+    /// `RwLock` in order, as soon as readers aren't blocked.
+    pub fn queue_position(&self) -> Option<usize> {
+        match self.state {
+            FutState::Pending(_) => {
+                let lock_data = self.rwlock.lock_state_infallible();
+                // Don't count readers that were dropped while queued
+                lock_data
+                    .read_waiters
+                    .iter()
+                    .filter(|w| !w.tx.is_canceled())
+                    .position(|w| w.ticket == self.ticket)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if this `Future` has been granted shared access to the
+    /// `RwLock`, even if it hasn't yet been polled to completion.  See
+    /// [`MutexFut::is_acquired`](struct.MutexFut.html#method.is_acquired).
+    ///
+    /// Unlike `MutexFut::is_acquired`, this takes `&mut self`, because it
+    /// must take delivery of the grant.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// let guard = rwlock.try_write().unwrap();
+    /// let mut fut = rwlock.read();
+    /// assert!(fut.poll_once().unwrap().is_not_ready());
+    /// assert!(!fut.is_acquired());
+    /// drop(guard);
+    /// assert!(fut.is_acquired());
+    /// # }
+    /// ```
+    pub fn is_acquired(&mut self) -> bool {
+        let granted = match self.state {
+            FutState::New => false,
+            FutState::Pending(ref mut rx) => rx.try_recv() == Ok(Some(())),
+            FutState::Granted | FutState::Acquired => return true,
+        };
+        if granted {
+            self.state = FutState::Granted;
+        }
+        granted
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for RwLockReadFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLockReadFut")
+            .field("state", &self.state.name())
+            .field("queue_position", &self.queue_position())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadFut<T> {
+    fn drop(&mut self) {
+        match self.state {
+            FutState::New => {
+                // RwLock hasn't yet been modified; nothing to do
+            }
+            FutState::Pending(ref mut rx) => {
+                rx.close();
+                match rx.try_recv() {
+                    Ok(Some(())) => {
+                        // This future received ownership of the lock, but got
+                        // dropped before it was ever polled.  Release the
+                        // lock.
+                        self.rwlock.unlock_reader()
+                    }
+                    Ok(None) | Err(oneshot::Canceled) => {
+                        // Dropping the Future before it acquires the lock is
+                        // equivalent to cancelling it.  Leave the queue now,
+                        // rather than when the lock gets around to us.
+                        let mut lock_data = self.rwlock.lock_state_infallible();
+                        lock_data.read_waiters.retain(|w| !w.tx.is_canceled());
+                    }
+                }
+            }
+            FutState::Granted => self.rwlock.unlock_reader(),
+            FutState::Acquired => {
+                // The RwLockReadGuard will take care of releasing the RwLock
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Future for RwLockReadFut<T> {
+    type Item = RwLockReadGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut granted = false;
+        let (result, new_state) = match self.state {
+            FutState::New => {
+                let mut lock_data = self.rwlock.lock_state()?;
+                if !self.checked && self.rwlock.is_poisoned() {
+                    return Err(AcquireError::Panicked);
+                }
+                if lock_data.readers_blocked(&self.rwlock.inner.config) {
+                    if lock_data
+                        .queue_full(self.rwlock.inner.config.max_waiters)
+                    {
+                        return Err(AcquireError::QueueFull);
+                    }
+                    let (tx, mut rx) = oneshot::channel::<()>();
+                    self.ticket = lock_data.next_ticket;
+                    lock_data.next_ticket =
+                        lock_data.next_ticket.wrapping_add(1);
+                    lock_data.read_waiters.push_back(ReadWaiter {
+                        ticket: self.ticket,
+                        tx,
+                    });
+                    #[cfg(feature = "metrics-histogram")]
+                    {
+                        self.since = Some(Instant::now());
+                    }
+                    #[cfg(feature = "stats")]
+                    {
+                        if self.rwlock.inner.config.stats {
+                            lock_data.record_queue_depth();
+                        }
+                    }
+                    // Even though we know it isn't ready, we need to poll the
+                    // receiver in order to register our task for notification.
+                    assert!(rx.poll().unwrap().is_not_ready());
+                    (Ok(Async::NotReady), FutState::Pending(rx))
+                } else if lock_data
+                    .streak
+                    .exhausted(self.rwlock.inner.config.yield_budget)
+                {
+                    (Ok(Async::NotReady), FutState::New)
+                } else {
+                    lock_data.num_readers += 1;
+                    #[cfg(feature = "metrics-histogram")]
+                    {
+                        if self.rwlock.inner.config.stats {
+                            lock_data
+                                .stats
+                                .record_wait_time(Duration::from_secs(0));
+                        }
+                    }
+                    let starved = lock_data.starved_writer();
+                    drop(lock_data);
+                    report_starvation(starved);
+                    let guard = RwLockReadGuard {
+                        rwlock: self.rwlock.clone(),
+                    };
+                    (Ok(Async::Ready(guard)), FutState::Acquired)
+                }
+            }
+            FutState::Pending(ref mut rx) => {
+                match rx.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // It's impossible for receiver.poll() to return an error.
+                    // The only way that would happen is if the sender got
                     // dropped.  But that can't happen because the RwLock owns
                     // the sender, and the Fut retains a clone of the RwLock.
                     Err(_) => unreachable!(),
                     Ok(Async::Ready(_)) => {
-                        let state = FutState::Acquired;
-                        let result = Ok(Async::Ready(RwLockWriteGuard {
-                            rwlock: self.rwlock.clone(),
-                        }));
-                        (result, state)
-                    } // LCOV_EXCL_LINE   kcov false negative
+                        granted = true;
+                        (Ok(Async::NotReady), FutState::Granted)
+                    }
                 }
             }
-            &mut FutState::Acquired => panic!("Double-poll of ready Future"),
+            FutState::Granted => {
+                #[cfg(feature = "metrics-histogram")]
+                if self.rwlock.inner.config.stats {
+                    let waited =
+                        self.since.map(|t| t.elapsed()).unwrap_or_default();
+                    self.rwlock
+                        .lock_state_infallible()
+                        .stats
+                        .record_wait_time(waited);
+                }
+                let state = FutState::Acquired;
+                let result = Ok(Async::Ready(RwLockReadGuard {
+                    rwlock: self.rwlock.clone(),
+                }));
+                (result, state)
+            } // LCOV_EXCL_LINE   kcov false negative
+            FutState::Acquired => panic!("Double-poll of ready Future"),
         };
         self.state = new_state;
-        result
+        if granted {
+            return self.poll();
+        }
+        match result {
+            Ok(Async::Ready(guard))
+                if !self.checked && guard.rwlock.is_poisoned() =>
+            {
+                // Poisoned while we waited.  Pass the RwLock on.
+                drop(guard);
+                Err(AcquireError::Panicked)
+            }
+            result => result,
+        }
     }
 }
 
-#[derive(Debug, Default)]
-struct RwLockData {
-    /// True iff the `RwLock` is currently exclusively owned
-    exclusive: bool,
+/// A `Future` representing a pending `RwLock` exclusive acquisition.
+pub struct RwLockWriteFut<T: ?Sized> {
+    state: FutState,
+    rwlock: RwLock<T>,
+    /// Identifies this future's entry in the writer queue, once it's queued
+    ticket: u64,
+    /// Set by `RwLock::write_checked`, which returns the guard of a poisoned
+    /// `RwLock` instead of failing
+    checked: bool,
+    /// When this acquisition started waiting
+    #[cfg(feature = "metrics-histogram")]
+    since: Option<Instant>,
+}
 
-    /// The number of tasks that currently have shared ownership of the RwLock
-    num_readers: u32,
+impl<T: ?Sized> RwLockWriteFut<T> {
+    fn new(state: FutState, rwlock: RwLock<T>) -> Self {
+        RwLockWriteFut {
+            state,
+            rwlock,
+            ticket: 0,
+            checked: false,
+            #[cfg(feature = "metrics-histogram")]
+            since: None,
+        }
+    }
 
-    // FIFO queue of waiting readers
-    read_waiters: VecDeque<oneshot::Sender<()>>,
+    /// Polls the `Future` once, without needing an executor.  See
+    /// [`MutexFut::poll_once`](struct.MutexFut.html#method.poll_once).
+    pub fn poll_once(&mut self) -> Poll<RwLockWriteGuard<T>, AcquireError> {
+        poll_detached(self)
+    }
 
-    // FIFO queue of waiting writers
-    write_waiters: VecDeque<oneshot::Sender<()>>,
+    /// Acquires the `RwLock` exclusively if that's possible without waiting.
+    /// Otherwise, gives up and returns `None`.
+    pub fn now_or_never(
+        mut self,
+    ) -> Result<Option<RwLockWriteGuard<T>>, AcquireError> {
+        match self.poll_once()? {
+            Async::Ready(guard) => Ok(Some(guard)),
+            Async::NotReady => Ok(None),
+        }
+    }
+
+    /// Returns the number of writers queued ahead of this `Future`, or `None`
+    /// if it isn't queued.  It's only queued after it's been polled once and
+    /// before it's been granted the `RwLock`.
+    pub fn queue_position(&self) -> Option<usize> {
+        match self.state {
+            FutState::Pending(_) => {
+                let lock_data = self.rwlock.lock_state_infallible();
+                // Don't count writers that were dropped while queued
+                let live = lock_data
+                    .write_waiters
+                    .iter()
+                    .filter(|w| !w.tx.is_canceled())
+                    .map(|w| w.ticket);
+                self.rwlock
+                    .inner
+                    .config
+                    .fairness
+                    .queue_position(live, self.ticket)
+            }
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Default)]
-struct Inner<T: ?Sized> {
-    mutex: sync::Mutex<RwLockData>,
-    data: UnsafeCell<T>,
+impl<T: ?Sized> RwLockWriteFut<T> {
+    /// Returns `true` if this `Future` has been granted exclusive access to the
+    /// `RwLock`, even if it hasn't yet been polled to completion.  See
+    /// [`MutexFut::is_acquired`](struct.MutexFut.html#method.is_acquired).
+    ///
+    /// Unlike `MutexFut::is_acquired`, this takes `&mut self`, because it
+    /// must take delivery of the grant.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// let guard = rwlock.try_write().unwrap();
+    /// let mut fut = rwlock.write();
+    /// assert!(fut.poll_once().unwrap().is_not_ready());
+    /// assert!(!fut.is_acquired());
+    /// drop(guard);
+    /// assert!(fut.is_acquired());
+    /// # }
+    /// ```
+    pub fn is_acquired(&mut self) -> bool {
+        let granted = match self.state {
+            FutState::New => false,
+            FutState::Pending(ref mut rx) => rx.try_recv() == Ok(Some(())),
+            FutState::Granted | FutState::Acquired => return true,
+        };
+        if granted {
+            self.state = FutState::Granted;
+        }
+        granted
+    }
 }
 
-/// A Futures-aware RwLock.
-///
-/// `std::sync::RwLock` cannot be used in an asynchronous environment like
-/// Tokio, because an acquisition can block an entire reactor.  This class can
-/// be used instead.  It functions much like `std::sync::RwLock`.  Unlike that
-/// class, it also has a builtin `Arc`, making it accessible from multiple
-/// threads.  It's also safe to `clone`.  Also unlike `std::sync::RwLock`, this
-/// class does not detect lock poisoning.
-#[derive(Debug, Default)]
-pub struct RwLock<T: ?Sized> {
-    inner: sync::Arc<Inner<T>>,
+impl<T: ?Sized> fmt::Debug for RwLockWriteFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLockWriteFut")
+            .field("state", &self.state.name())
+            .field("queue_position", &self.queue_position())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteFut<T> {
+    fn drop(&mut self) {
+        match self.state {
+            FutState::New => {
+                // RwLock hasn't yet been modified; nothing to do
+            }
+            FutState::Pending(ref mut rx) => {
+                rx.close();
+                match rx.try_recv() {
+                    Ok(Some(())) => {
+                        // This future received ownership of the lock, but got
+                        // dropped before it was ever polled.  Release the
+                        // lock.
+                        self.rwlock.unlock_writer()
+                    }
+                    Ok(None) | Err(oneshot::Canceled) => {
+                        // Dropping the Future before it acquires the lock is
+                        // equivalent to cancelling it.  Leave the queue now,
+                        // rather than when the lock gets around to us.
+                        let mut lock_data = self.rwlock.lock_state_infallible();
+                        lock_data.write_waiters.retain(|w| !w.tx.is_canceled());
+                        // Readers may have been waiting behind us
+                        lock_data.grant_readers(&self.rwlock.inner.config);
+                    }
+                }
+            }
+            FutState::Granted => self.rwlock.unlock_writer(),
+            FutState::Acquired => {
+                // The RwLockWriteGuard will take care of releasing the RwLock
+            }
+        }
+    }
 }
 
-impl<T: ?Sized> Clone for RwLock<T> {
-    fn clone(&self) -> RwLock<T> {
-        RwLock {
-            inner: self.inner.clone(),
+impl<T: ?Sized> Future for RwLockWriteFut<T> {
+    type Item = RwLockWriteGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut granted = false;
+        let (result, new_state) = match self.state {
+            FutState::New => {
+                let mut lock_data = self.rwlock.lock_state()?;
+                if !self.checked && self.rwlock.is_poisoned() {
+                    return Err(AcquireError::Panicked);
+                }
+                if lock_data.exclusive || lock_data.num_readers > 0 {
+                    if lock_data
+                        .queue_full(self.rwlock.inner.config.max_waiters)
+                    {
+                        return Err(AcquireError::QueueFull);
+                    }
+                    let (tx, mut rx) = oneshot::channel::<()>();
+                    self.ticket = lock_data.next_ticket;
+                    lock_data.next_ticket =
+                        lock_data.next_ticket.wrapping_add(1);
+                    let waiter = WriteWaiter::new(self.ticket, tx);
+                    lock_data.write_waiters.push_back(waiter);
+                    #[cfg(feature = "metrics-histogram")]
+                    {
+                        self.since = Some(Instant::now());
+                    }
+                    #[cfg(feature = "stats")]
+                    {
+                        if self.rwlock.inner.config.stats {
+                            lock_data.record_queue_depth();
+                        }
+                    }
+                    // Even though we know it isn't ready, we need to poll the
+                    // receiver in order to register our task for notification.
+                    assert!(rx.poll().unwrap().is_not_ready());
+                    (Ok(Async::NotReady), FutState::Pending(rx))
+                } else if lock_data
+                    .streak
+                    .exhausted(self.rwlock.inner.config.yield_budget)
+                {
+                    (Ok(Async::NotReady), FutState::New)
+                } else {
+                    lock_data.exclusive = true;
+                    #[cfg(feature = "metrics-histogram")]
+                    {
+                        if self.rwlock.inner.config.stats {
+                            lock_data
+                                .stats
+                                .record_wait_time(Duration::from_secs(0));
+                        }
+                    }
+                    let guard = RwLockWriteGuard {
+                        rwlock: self.rwlock.clone(),
+                    };
+                    (Ok(Async::Ready(guard)), FutState::Acquired)
+                }
+            }
+            FutState::Pending(ref mut rx) => {
+                match rx.poll() {
+                    Ok(Async::NotReady) => {
+                        // Whatever woke us, it's a chance to notice that we've
+                        // been waiting too long
+                        let starved = self
+                            .rwlock
+                            .lock_state_infallible()
+                            .starved_writer();
+                        report_starvation(starved);
+                        return Ok(Async::NotReady);
+                    }
+                    // It's impossible for receiver.poll() to return an error.
+                    // The only way that would happen is if the sender got
+                    // dropped.  But that can't happen because the RwLock owns
+                    // the sender, and the Fut retains a clone of the RwLock.
+                    Err(_) => unreachable!(),
+                    Ok(Async::Ready(_)) => {
+                        granted = true;
+                        (Ok(Async::NotReady), FutState::Granted)
+                    }
+                }
+            }
+            FutState::Granted => {
+                #[cfg(feature = "metrics-histogram")]
+                if self.rwlock.inner.config.stats {
+                    let waited =
+                        self.since.map(|t| t.elapsed()).unwrap_or_default();
+                    self.rwlock
+                        .lock_state_infallible()
+                        .stats
+                        .record_wait_time(waited);
+                }
+                let state = FutState::Acquired;
+                let result = Ok(Async::Ready(RwLockWriteGuard {
+                    rwlock: self.rwlock.clone(),
+                }));
+                (result, state)
+            } // LCOV_EXCL_LINE   kcov false negative
+            FutState::Acquired => panic!("Double-poll of ready Future"),
+        };
+        self.state = new_state;
+        if granted {
+            return self.poll();
+        }
+        match result {
+            Ok(Async::Ready(guard))
+                if !self.checked && guard.rwlock.is_poisoned() =>
+            {
+                // Poisoned while we waited.  Pass the RwLock on.
+                drop(guard);
+                Err(AcquireError::Panicked)
+            }
+            result => result,
+        }
+    }
+}
+
+/// A `Future` representing a pending upgradable shared acquisition of an
+/// `RwLock`.  Returned by
+/// [`RwLock::upgradable_read`](struct.RwLock.html#method.upgradable_read).
+pub struct RwLockUpgradableReadFut<T: ?Sized> {
+    state: FutState,
+    rwlock: RwLock<T>,
+}
+
+impl<T: ?Sized> fmt::Debug for RwLockUpgradableReadFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLockUpgradableReadFut")
+            .field("state", &self.state.name())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockUpgradableReadFut<T> {
+    fn drop(&mut self) {
+        match self.state {
+            FutState::New | FutState::Acquired => (),
+            FutState::Pending(ref mut rx) => {
+                rx.close();
+                match rx.try_recv() {
+                    // Received the upgradable slot, but never took it
+                    Ok(Some(())) => self.rwlock.unlock_upgradable(),
+                    Ok(None) | Err(oneshot::Canceled) => {
+                        let mut lock_data = self.rwlock.lock_state_infallible();
+                        lock_data
+                            .upgradable_waiters
+                            .retain(|w| !w.is_canceled());
+                    }
+                }
+            }
+            FutState::Granted => self.rwlock.unlock_upgradable(),
+        }
+    }
+}
+
+impl<T: ?Sized> Future for RwLockUpgradableReadFut<T> {
+    type Item = RwLockUpgradableReadGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let new_state = match self.state {
+            FutState::New => {
+                let mut lock_data = self.rwlock.lock_state()?;
+                if self.rwlock.is_poisoned() {
+                    return Err(AcquireError::Panicked);
+                }
+                let config = &self.rwlock.inner.config;
+                if lock_data.upgradable || lock_data.readers_blocked(config) {
+                    if lock_data
+                        .queue_full(self.rwlock.inner.config.max_waiters)
+                    {
+                        return Err(AcquireError::QueueFull);
+                    }
+                    let (tx, mut rx) = oneshot::channel::<()>();
+                    lock_data.upgradable_waiters.push_back(tx);
+                    // Even though we know it isn't ready, we need to poll the
+                    // receiver in order to register our task for notification.
+                    assert!(rx.poll().unwrap().is_not_ready());
+                    self.state = FutState::Pending(rx);
+                    return Ok(Async::NotReady);
+                } else if lock_data
+                    .streak
+                    .exhausted(self.rwlock.inner.config.yield_budget)
+                {
+                    return Ok(Async::NotReady);
+                }
+                lock_data.upgradable = true;
+                lock_data.num_readers += 1;
+                FutState::Acquired
+            }
+            FutState::Pending(ref mut rx) => match rx.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // The RwLock owns the sender, and we retain a clone of the
+                // RwLock
+                Err(_) => unreachable!(),
+                Ok(Async::Ready(_)) => FutState::Acquired,
+            },
+            FutState::Granted => FutState::Acquired,
+            FutState::Acquired => panic!("Double-poll of ready Future"),
+        };
+        self.state = new_state;
+        let guard = RwLockUpgradableReadGuard {
+            rwlock: self.rwlock.clone(),
+        };
+        if self.rwlock.is_poisoned() {
+            // Poisoned while we waited.  Pass the RwLock on.
+            drop(guard);
+            return Err(AcquireError::Panicked);
+        }
+        Ok(Async::Ready(guard))
+    }
+}
+
+/// A `Future` representing a pending upgrade of an upgradable read guard to a
+/// write guard.  Returned by
+/// [`RwLockUpgradableReadGuard::upgrade`](struct.RwLockUpgradableReadGuard.html#method.upgrade).
+///
+/// Dropping it before it completes releases the `RwLock`, like dropping the
+/// original guard.
+pub struct RwLockUpgradeFut<T: ?Sized> {
+    state: FutState,
+    rwlock: RwLock<T>,
+}
+
+impl<T: ?Sized> fmt::Debug for RwLockUpgradeFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLockUpgradeFut")
+            .field("state", &self.state.name())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockUpgradeFut<T> {
+    fn drop(&mut self) {
+        match self.state {
+            FutState::New => unreachable!("RwLockUpgradeFut is never New"),
+            FutState::Pending(ref mut rx) => {
+                rx.close();
+                match rx.try_recv() {
+                    // Upgraded, but never took the write guard
+                    Ok(Some(())) => self.rwlock.unlock_writer(),
+                    Ok(None) | Err(oneshot::Canceled) => {
+                        self.rwlock
+                            .lock_state_infallible()
+                            .cancel_upgrade(&self.rwlock.inner.config);
+                        self.rwlock.unlock_upgradable();
+                    }
+                }
+            }
+            FutState::Granted => self.rwlock.unlock_writer(),
+            FutState::Acquired => (),
+        }
+    }
+}
+
+impl<T: ?Sized> Future for RwLockUpgradeFut<T> {
+    type Item = RwLockWriteGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            FutState::Pending(ref mut rx) => match rx.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // The RwLock owns the sender, and we retain a clone of the
+                // RwLock
+                Err(_) => unreachable!(),
+                Ok(Async::Ready(_)) => (),
+            },
+            FutState::Granted => (),
+            FutState::New => unreachable!("RwLockUpgradeFut is never New"),
+            FutState::Acquired => panic!("Double-poll of ready Future"),
+        }
+        self.state = FutState::Acquired;
+        Ok(Async::Ready(RwLockWriteGuard {
+            rwlock: self.rwlock.clone(),
+        }))
+    }
+}
+
+/// Polls an acquisition with a time limit, dropping it to leave the
+/// `RwLock`'s wait queue once `delay` fires.
+#[cfg(feature = "timer")]
+fn poll_timeout<F>(
+    fut: &mut Option<F>,
+    delay: &mut Delay,
+) -> Poll<F::Item, F::Error>
+where
+    F: Future<Error = AcquireError>,
+{
+    {
+        let fut = fut.as_mut().expect("Double-poll of failed Future");
+        if let Async::Ready(guard) = fut.poll()? {
+            return Ok(Async::Ready(guard));
+        }
+    }
+    match delay.poll() {
+        Ok(Async::NotReady) => Ok(Async::NotReady),
+        // A timer error means that the delay will never fire, so give up now
+        // rather than wait forever.
+        Ok(Async::Ready(())) | Err(_) => {
+            *fut = None;
+            Err(AcquireError::TimedOut)
+        }
+    }
+}
+
+/// A `Future` representing a pending nonexclusive `RwLock` acquisition with a
+/// time limit.  Returned by
+/// [`RwLock::read_timeout`](struct.RwLock.html#method.read_timeout) and
+/// [`RwLock::read_until`](struct.RwLock.html#method.read_until).
+#[cfg(feature = "timer")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub struct RwLockReadTimeoutFut<T: ?Sized> {
+    /// `None` once the time limit has expired
+    fut: Option<RwLockReadFut<T>>,
+    delay: Delay,
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> fmt::Debug for RwLockReadTimeoutFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLockReadTimeoutFut")
+            .field("fut", &self.fut)
+            .field("deadline", &self.delay.deadline())
+            .finish()
+    }
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> Future for RwLockReadTimeoutFut<T> {
+    type Item = RwLockReadGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_timeout(&mut self.fut, &mut self.delay)
+    }
+}
+
+/// A `Future` representing a pending exclusive `RwLock` acquisition with a
+/// time limit.  Returned by
+/// [`RwLock::write_timeout`](struct.RwLock.html#method.write_timeout) and
+/// [`RwLock::write_until`](struct.RwLock.html#method.write_until).
+#[cfg(feature = "timer")]
+#[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+pub struct RwLockWriteTimeoutFut<T: ?Sized> {
+    /// `None` once the time limit has expired
+    fut: Option<RwLockWriteFut<T>>,
+    delay: Delay,
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> fmt::Debug for RwLockWriteTimeoutFut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLockWriteTimeoutFut")
+            .field("fut", &self.fut)
+            .field("deadline", &self.delay.deadline())
+            .finish()
+    }
+}
+
+#[cfg(feature = "timer")]
+impl<T: ?Sized> Future for RwLockWriteTimeoutFut<T> {
+    type Item = RwLockWriteGuard<T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        poll_timeout(&mut self.fut, &mut self.delay)
+    }
+}
+
+#[derive(Debug, Default)]
+struct RwLockData {
+    /// True iff the `RwLock` is currently exclusively owned
+    exclusive: bool,
+
+    /// The number of tasks that currently have shared ownership of the RwLock,
+    /// including the upgradable reader, if any
+    num_readers: u32,
+
+    /// True iff one of the readers holds an upgradable read guard
+    upgradable: bool,
+
+    /// Notifies the upgradable reader once it's the only reader left, if it's
+    /// trying to upgrade.  New readers must wait until then.
+    upgrader: Option<oneshot::Sender<()>>,
+
+    // FIFO queue of waiting readers
+    read_waiters: VecDeque<ReadWaiter>,
+
+    // FIFO queue of waiting writers
+    write_waiters: VecDeque<WriteWaiter>,
+
+    // FIFO queue of tasks waiting for an upgradable read guard
+    upgradable_waiters: VecDeque<oneshot::Sender<()>>,
+
+    /// The ticket for the next reader or writer to join the queue
+    next_ticket: u64,
+
+    /// How many times the `RwLock` has been released by a writer
+    version: u64,
+
+    /// Recent acquisitions that didn't have to wait
+    streak: Streak,
+
+    /// Reports writers that have been waiting too long
+    starvation_hook: Option<StarvationHook>,
+
+    #[cfg(feature = "stats")]
+    stats: LockStats,
+}
+
+impl RwLockData {
+    /// If the longest-waiting writer has exceeded the starvation threshold,
+    /// and hasn't been reported yet, return the event to report.  The caller
+    /// should fire it after releasing the `RwLockData`.
+    fn starved_writer(
+        &mut self,
+    ) -> Option<(sync::Arc<StarvationFn>, WriterStarvation)> {
+        let num_readers = self.num_readers;
+        let hook = self.starvation_hook.as_ref()?;
+        let waiter = self.write_waiters.front_mut()?;
+        let waited = waiter.since.elapsed();
+        if waiter.reported || waited <= hook.threshold {
+            return None;
+        }
+        waiter.reported = true;
+        Some((
+            hook.f.clone(),
+            WriterStarvation {
+                waited,
+                num_readers,
+            },
+        ))
+    }
+
+    /// Returns true if another waiter would make the queue longer than `max`,
+    /// even after discarding waiters that were dropped while queued.
+    fn queue_full(&mut self, max: Option<usize>) -> bool {
+        let max = match max {
+            Some(max) => max,
+            None => return false,
+        };
+        if self.num_waiters() >= max {
+            self.read_waiters.retain(|w| !w.tx.is_canceled());
+            self.write_waiters.retain(|w| !w.tx.is_canceled());
+            self.upgradable_waiters.retain(|tx| !tx.is_canceled());
+        }
+        self.num_waiters() >= max
+    }
+
+    /// The number of queued waiters, including any that were dropped while
+    /// queued.
+    fn num_waiters(&self) -> usize {
+        self.read_waiters.len()
+            + self.write_waiters.len()
+            + self.upgradable_waiters.len()
+    }
+
+    /// Converts the upgradable reader, which must be the only reader, into a
+    /// writer.
+    fn upgrade(&mut self) {
+        debug_assert!(self.upgradable);
+        debug_assert_eq!(self.num_readers, 1);
+        self.upgradable = false;
+        self.num_readers = 0;
+        self.exclusive = true;
+    }
+
+    /// Abandons a pending upgrade, and admits the readers that were waiting
+    /// for it.
+    fn cancel_upgrade(&mut self, config: &LockConfig) {
+        self.upgrader = None;
+        self.grant_readers(config);
+    }
+
+    /// Returns true if new readers must wait: when the `RwLock` is exclusively
+    /// owned or being upgraded, when writers are waiting and have priority, or
+    /// when it already has as many readers as it allows.
+    fn readers_blocked(&self, config: &LockConfig) -> bool {
+        self.exclusive
+            || self.upgrader.is_some()
+            || (config.preference == Preference::Writers
+                && self.write_waiters.iter().any(|w| !w.tx.is_canceled()))
+            || config
+                .max_readers
+                .map_or(false, |max| self.num_readers >= max)
+    }
+
+    /// Hands shared ownership to waiting readers, in order, and to the first
+    /// waiting upgradable reader, for as long as readers aren't blocked.
+    fn grant_readers(&mut self, config: &LockConfig) {
+        while !self.readers_blocked(config) {
+            match self.read_waiters.pop_front() {
+                Some(w) => {
+                    if w.tx.send(()).is_ok() {
+                        self.num_readers += 1;
+                    } else {
+                        eprintln!("Read lock was canceled before acquired")
+                    }
+                }
+                None => break,
+            }
+        }
+        if !self.upgradable && !self.readers_blocked(config) {
+            while let Some(tx) = self.upgradable_waiters.pop_front() {
+                if tx.send(()).is_ok() {
+                    self.upgradable = true;
+                    self.num_readers += 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Whether and how the `RwLock` is held.
+    fn state(&self) -> LockState {
+        if self.exclusive {
+            LockState::Exclusive
+        } else if self.num_readers > 0 {
+            LockState::Shared
+        } else {
+            LockState::Unlocked
+        }
+    }
+
+    /// The number of waiting readers and writers, not counting those that
+    /// were dropped while queued.
+    fn live_waiters(&self) -> usize {
+        let readers = self.read_waiters.iter().filter(|w| !w.tx.is_canceled());
+        let writers = self.write_waiters.iter().filter(|w| !w.tx.is_canceled());
+        let upgradable = self
+            .upgradable_waiters
+            .iter()
+            .filter(|tx| !tx.is_canceled());
+        readers.count() + writers.count() + upgradable.count()
+    }
+
+    #[cfg(feature = "stats")]
+    fn record_queue_depth(&mut self) {
+        let depth = self.num_waiters();
+        self.stats.record_queue_depth(depth);
+    }
+}
+
+/// A task waiting for shared ownership of an `RwLock`
+#[derive(Debug)]
+struct ReadWaiter {
+    ticket: u64,
+    tx: oneshot::Sender<()>,
+}
+
+/// A task waiting for exclusive ownership of an `RwLock`
+#[derive(Debug)]
+struct WriteWaiter {
+    ticket: u64,
+    tx: oneshot::Sender<()>,
+    /// When the writer started waiting
+    since: Instant,
+    /// Whether this writer has already been reported as starved
+    reported: bool,
+}
+
+impl WriteWaiter {
+    fn new(ticket: u64, tx: oneshot::Sender<()>) -> Self {
+        WriteWaiter {
+            ticket,
+            tx,
+            since: Instant::now(),
+            reported: false,
+        }
+    }
+}
+
+type StarvationFn = dyn Fn(WriterStarvation) + Send + Sync;
+
+/// Installed by `RwLock::on_writer_starvation`
+struct StarvationHook {
+    threshold: Duration,
+    f: sync::Arc<StarvationFn>,
+}
+
+impl fmt::Debug for StarvationHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StarvationHook")
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+/// Fire a starvation event returned by `RwLockData::starved_writer`.
+fn report_starvation(
+    starved: Option<(sync::Arc<StarvationFn>, WriterStarvation)>,
+) {
+    if let Some((f, event)) = starved {
+        f(event);
+    }
+}
+
+/// Describes a writer that has waited too long to acquire an `RwLock`.  See
+/// [`RwLock::on_writer_starvation`].
+///
+/// [`RwLock::on_writer_starvation`]: struct.RwLock.html#method.on_writer_starvation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WriterStarvation {
+    /// How long the writer had been waiting when the event fired
+    pub waited: Duration,
+    /// How many readers held the `RwLock` at that time
+    pub num_readers: u32,
+}
+
+#[derive(Debug, Default)]
+struct Inner<T: ?Sized> {
+    mutex: sync::Mutex<RwLockData>,
+    config: LockConfig,
+    /// A `PoisonPolicy`, stored as a `usize`
+    poison_policy: AtomicUsize,
+    /// Set if a write guard was dropped during a panic, with
+    /// `poison_on_panic`
+    poisoned: AtomicBool,
+    validator: Validator,
+    data: UnsafeCell<T>,
+}
+
+/// Creates an [`RwLock`] with non-default settings.  Returned by
+/// [`RwLock::builder`].
+///
+/// [`RwLock`]: struct.RwLock.html
+/// [`RwLock::builder`]: struct.RwLock.html#method.builder
+pub struct RwLockBuilder<T> {
+    config: LockConfig,
+    poison_policy: PoisonPolicy,
+    _data: PhantomData<fn(T)>,
+}
+
+impl<T> RwLockBuilder<T> {
+    /// Names the `RwLock`, to identify it in diagnostics.
+    pub fn name<N: Into<Cow<'static, str>>>(mut self, name: N) -> Self {
+        self.config.name = Some(name.into());
+        self
+    }
+
+    /// Chooses which waiting writer gets the `RwLock` whenever it's released.
+    /// Waiting readers are always granted the `RwLock` together.  The default
+    /// is [`Fairness::Fifo`](enum.Fairness.html#variant.Fifo).
+    pub fn fairness(mut self, fairness: Fairness) -> Self {
+        self.config.fairness = fairness;
+        self
+    }
+
+    /// Chooses whether new readers may pass waiting writers.  The default is
+    /// [`Preference::Readers`](enum.Preference.html#variant.Readers).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::builder().preference(Preference::Writers).build(0);
+    /// let reader = rwlock.try_read().unwrap();
+    /// let mut writer = rwlock.write();
+    /// assert!(writer.poll_once().unwrap().is_not_ready());
+    /// // The waiting writer holds off new readers
+    /// assert!(rwlock.try_read().is_err());
+    /// # }
+    /// ```
+    pub fn preference(mut self, preference: Preference) -> Self {
+        self.config.preference = preference;
+        self
+    }
+
+    /// Limits how many readers, including an upgradable reader, may share the
+    /// `RwLock` at once.  Beyond that, readers wait their turn as writers do.
+    /// By default, there's no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::builder().max_readers(2).build(0u32);
+    /// let r1 = rwlock.try_read().unwrap();
+    /// let r2 = rwlock.try_read().unwrap();
+    /// assert!(rwlock.try_read().is_err());
+    /// drop(r1);
+    /// assert!(rwlock.try_read().is_ok());
+    /// # }
+    /// ```
+    pub fn max_readers(mut self, max: u32) -> Self {
+        assert!(max > 0, "an RwLock must allow at least one reader");
+        self.config.max_readers = Some(max);
+        self
+    }
+
+    /// Limits how many tasks, readers and writers together, may wait for the
+    /// `RwLock` at once.  Beyond that, acquisitions will fail with
+    /// [`AcquireError::QueueFull`](enum.AcquireError.html#variant.QueueFull)
+    /// instead of waiting.  By default, there's no limit.
+    pub fn max_waiters(mut self, max: usize) -> Self {
+        self.config.max_waiters = Some(max);
+        self
+    }
+
+    /// Poisons the `RwLock` if a write guard is dropped while its thread is
+    /// panicking, like `std::sync::RwLock`.  Until the poison is cleared with
+    /// [`RwLock::clear_poison`], ordinary acquisitions like `read`, `write`,
+    /// and their `try_*` variants fail with `Panicked`.  Only
+    /// [`RwLock::read_checked`] and [`RwLock::write_checked`] can still
+    /// acquire it, and they report the poison with a recoverable
+    /// `PoisonError`.  Disabled by default.
+    ///
+    /// [`RwLock::clear_poison`]: struct.RwLock.html#method.clear_poison
+    /// [`RwLock::read_checked`]: struct.RwLock.html#method.read_checked
+    /// [`RwLock::write_checked`]: struct.RwLock.html#method.write_checked
+    pub fn poison_on_panic(mut self, enabled: bool) -> Self {
+        self.config.poison_on_panic = enabled;
+        self
+    }
+
+    /// Limits how many times in a row a single task may acquire the `RwLock`,
+    /// for reading or writing, without waiting.  After `budget` such
+    /// acquisitions, the next one will yield to the executor first.  That
+    /// keeps a task that locks an uncontended `RwLock` in a loop from starving
+    /// other tasks on a single-threaded runtime.  By default, there's no
+    /// limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `budget` is 0.
+    pub fn yield_budget(mut self, budget: u32) -> Self {
+        assert!(budget > 0, "a yield budget must be at least 1");
+        self.config.yield_budget = Some(budget);
+        self
+    }
+
+    /// Sets the initial policy for handling a poisoned `RwLock`.  See
+    /// [`RwLock::set_poison_policy`](struct.RwLock.html#method.set_poison_policy).
+    pub fn poison_policy(mut self, policy: PoisonPolicy) -> Self {
+        self.poison_policy = policy;
+        self
+    }
+
+    /// Enables or disables collecting statistics for this `RwLock`, including
+    /// wait times with the `metrics-histogram` feature.  Statistics are
+    /// enabled by default.
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn stats(mut self, enabled: bool) -> Self {
+        self.config.stats = enabled;
+        self
+    }
+
+    /// Creates the `RwLock`, in the unlocked state.
+    pub fn build(self, t: T) -> RwLock<T> {
+        let lock_data = RwLockData {
+            exclusive: false,
+            num_readers: 0,
+            upgradable: false,
+            upgrader: None,
+            read_waiters: VecDeque::new(),
+            write_waiters: VecDeque::new(),
+            upgradable_waiters: VecDeque::new(),
+            next_ticket: 0,
+            version: 0,
+            streak: Streak::default(),
+            starvation_hook: None,
+            #[cfg(feature = "stats")]
+            stats: LockStats::default(),
+        }; // LCOV_EXCL_LINE   kcov false negative
+        let inner = Inner {
+            mutex: sync::Mutex::new(lock_data),
+            config: self.config,
+            poison_policy: AtomicUsize::new(self.poison_policy.to_usize()),
+            poisoned: AtomicBool::new(false),
+            validator: Validator::default(),
+            data: UnsafeCell::new(t),
+        }; // LCOV_EXCL_LINE   kcov false negative
+        RwLock {
+            inner: sync::Arc::new(inner),
+        }
+    }
+}
+
+/// `RwLockWeak` is a non-owning reference to an [`RwLock`], just like
+/// [`MutexWeak`] is to a `Mutex`.  It doesn't keep the protected data alive,
+/// and must be upgraded before it can be locked.
+///
+/// # Examples
+/// ```
+/// # use futures_locks::RwLock;
+/// # fn main() {
+/// let rwlock = RwLock::<u32>::new(0);
+/// let weak = RwLock::downgrade(&rwlock);
+/// assert!(weak.upgrade().is_some());
+/// drop(rwlock);
+/// assert!(weak.upgrade().is_none());
+/// # }
+/// ```
+///
+/// [`RwLock`]: struct.RwLock.html
+/// [`MutexWeak`]: struct.MutexWeak.html
+#[derive(Debug)]
+pub struct RwLockWeak<T: ?Sized> {
+    inner: sync::Weak<Inner<T>>,
+}
+
+impl<T: ?Sized> RwLockWeak<T> {
+    /// Tries to upgrade the `RwLockWeak` to `RwLock`.  If the `RwLock` was
+    /// dropped then the function returns `None`.
+    pub fn upgrade(&self) -> Option<RwLock<T>> {
+        self.inner.upgrade().map(|inner| RwLock { inner })
+    }
+}
+
+impl<T: ?Sized> Clone for RwLockWeak<T> {
+    fn clone(&self) -> RwLockWeak<T> {
+        RwLockWeak {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLockWeak<T> {}
+unsafe impl<T: ?Sized + Send> Sync for RwLockWeak<T> {}
+
+/// A Futures-aware RwLock.
+///
+/// `std::sync::RwLock` cannot be used in an asynchronous environment like
+/// Tokio, because an acquisition can block an entire reactor.  This class can
+/// be used instead.  It functions much like `std::sync::RwLock`.  Unlike that
+/// class, it also has a builtin `Arc`, making it accessible from multiple
+/// threads.  It's also safe to `clone`.  Also unlike `std::sync::RwLock`, this
+/// class only detects lock poisoning if it's built with
+/// `RwLockBuilder::poison_on_panic`.
+#[derive(Default)]
+pub struct RwLock<T: ?Sized> {
+    inner: sync::Arc<Inner<T>>,
+}
+
+impl<T: ?Sized> Clone for RwLock<T> {
+    fn clone(&self) -> RwLock<T> {
+        RwLock {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Create a new `RwLock` in the unlocked state.
+    pub fn new(t: T) -> RwLock<T> {
+        RwLock::builder().build(t)
+    }
+
+    /// Returns a builder, for creating an `RwLock` with non-default settings.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let lock = RwLock::builder()
+    ///     .name("routing table")
+    ///     .max_waiters(100)
+    ///     .build(Vec::<u32>::new());
+    /// assert_eq!(lock.name(), Some("routing table"));
+    /// # }
+    /// ```
+    pub fn builder() -> RwLockBuilder<T> {
+        RwLockBuilder {
+            config: LockConfig::default(),
+            poison_policy: PoisonPolicy::default(),
+            _data: PhantomData,
+        }
+    }
+
+    /// Installs an invariant check that runs whenever an exclusive guard is
+    /// released.  If it returns `false`, dropping the guard will panic, after
+    /// releasing the `RwLock`.  Shared guards can't break the invariant, so
+    /// they aren't checked.
+    ///
+    /// The check only runs in builds with debug assertions.  It replaces any
+    /// previous check, and applies to every clone of this `RwLock`.
+    ///
+    /// # Examples
+    /// ```should_panic
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let lock = RwLock::<Vec<u32>>::new(vec![]);
+    /// lock.debug_validate(|v| v.windows(2).all(|w| w[0] <= w[1]));
+    /// lock.try_write().unwrap().push(2);   // Ok
+    /// lock.try_write().unwrap().push(1);   // Panics
+    /// # }
+    /// ```
+    pub fn debug_validate<F>(&self, f: F)
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+        T: 'static,
+    {
+        self.inner.validator.set(f);
+    }
+
+    /// Consumes the `RwLock` and returns the wrapped data.  If the `RwLock`
+    /// still has multiple references (not necessarily locked), returns a copy
+    /// of `self` instead, along with a description of who else is using it.
+    pub fn try_unwrap(self) -> Result<T, TryUnwrapError<RwLock<T>>> {
+        match sync::Arc::try_unwrap(self.inner) {
+            Ok(inner) => Ok({
+                // `unsafe` is no longer needed as of somewhere around 1.25.0.
+                // https://github.com/rust-lang/rust/issues/35067
+                #[allow(unused_unsafe)]
+                unsafe {
+                    inner.data.into_inner()
+                }
+            }),
+            Err(arc) => {
+                let rwlock = RwLock { inner: arc };
+                let (locked, waiters) = {
+                    let lock_data = rwlock.lock_state_infallible();
+                    let locked = lock_data.state() != LockState::Unlocked;
+                    (locked, lock_data.live_waiters())
+                };
+                let handles = sync::Arc::strong_count(&rwlock.inner);
+                Err(TryUnwrapError {
+                    lock: rwlock,
+                    handles,
+                    locked,
+                    waiters,
+                })
+            }
+        }
+    }
+
+    /// Acquires the `RwLock` nonexclusively, copies the protected data into a
+    /// new `Arc`, and immediately releases the `RwLock` again.  Long-lived
+    /// consumers can work from the snapshot without blocking writers.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::<Vec<u32>>::new(vec![1, 2]);
+    /// let snapshot = rwlock.snapshot().wait().unwrap();
+    /// rwlock.try_write().unwrap().push(3);
+    /// assert_eq!(*snapshot, vec![1, 2]);
+    /// # }
+    /// ```
+    pub fn snapshot(
+        &self,
+    ) -> impl Future<Item = sync::Arc<T>, Error = AcquireError>
+    where
+        T: Clone,
+    {
+        self.read().map(|guard| RwLockReadGuard::to_arc(&guard))
+    }
+
+    /// Acquires the `RwLock` for an upgradable read, returning a clone-on-write
+    /// guard.  It reads the shared data until it's first mutated.  Then it
+    /// copies the data and works on the copy.  Use [`RwLockCowGuard::commit`]
+    /// to publish the changes.  Like an [`upgradable_read`] guard, it
+    /// coexists with ordinary readers, but not with writers or other
+    /// upgradable guards.
+    ///
+    /// [`upgradable_read`]: #method.upgradable_read
+    /// [`RwLockCowGuard::commit`]: struct.RwLockCowGuard.html#method.commit
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::<Vec<u32>>::new(vec![3, 1, 2]);
+    /// let mut guard = rwlock.read_cow().wait().unwrap();
+    /// if !guard.windows(2).all(|w| w[0] <= w[1]) {
+    ///     // Only clones the data if it actually needs sorting
+    ///     guard.sort();
+    /// }
+    /// RwLockCowGuard::commit(guard).wait().unwrap();
+    /// assert_eq!(*rwlock.try_read().unwrap(), vec![1, 2, 3]);
+    /// # }
+    /// ```
+    pub fn read_cow(
+        &self,
+    ) -> impl Future<Item = RwLockCowGuard<T>, Error = AcquireError>
+    where
+        T: Clone,
+    {
+        self.upgradable_read().map(|guard| RwLockCowGuard {
+            state: CowState::Upgradable(guard),
+            copy: None,
+        })
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Create an [`RwLockWeak`] reference to this `RwLock`.
+    ///
+    /// [`RwLockWeak`]: struct.RwLockWeak.html
+    pub fn downgrade(this: &RwLock<T>) -> RwLockWeak<T> {
+        RwLockWeak {
+            inner: sync::Arc::downgrade(&this.inner),
+        }
+    }
+
+    /// Returns true if the two `RwLock`s point to the same data, like
+    /// `Arc::ptr_eq`.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// assert!(RwLock::ptr_eq(&rwlock, &rwlock.clone()));
+    /// assert!(!RwLock::ptr_eq(&rwlock, &RwLock::new(0)));
+    /// # }
+    /// ```
+    pub fn ptr_eq(this: &RwLock<T>, other: &RwLock<T>) -> bool {
+        sync::Arc::ptr_eq(&this.inner, &other.inner)
+    }
+
+    /// Returns a reference to the underlying data, if there are no other
+    /// clones of the `RwLock`.
+    ///
+    /// Since this call borrows the `RwLock` mutably, no actual locking takes
+    /// place -- the mutable borrow statically guarantees no locks exist.
+    /// However, if the `RwLock` has already been cloned, then `None` will be
+    /// returned instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mut lock = RwLock::<u32>::new(0);
+    /// *lock.get_mut().unwrap() += 5;
+    /// assert_eq!(lock.try_unwrap().unwrap(), 5);
+    /// # }
+    /// ```
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if let Some(inner) = sync::Arc::get_mut(&mut self.inner) {
+            let policy =
+                PoisonPolicy::from_usize(*inner.poison_policy.get_mut());
+            let lock_data = match inner.mutex.get_mut() {
+                Ok(lock_data) => lock_data,
+                Err(e) if policy != PoisonPolicy::Panic => e.into_inner(),
+                Err(e) => panic!("sync::Mutex::get_mut: {:?}", e),
+            };
+            let data = unsafe { inner.data.get().as_mut() }.unwrap();
+            debug_assert!(!lock_data.exclusive);
+            debug_assert_eq!(lock_data.num_readers, 0);
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    /// Acquire the `RwLock` nonexclusively, read-only, blocking the task in the
+    /// meantime.
+    ///
+    /// When the returned `Future` is ready, then this task will have read-only
+    /// access to the protected data.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::executor::{Spawn, spawn};
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(42);
+    /// let fut = rwlock.read().map(|mut guard| { *guard });
+    /// assert_eq!(spawn(fut).wait_future(), Ok(42));
+    /// # }
+    ///
+    /// ```
+    pub fn read(&self) -> RwLockReadFut<T> {
+        RwLockReadFut::new(FutState::New, self.clone())
+    }
+
+    /// Acquire the `RwLock` exclusively, read-write, blocking the task in the
+    /// meantime.
+    ///
+    /// When the returned `Future` is ready, then this task will have read-write
+    /// access to the protected data.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::executor::{Spawn, spawn};
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(42);
+    /// let fut = rwlock.write().map(|mut guard| { *guard = 5;});
+    /// spawn(fut).wait_future().expect("spawn");
+    /// assert_eq!(rwlock.try_unwrap().unwrap(), 5);
+    /// # }
+    ///
+    /// ```
+    pub fn write(&self) -> RwLockWriteFut<T> {
+        RwLockWriteFut::new(FutState::New, self.clone())
+    }
+
+    /// Attempts to acquire the `RwLock` nonexclusively.
+    ///
+    /// If the operation would block, returns `TryLockError::WouldBlock`
+    /// instead.  Otherwise, returns a guard (not a `Future`).  With the `chaos`
+    /// feature, it may also fail spuriously; see [`chaos`](chaos/index.html).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mut lock = RwLock::<u32>::new(5);
+    /// let r = match lock.try_read() {
+    ///     Ok(guard) => *guard,
+    ///     Err(e) => panic!("Better luck next time! {}", e)
+    /// };
+    /// assert_eq!(5, r);
+    /// # }
+    /// ```
+    pub fn try_read(&self) -> Result<RwLockReadGuard<T>, TryLockError> {
+        #[cfg(feature = "chaos")]
+        {
+            if ::chaos::spurious_failure() {
+                return Err(TryLockError::WouldBlock);
+            }
+        }
+        if self.is_poisoned() {
+            return Err(TryLockError::Panicked);
+        }
+        let mut lock_data =
+            self.lock_state().map_err(|_| TryLockError::Poisoned)?;
+        if lock_data.readers_blocked(&self.inner.config) {
+            Err(TryLockError::WouldBlock)
+        } else {
+            lock_data.num_readers += 1;
+            let starved = lock_data.starved_writer();
+            drop(lock_data);
+            report_starvation(starved);
+            Ok(RwLockReadGuard {
+                rwlock: self.clone(),
+            })
+        }
+    }
+
+    /// Attempts to acquire the `RwLock` exclusively.
+    ///
+    /// If the operation would block, returns `TryLockError::WouldBlock`
+    /// instead.  Otherwise, returns a guard (not a `Future`).  With the `chaos`
+    /// feature, it may also fail spuriously; see [`chaos`](chaos/index.html).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let mut lock = RwLock::<u32>::new(5);
+    /// match lock.try_write() {
+    ///     Ok(mut guard) => *guard += 5,
+    ///     Err(e) => panic!("Better luck next time! {}", e)
+    /// }
+    /// assert_eq!(10, lock.try_unwrap().unwrap());
+    /// # }
+    /// ```
+    pub fn try_write(&self) -> Result<RwLockWriteGuard<T>, TryLockError> {
+        #[cfg(feature = "chaos")]
+        {
+            if ::chaos::spurious_failure() {
+                return Err(TryLockError::WouldBlock);
+            }
+        }
+        if self.is_poisoned() {
+            return Err(TryLockError::Panicked);
+        }
+        let mut lock_data =
+            self.lock_state().map_err(|_| TryLockError::Poisoned)?;
+        if lock_data.exclusive || lock_data.num_readers > 0 {
+            Err(TryLockError::WouldBlock)
+        } else {
+            lock_data.exclusive = true;
+            Ok(RwLockWriteGuard {
+                rwlock: self.clone(),
+            })
+        }
+    }
+
+    /// Acquires the `RwLock` nonexclusively, but with the option to upgrade to
+    /// exclusive access later without releasing it in between.  See
+    /// [`RwLockUpgradableReadGuard::upgrade`].
+    ///
+    /// The guard coexists with ordinary readers, but only one upgradable guard
+    /// may exist at a time.  Others will wait their turn.
+    ///
+    /// [`RwLockUpgradableReadGuard::upgrade`]: struct.RwLockUpgradableReadGuard.html#method.upgrade
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(42);
+    /// let guard = rwlock.upgradable_read().wait().unwrap();
+    /// assert_eq!(*rwlock.try_read().unwrap(), 42);
+    /// assert!(rwlock.try_upgradable_read().is_err());
+    /// assert!(rwlock.try_write().is_err());
+    /// # drop(guard);
+    /// # }
+    /// ```
+    pub fn upgradable_read(&self) -> RwLockUpgradableReadFut<T> {
+        RwLockUpgradableReadFut {
+            state: FutState::New,
+            rwlock: self.clone(),
+        }
+    }
+
+    /// Attempts to acquire an upgradable read guard without waiting.  See
+    /// [`upgradable_read`](#method.upgradable_read).  With the `chaos`
+    /// feature, it may also fail spuriously; see [`chaos`](chaos/index.html).
+    pub fn try_upgradable_read(
+        &self,
+    ) -> Result<RwLockUpgradableReadGuard<T>, TryLockError> {
+        #[cfg(feature = "chaos")]
+        {
+            if ::chaos::spurious_failure() {
+                return Err(TryLockError::WouldBlock);
+            }
+        }
+        if self.is_poisoned() {
+            return Err(TryLockError::Panicked);
+        }
+        let mut lock_data =
+            self.lock_state().map_err(|_| TryLockError::Poisoned)?;
+        let config = &self.inner.config;
+        if lock_data.upgradable || lock_data.readers_blocked(config) {
+            Err(TryLockError::WouldBlock)
+        } else {
+            lock_data.upgradable = true;
+            lock_data.num_readers += 1;
+            Ok(RwLockUpgradableReadGuard {
+                rwlock: self.clone(),
+            })
+        }
+    }
+
+    /// Returns the number of times that this `RwLock` has been exclusively
+    /// acquired and released.  Caches derived from the protected data can
+    /// compare versions to tell whether the data may have changed since they
+    /// were built.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let lock = RwLock::<u32>::new(0);
+    /// let v = lock.version();
+    /// drop(lock.try_read().unwrap());
+    /// assert_eq!(lock.version(), v);
+    /// *lock.try_write().unwrap() += 1;
+    /// assert_eq!(lock.version(), v + 1);
+    /// # }
+    /// ```
+    pub fn version(&self) -> u64 {
+        self.lock_state_infallible().version
+    }
+
+    /// Returns the name given to this `RwLock` by
+    /// [`RwLockBuilder::name`](struct.RwLockBuilder.html#method.name), if any.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.config.name.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns whether this `RwLock` favors readers or writers, as chosen by
+    /// [`RwLockBuilder::preference`].
+    ///
+    /// [`RwLockBuilder::preference`]: struct.RwLockBuilder.html#method.preference
+    pub fn preference(&self) -> Preference {
+        self.inner.config.preference
+    }
+
+    /// Acquires the `RwLock` nonexclusively like [`read`](#method.read), but
+    /// gives up if it can't be acquired within `timeout`.  Then, the returned
+    /// `Future` fails with [`AcquireError::TimedOut`], and leaves the wait
+    /// queue.
+    ///
+    /// Requires a Tokio timer, as provided by Tokio's runtimes.  Without one,
+    /// the `Future` times out the first time that it would have to wait.  See
+    /// [Timers](index.html#timers).
+    ///
+    /// [`AcquireError::TimedOut`]: enum.AcquireError.html#variant.TimedOut
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use std::time::Duration;
+    /// # use tokio::runtime::current_thread;
+    /// # fn main() {
+    /// let lock = RwLock::<u32>::new(0);
+    /// let _guard = lock.try_write().unwrap();
+    /// let mut rt = current_thread::Runtime::new().unwrap();
+    /// let r = rt.block_on(lock.read_timeout(Duration::from_millis(10)));
+    /// assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    /// # }
+    /// ```
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    pub fn read_timeout(&self, timeout: Duration) -> RwLockReadTimeoutFut<T> {
+        self.read_until(Instant::now() + timeout)
+    }
+
+    /// Like [`read_timeout`](#method.read_timeout), but gives up at an
+    /// absolute `deadline` instead of after a relative timeout.
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    pub fn read_until(&self, deadline: Instant) -> RwLockReadTimeoutFut<T> {
+        RwLockReadTimeoutFut {
+            fut: Some(self.read()),
+            delay: Delay::new(deadline),
         }
     }
-}
 
-impl<T> RwLock<T> {
-    /// Create a new `RwLock` in the unlocked state.
-    pub fn new(t: T) -> RwLock<T> {
-        let lock_data = RwLockData {
-            exclusive: false,
-            num_readers: 0,
-            read_waiters: VecDeque::new(),
-            write_waiters: VecDeque::new(),
-        }; // LCOV_EXCL_LINE   kcov false negative
-        let inner = Inner {
-            mutex: sync::Mutex::new(lock_data),
-            data: UnsafeCell::new(t),
-        }; // LCOV_EXCL_LINE   kcov false negative
-        RwLock {
-            inner: sync::Arc::new(inner),
-        }
+    /// Acquires the `RwLock` exclusively like [`write`](#method.write), but
+    /// gives up if it can't be acquired within `timeout`.  See
+    /// [`read_timeout`](#method.read_timeout).
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # extern crate tokio_ as tokio;
+    /// # use futures_locks::*;
+    /// # use std::time::Duration;
+    /// # use tokio::runtime::current_thread;
+    /// # fn main() {
+    /// let lock = RwLock::<u32>::new(0);
+    /// let _guard = lock.try_read().unwrap();
+    /// let mut rt = current_thread::Runtime::new().unwrap();
+    /// let r = rt.block_on(lock.write_timeout(Duration::from_millis(10)));
+    /// assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    /// # }
+    /// ```
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    pub fn write_timeout(&self, timeout: Duration) -> RwLockWriteTimeoutFut<T> {
+        self.write_until(Instant::now() + timeout)
     }
 
-    /// Consumes the `RwLock` and returns the wrapped data.  If the `RwLock`
-    /// still has multiple references (not necessarily locked), returns a copy
-    /// of `self` instead.
-    pub fn try_unwrap(self) -> Result<T, RwLock<T>> {
-        match sync::Arc::try_unwrap(self.inner) {
-            Ok(inner) => Ok({
-                // `unsafe` is no longer needed as of somewhere around 1.25.0.
-                // https://github.com/rust-lang/rust/issues/35067
-                #[allow(unused_unsafe)]
-                unsafe {
-                    inner.data.into_inner()
-                }
-            }),
-            Err(arc) => Err(RwLock { inner: arc }),
+    /// Like [`write_timeout`](#method.write_timeout), but gives up at an
+    /// absolute `deadline` instead of after a relative timeout.
+    #[cfg(feature = "timer")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "timer")))]
+    pub fn write_until(&self, deadline: Instant) -> RwLockWriteTimeoutFut<T> {
+        RwLockWriteTimeoutFut {
+            fut: Some(self.write()),
+            delay: Delay::new(deadline),
         }
     }
-}
 
-impl<T: ?Sized> RwLock<T> {
-    /// Returns a reference to the underlying data, if there are no other
-    /// clones of the `RwLock`.
+    /// Acquires the `RwLock` nonexclusively like [`read`](#method.read), but
+    /// gives up as soon as `token` completes, successfully or not.  Then the
+    /// returned `Future` fails with [`AcquireError::Cancelled`], and stops
+    /// waiting for the `RwLock`.
     ///
-    /// Since this call borrows the `RwLock` mutably, no actual locking takes
-    /// place -- the mutable borrow statically guarantees no locks exist.
-    /// However, if the `RwLock` has already been cloned, then `None` will be
-    /// returned instead.
+    /// [`AcquireError::Cancelled`]: enum.AcquireError.html#variant.Cancelled
+    pub fn read_cancellable<C>(
+        &self,
+        token: C,
+    ) -> CancellableFut<RwLockReadFut<T>, C>
+    where
+        C: Future,
+    {
+        CancellableFut::new(self.read(), token)
+    }
+
+    /// Acquires the `RwLock` exclusively like [`write`](#method.write), but
+    /// gives up as soon as `token` completes, successfully or not.  See
+    /// [`read_cancellable`](#method.read_cancellable).
     ///
     /// # Examples
-    ///
     /// ```
+    /// # extern crate futures;
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
+    /// # use futures::Future;
+    /// # use futures::sync::oneshot;
     /// # fn main() {
-    /// let mut lock = RwLock::<u32>::new(0);
-    /// *lock.get_mut().unwrap() += 5;
-    /// assert_eq!(lock.try_unwrap().unwrap(), 5);
+    /// let lock = RwLock::<u32>::new(0);
+    /// let _guard = lock.try_read().unwrap();
+    /// let (cancel, token) = oneshot::channel::<()>();
+    /// let fut = lock.write_cancellable(token);
+    /// cancel.send(()).unwrap();
+    /// assert_eq!(fut.wait().err(), Some(AcquireError::Cancelled));
     /// # }
     /// ```
-    pub fn get_mut(&mut self) -> Option<&mut T> {
-        if let Some(inner) = sync::Arc::get_mut(&mut self.inner) {
-            let lock_data = inner.mutex.get_mut().unwrap();
-            let data = unsafe { inner.data.get().as_mut() }.unwrap();
-            debug_assert!(!lock_data.exclusive);
-            debug_assert_eq!(lock_data.num_readers, 0);
-            Some(data)
-        } else {
-            None
-        }
+    pub fn write_cancellable<C>(
+        &self,
+        token: C,
+    ) -> CancellableFut<RwLockWriteFut<T>, C>
+    where
+        C: Future,
+    {
+        CancellableFut::new(self.write(), token)
     }
 
-    /// Acquire the `RwLock` nonexclusively, read-only, blocking the task in the
-    /// meantime.
+    /// Acquires the `RwLock` nonexclusively like [`read`](#method.read), but
+    /// reports whether it's poisoned, like `std::sync::RwLock::read`.  Only an
+    /// `RwLock` built with [`RwLockBuilder::poison_on_panic`] can be poisoned.
     ///
-    /// When the returned `Future` is ready, then this task will have read-only
-    /// access to the protected data.
+    /// [`RwLockBuilder::poison_on_panic`]: struct.RwLockBuilder.html#method.poison_on_panic
+    pub fn read_checked(
+        &self,
+    ) -> impl Future<Item = sync::LockResult<RwLockReadGuard<T>>, Error = AcquireError>
+    {
+        let mut fut = self.read();
+        fut.checked = true;
+        fut.map(|guard| {
+            if guard.rwlock.is_poisoned() {
+                Err(sync::PoisonError::new(guard))
+            } else {
+                Ok(guard)
+            }
+        })
+    }
+
+    /// Acquires the `RwLock` exclusively like [`write`](#method.write), but
+    /// reports whether it's poisoned, like `std::sync::RwLock::write`.
     ///
     /// # Examples
     /// ```
     /// # extern crate futures;
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
-    /// # use futures::executor::{Spawn, spawn};
     /// # use futures::Future;
+    /// # use std::thread;
     /// # fn main() {
-    /// let rwlock = RwLock::<u32>::new(42);
-    /// let fut = rwlock.read().map(|mut guard| { *guard });
-    /// assert_eq!(spawn(fut).wait_future(), Ok(42));
+    /// let lock = RwLock::builder().poison_on_panic(true).build(0u32);
+    /// let lock2 = lock.clone();
+    /// thread::spawn(move || {
+    ///     let _guard = lock2.try_write().unwrap();
+    ///     panic!("oops");
+    /// }).join().unwrap_err();
+    /// let r = lock.write_checked().wait().unwrap();
+    /// assert!(r.is_err());
+    /// drop(r);
+    /// lock.clear_poison();
+    /// assert!(lock.read_checked().wait().unwrap().is_ok());
     /// # }
+    /// ```
+    pub fn write_checked(
+        &self,
+    ) -> impl Future<
+        Item = sync::LockResult<RwLockWriteGuard<T>>,
+        Error = AcquireError,
+    > {
+        let mut fut = self.write();
+        fut.checked = true;
+        fut.map(|guard| {
+            if guard.rwlock.is_poisoned() {
+                Err(sync::PoisonError::new(guard))
+            } else {
+                Ok(guard)
+            }
+        })
+    }
+
+    /// Returns `true` if a write guard of this `RwLock` was dropped during a
+    /// panic.  See [`RwLockBuilder::poison_on_panic`].
+    ///
+    /// [`RwLockBuilder::poison_on_panic`]: struct.RwLockBuilder.html#method.poison_on_panic
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clears the `RwLock`'s poisoned state, for example after restoring its
+    /// data to a consistent state.
+    pub fn clear_poison(&self) {
+        self.inner.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the `RwLock`'s state and settings.
     ///
+    /// # Examples
     /// ```
-    pub fn read(&self) -> RwLockReadFut<T> {
-        return RwLockReadFut::new(FutState::New, self.clone());
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let lock = RwLock::<u32>::new(0);
+    /// let _guard = lock.try_read().unwrap();
+    /// let info = lock.info();
+    /// assert_eq!(info.state(), LockState::Shared);
+    /// assert_eq!(info.readers(), 1);
+    /// # }
+    /// ```
+    pub fn info(&self) -> LockInfo {
+        let config = &self.inner.config;
+        let lock_data = self.lock_state_infallible();
+        LockInfo {
+            name: config.name.clone(),
+            state: lock_data.state(),
+            readers: lock_data.num_readers,
+            waiters: lock_data.live_waiters(),
+            fairness: config.fairness,
+            max_waiters: config.max_waiters,
+            yield_budget: config.yield_budget,
+            spin_limit: 0,
+            preference: Some(config.preference),
+            max_readers: config.max_readers,
+            poison_policy: self.poison_policy(),
+            poison_on_panic: config.poison_on_panic,
+            closed: false,
+            #[cfg(feature = "stats")]
+            stats: if config.stats {
+                Some(lock_data.stats.clone())
+            } else {
+                None
+            },
+        }
     }
 
-    /// Acquire the `RwLock` exclusively, read-write, blocking the task in the
-    /// meantime.
+    /// Returns how many readers currently share the `RwLock`, including an
+    /// upgradable reader and any waiters that have been granted access but
+    /// not yet polled.  Like [`is_locked`](#method.is_locked), it never
+    /// acquires anything, and the answer may be stale by the time it's used.
     ///
-    /// When the returned `Future` is ready, then this task will have read-write
-    /// access to the protected data.
+    /// # Examples
+    /// ```
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// let _r1 = rwlock.try_read().unwrap();
+    /// let _r2 = rwlock.try_read().unwrap();
+    /// assert_eq!(rwlock.reader_count(), 2);
+    /// # }
+    /// ```
+    pub fn reader_count(&self) -> u32 {
+        self.lock_state_infallible().num_readers
+    }
+
+    /// Returns `true` if the `RwLock` is currently held exclusively, either
+    /// by a write guard or by a writer that hasn't yet been polled.
     ///
     /// # Examples
     /// ```
-    /// # extern crate futures;
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
-    /// # use futures::executor::{Spawn, spawn};
-    /// # use futures::Future;
     /// # fn main() {
-    /// let rwlock = RwLock::<u32>::new(42);
-    /// let fut = rwlock.write().map(|mut guard| { *guard = 5;});
-    /// spawn(fut).wait_future().expect("spawn");
-    /// assert_eq!(rwlock.try_unwrap().unwrap(), 5);
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// let guard = rwlock.try_write().unwrap();
+    /// assert!(rwlock.is_write_locked());
+    /// drop(guard);
+    /// assert!(!rwlock.is_write_locked());
     /// # }
+    /// ```
+    pub fn is_write_locked(&self) -> bool {
+        self.lock_state_infallible().exclusive
+    }
+
+    /// Returns `true` if the `RwLock` is currently held, either shared or
+    /// exclusively.  Unlike `try_read` or `try_write`, this never acquires the
+    /// `RwLock` nor disturbs its waiters.  The answer may be stale by the time
+    /// it's used, so it's best suited for metrics and assertions.
     ///
+    /// # Examples
     /// ```
-    pub fn write(&self) -> RwLockWriteFut<T> {
-        return RwLockWriteFut::new(FutState::New, self.clone());
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # fn main() {
+    /// let rwlock = RwLock::<u32>::new(0);
+    /// assert!(!rwlock.is_locked());
+    /// let guard = rwlock.try_read().unwrap();
+    /// assert!(rwlock.is_locked());
+    /// assert!(!rwlock.is_write_locked());
+    /// drop(guard);
+    /// assert!(!rwlock.is_locked());
+    /// # }
+    /// ```
+    pub fn is_locked(&self) -> bool {
+        self.lock_state_infallible().state() != LockState::Unlocked
     }
 
-    /// Attempts to acquire the `RwLock` nonexclusively.
+    /// Returns the policy for handling a poisoned `RwLock`.
     ///
-    /// If the operation would block, returns `Err` instead.  Otherwise, returns
-    /// a guard (not a `Future`).
+    /// See [`PoisonPolicy`](enum.PoisonPolicy.html).
+    pub fn poison_policy(&self) -> PoisonPolicy {
+        PoisonPolicy::from_usize(
+            self.inner.poison_policy.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Sets the policy for handling a poisoned `RwLock`.  It applies to every
+    /// clone of this `RwLock`.
     ///
     /// # Examples
     /// ```
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
     /// # fn main() {
-    /// let mut lock = RwLock::<u32>::new(5);
-    /// let r = match lock.try_read() {
-    ///     Ok(guard) => *guard,
-    ///     Err(()) => panic!("Better luck next time!")
-    /// };
-    /// assert_eq!(5, r);
+    /// let lock = RwLock::<u32>::new(0);
+    /// lock.set_poison_policy(PoisonPolicy::Ignore);
+    /// assert_eq!(lock.clone().poison_policy(), PoisonPolicy::Ignore);
     /// # }
     /// ```
-    pub fn try_read(&self) -> Result<RwLockReadGuard<T>, ()> {
-        let mut lock_data = self.inner.mutex.lock().expect("sync::Mutex::lock");
-        if lock_data.exclusive {
-            Err(())
-        } else {
-            lock_data.num_readers += 1;
-            Ok(RwLockReadGuard {
-                rwlock: self.clone(),
-            })
-        }
+    pub fn set_poison_policy(&self, policy: PoisonPolicy) {
+        self.inner
+            .poison_policy
+            .store(policy.to_usize(), Ordering::Relaxed);
     }
 
-    /// Attempts to acquire the `RwLock` exclusively.
+    /// Returns usage statistics for this `RwLock`.  Readers and writers share
+    /// a single queue depth.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate futures_locks;
+    /// # use futures_locks::*;
+    /// # use futures::{Future, future};
+    /// # use futures::executor::spawn;
+    /// # fn main() {
+    /// let lock = RwLock::<u32>::new(0);
+    /// let guard = lock.try_write().unwrap();
+    /// spawn(future::lazy(|| {
+    ///     let mut fut1 = lock.read();
+    ///     let mut fut2 = lock.write();
+    ///     assert!(fut1.poll().unwrap().is_not_ready());
+    ///     assert!(fut2.poll().unwrap().is_not_ready());
+    ///     future::ok::<(), ()>(())
+    /// })).wait_future().unwrap();
+    /// assert_eq!(lock.stats().max_queue_depth, 2);
+    /// # }
+    /// ```
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn stats(&self) -> LockStats {
+        self.lock_state_infallible().stats.clone()
+    }
+
+    /// Resets all of this `RwLock`'s usage statistics.
+    #[cfg(feature = "stats")]
+    #[cfg_attr(feature = "nightly-docs", doc(cfg(feature = "stats")))]
+    pub fn reset_stats(&self) {
+        self.lock_state_infallible().stats = LockStats::default();
+    }
+
+    /// Installs a hook that fires when a queued writer has waited longer than
+    /// `threshold` to acquire the `RwLock`, for example because a steady stream
+    /// of readers has kept it shared.  The hook fires at most once per writer.
     ///
-    /// If the operation would block, returns `Err` instead.  Otherwise, returns
-    /// a guard (not a `Future`).
+    /// There is no timer involved.  Starvation is only detected when a reader
+    /// acquires or releases the `RwLock`, or when a waiting writer's `Future`
+    /// gets polled again.  So a writer that's blocked behind a single
+    /// long-lived reader, and isn't otherwise polled, won't be reported until
+    /// that reader releases the `RwLock`.  The hook runs synchronously on the
+    /// detecting thread, so it should be quick.  It replaces any previous
+    /// hook, and applies to every clone of this `RwLock`.
     ///
     /// # Examples
     /// ```
     /// # extern crate futures_locks;
     /// # use futures_locks::*;
+    /// # use std::time::Duration;
     /// # fn main() {
-    /// let mut lock = RwLock::<u32>::new(5);
-    /// match lock.try_write() {
-    ///     Ok(mut guard) => *guard += 5,
-    ///     Err(()) => panic!("Better luck next time!")
-    /// }
-    /// assert_eq!(10, lock.try_unwrap().unwrap());
+    /// let lock = RwLock::<u32>::new(0);
+    /// lock.on_writer_starvation(Duration::from_secs(1), |ev| {
+    ///     eprintln!("A writer has waited {:?} behind {} readers",
+    ///               ev.waited, ev.num_readers);
+    /// });
     /// # }
     /// ```
-    pub fn try_write(&self) -> Result<RwLockWriteGuard<T>, ()> {
-        let mut lock_data = self.inner.mutex.lock().expect("sync::Mutex::lock");
-        if lock_data.exclusive || lock_data.num_readers > 0 {
-            Err(())
-        } else {
-            lock_data.exclusive = true;
-            Ok(RwLockWriteGuard {
-                rwlock: self.clone(),
-            })
-        }
+    pub fn on_writer_starvation<F>(&self, threshold: Duration, f: F)
+    where
+        F: Fn(WriterStarvation) + Send + Sync + 'static,
+    {
+        self.lock_state_infallible().starvation_hook = Some(StarvationHook {
+            threshold,
+            f: sync::Arc::new(f),
+        });
+    }
+
+    fn lock_state(
+        &self,
+    ) -> Result<sync::MutexGuard<'_, RwLockData>, AcquireError> {
+        lock_state(&self.inner.mutex, self.poison_policy())
+    }
+
+    fn lock_state_infallible(&self) -> sync::MutexGuard<'_, RwLockData> {
+        lock_state_infallible(&self.inner.mutex, self.poison_policy())
     }
 
     /// Release a shared lock of an `RwLock`.
     fn unlock_reader(&self) {
-        let mut lock_data = self.inner.mutex.lock().expect("sync::Mutex::lock");
+        let config = &self.inner.config;
+        let mut lock_data = self.lock_state_infallible();
         assert!(lock_data.num_readers > 0);
         assert!(!lock_data.exclusive);
-        assert_eq!(lock_data.read_waiters.len(), 0);
         lock_data.num_readers -= 1;
-        if lock_data.num_readers == 0 {
-            while let Some(tx) = lock_data.write_waiters.pop_front() {
+        if lock_data.num_readers == 1 && lock_data.upgrader.is_some() {
+            // Only the upgrading reader is left
+            let tx = lock_data.upgrader.take().unwrap();
+            lock_data.upgrade();
+            if tx.send(()).is_err() {
+                // The upgrade was abandoned.  Its future will release the
+                // upgradable guard.
+                lock_data.exclusive = false;
+                lock_data.upgradable = true;
+                lock_data.num_readers = 1;
+                lock_data.grant_readers(config);
+            }
+        } else if lock_data.num_readers == 0 {
+            while let Some(w) =
+                config.fairness.pop(&mut lock_data.write_waiters)
+            {
                 lock_data.exclusive = true;
-                if tx.send(()).is_err() {
+                if w.tx.send(()).is_err() {
                     lock_data.exclusive = false;
                     eprintln!("Write lock was canceled before acquired")
                 } else {
                     break;
                 }
             }
+            // Readers may have been waiting behind cancelled writers
+            lock_data.grant_readers(config);
+        } else {
+            // Make room for a reader waiting because of max_readers
+            lock_data.grant_readers(config);
+            let starved = lock_data.starved_writer();
+            drop(lock_data);
+            report_starvation(starved);
+        }
+    }
+
+    /// Release an exclusive lock of an `RwLock` on behalf of a dropped write
+    /// guard, poisoning it or checking its validator first as configured.
+    fn release_writer(&self) {
+        if self.inner.config.poison_on_panic && thread::panicking() {
+            self.inner.poisoned.store(true, Ordering::Relaxed);
+        }
+        let valid = self.inner.validator.check(self.inner.data.get());
+        self.unlock_writer();
+        if !valid {
+            panic!("RwLock invariant violated on release");
         }
     }
 
     /// Release an exclusive lock of an `RwLock`.
     fn unlock_writer(&self) {
-        let mut lock_data = self.inner.mutex.lock().expect("sync::Mutex::lock");
+        let fairness = self.inner.config.fairness;
+        let mut lock_data = self.lock_state_infallible();
         assert!(lock_data.num_readers == 0);
         assert!(lock_data.exclusive);
+        lock_data.version = lock_data.version.wrapping_add(1);
         if !lock_data.write_waiters.is_empty() {
-            while let Some(tx) = lock_data.write_waiters.pop_front() {
-                if tx.send(()).is_err() {
+            while let Some(w) = fairness.pop(&mut lock_data.write_waiters) {
+                if w.tx.send(()).is_err() {
                     eprintln!("Write lock was canceled before acquired")
                 } else {
                     return;
@@ -497,14 +2947,23 @@ impl<T: ?Sized> RwLock<T> {
             }
         }
         lock_data.exclusive = false;
-        let readers = lock_data.read_waiters.drain(..).collect::<Vec<_>>();
-        lock_data.num_readers += readers.len() as u32;
-        for tx in readers {
-            if tx.send(()).is_err() {
-                lock_data.num_readers -= 1;
-                eprintln!("Read lock was canceled before acquired")
+        lock_data.grant_readers(&self.inner.config);
+    }
+
+    /// Release an upgradable shared lock of an `RwLock`.
+    fn unlock_upgradable(&self) {
+        let mut lock_data = self.lock_state_infallible();
+        assert!(lock_data.upgradable);
+        debug_assert!(lock_data.upgrader.is_none());
+        while let Some(tx) = lock_data.upgradable_waiters.pop_front() {
+            // Pass our share directly to the next upgradable reader
+            if tx.send(()).is_ok() {
+                return;
             }
         }
+        lock_data.upgradable = false;
+        drop(lock_data);
+        self.unlock_reader();
     }
 }
 
@@ -521,6 +2980,16 @@ impl<T: 'static + ?Sized> RwLock<T> {
     /// own tasks, lest their continuations get blocked by slow stacked
     /// combinators.
     ///
+    /// # Errors
+    ///
+    /// If the `RwLock` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
     /// # Examples
     ///
     /// ```
@@ -535,7 +3004,7 @@ impl<T: 'static + ?Sized> RwLock<T> {
     /// let mut rt = Runtime::new().unwrap();
     /// let r = rt.block_on(lazy(|| {
     ///     rwlock.with_read(|mut guard| {
-    ///         Ok(*guard) as Result<u32, ()>
+    ///         Ok(*guard) as Result<u32, AcquireError>
     ///     }).unwrap()
     /// }));
     /// assert_eq!(r, Ok(5));
@@ -552,29 +3021,42 @@ impl<T: 'static + ?Sized> RwLock<T> {
         B: IntoFuture<Item = R, Error = E> + 'static,
         <B as IntoFuture>::Future: Send,
         R: Send + 'static,
-        E: Send + 'static,
+        E: From<AcquireError> + Send + 'static,
         T: Send,
     {
         let (tx, rx) = oneshot::channel::<Result<R, E>>();
         tokio_executor::DefaultExecutor::current()
             .spawn(Box::new(
-                self.read().and_then(move |data| {
-                    f(data).into_future().then(move |result| {
+                self.read()
+                    .map_err(E::from)
+                    .and_then(f)
+                    .then(move |result| {
                         // Swallow errors; there's nothing to do if the
                         // receiver got cancelled
                         let _ = tx.send(result);
                         future::ok::<(), ()>(())
-                    })
-                }), // We control the sender so we're sure it won't be dropped before
-                    // sending so we can unwrap safely
+                    }), // We control the sender, so it will only be dropped
+                        // before sending if the task was.
             ))
-            .map(|_| rx.then(Result::unwrap))
+            .map(|_| {
+                rx.then(|r| r.expect("RwLock critical section was dropped"))
+            })
     }
 
     /// Like [`with_read`](#method.with_read) but for Futures that aren't
     /// `Send`.  Spawns a new task on a single-threaded Runtime to complete the
     /// Future.
     ///
+    /// # Errors
+    ///
+    /// If the `RwLock` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
     /// # Examples
     ///
     /// ```
@@ -591,7 +3073,7 @@ impl<T: 'static + ?Sized> RwLock<T> {
     /// let mut rt = current_thread::Runtime::new().unwrap();
     /// let r = rt.block_on(lazy(|| {
     ///     rwlock.with_read_local(|mut guard| {
-    ///         Ok(**guard) as Result<u32, ()>
+    ///         Ok(**guard) as Result<u32, AcquireError>
     ///     }).unwrap()
     /// }));
     /// assert_eq!(r, Ok(5));
@@ -607,22 +3089,25 @@ impl<T: 'static + ?Sized> RwLock<T> {
         F: FnOnce(RwLockReadGuard<T>) -> B + 'static,
         B: IntoFuture<Item = R, Error = E> + 'static,
         R: 'static,
-        E: 'static,
+        E: From<AcquireError> + 'static,
     {
         let (tx, rx) = oneshot::channel::<Result<R, E>>();
         current_thread::TaskExecutor::current()
             .spawn_local(Box::new(
-                self.read().and_then(move |data| {
-                    f(data).into_future().then(move |result| {
+                self.read()
+                    .map_err(E::from)
+                    .and_then(f)
+                    .then(move |result| {
                         // Swallow errors; there's nothing to do if the
                         // receiver got cancelled
                         let _ = tx.send(result);
                         future::ok::<(), ()>(())
-                    })
-                }), // We control the sender so we're sure it won't be dropped before
-                    // sending so we can unwrap safely
+                    }), // We control the sender, so it will only be dropped
+                        // before sending if the task was.
             ))
-            .map(|_| rx.then(Result::unwrap))
+            .map(|_| {
+                rx.then(|r| r.expect("RwLock critical section was dropped"))
+            })
     }
 
     /// Acquires a `RwLock` exclusively and performs a computation on its
@@ -637,6 +3122,16 @@ impl<T: 'static + ?Sized> RwLock<T> {
     /// own tasks, lest their continuations get blocked by slow stacked
     /// combinators.
     ///
+    /// # Errors
+    ///
+    /// If the `RwLock` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
     /// # Examples
     ///
     /// ```
@@ -652,7 +3147,7 @@ impl<T: 'static + ?Sized> RwLock<T> {
     /// let r = rt.block_on(lazy(|| {
     ///     rwlock.with_write(|mut guard| {
     ///         *guard += 5;
-    ///         Ok(()) as Result<(), ()>
+    ///         Ok(()) as Result<(), AcquireError>
     ///     }).unwrap()
     /// }));
     /// assert!(r.is_ok());
@@ -670,29 +3165,42 @@ impl<T: 'static + ?Sized> RwLock<T> {
         B: IntoFuture<Item = R, Error = E> + Send + 'static,
         <B as IntoFuture>::Future: Send,
         R: Send + 'static,
-        E: Send + 'static,
+        E: From<AcquireError> + Send + 'static,
         T: Send,
     {
         let (tx, rx) = oneshot::channel::<Result<R, E>>();
         tokio_executor::DefaultExecutor::current()
             .spawn(Box::new(
-                self.write().and_then(move |data| {
-                    f(data).into_future().then(move |result| {
+                self.write()
+                    .map_err(E::from)
+                    .and_then(f)
+                    .then(move |result| {
                         // Swallow errors; there's nothing to do if the
                         // receiver got cancelled
                         let _ = tx.send(result);
                         future::ok::<(), ()>(())
-                    })
-                }), // We control the sender so we're sure it won't be dropped before
-                    // sending so we can unwrap safely
+                    }), // We control the sender, so it will only be dropped
+                        // before sending if the task was.
             ))
-            .map(|_| rx.then(Result::unwrap))
+            .map(|_| {
+                rx.then(|r| r.expect("RwLock critical section was dropped"))
+            })
     }
 
     /// Like [`with_write`](#method.with_write) but for Futures that aren't
     /// `Send`.  Spawns a new task on a single-threaded Runtime to complete the
     /// Future.
     ///
+    /// # Errors
+    ///
+    /// If the `RwLock` can't be acquired, the returned `Future` fails with the
+    /// [`AcquireError`](enum.AcquireError.html), converted into `E`.
+    ///
+    /// # Panics
+    ///
+    /// The returned `Future` will panic if the spawned task is dropped before
+    /// completing, for example because its executor shut down.
+    ///
     /// # Examples
     ///
     /// ```
@@ -710,7 +3218,7 @@ impl<T: 'static + ?Sized> RwLock<T> {
     /// let r = rt.block_on(lazy(|| {
     ///     rwlock.with_write_local(|mut guard| {
     ///         *Rc::get_mut(&mut *guard).unwrap() += 5;
-    ///         Ok(()) as Result<(), ()>
+    ///         Ok(()) as Result<(), AcquireError>
     ///     }).unwrap()
     /// }));
     /// assert!(r.is_ok());
@@ -727,22 +3235,135 @@ impl<T: 'static + ?Sized> RwLock<T> {
         F: FnOnce(RwLockWriteGuard<T>) -> B + 'static,
         B: IntoFuture<Item = R, Error = E> + 'static,
         R: 'static,
-        E: 'static,
+        E: From<AcquireError> + 'static,
     {
         let (tx, rx) = oneshot::channel::<Result<R, E>>();
         current_thread::TaskExecutor::current()
             .spawn_local(Box::new(
-                self.write().and_then(move |data| {
-                    f(data).into_future().then(move |result| {
+                self.write()
+                    .map_err(E::from)
+                    .and_then(f)
+                    .then(move |result| {
                         // Swallow errors; there's nothing to do if the
                         // receiver got cancelled
                         let _ = tx.send(result);
                         future::ok::<(), ()>(())
-                    })
-                }), // We control the sender so we're sure it won't be dropped before
-                    // sending so we can unwrap safely
+                    }), // We control the sender, so it will only be dropped
+                        // before sending if the task was.
             ))
-            .map(|_| rx.then(Result::unwrap))
+            .map(|_| {
+                rx.then(|r| r.expect("RwLock critical section was dropped"))
+            })
+    }
+}
+
+/// Creates an unlocked `RwLock`, like [`RwLock::new`].
+///
+/// [`RwLock::new`]: struct.RwLock.html#method.new
+impl<T> From<T> for RwLock<T> {
+    fn from(t: T) -> Self {
+        RwLock::new(t)
+    }
+}
+
+/// Two `RwLock`s are equal if they're clones of each other, regardless of the
+/// protected data.
+impl<T: ?Sized> PartialEq for RwLock<T> {
+    fn eq(&self, other: &RwLock<T>) -> bool {
+        RwLock::ptr_eq(self, other)
+    }
+}
+
+impl<T: ?Sized> Eq for RwLock<T> {}
+
+/// Hashes the `RwLock`'s identity, not its protected data, consistent with its
+/// `Eq` implementation.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use std::collections::HashSet;
+/// # fn main() {
+/// let a = RwLock::<u32>::new(0);
+/// let b = RwLock::<u32>::new(0);
+/// let set: HashSet<_> = vec![a.clone(), b.clone(), a.clone()]
+///     .into_iter()
+///     .collect();
+/// assert_eq!(set.len(), 2);
+/// # }
+/// ```
+impl<T: ?Sized> Hash for RwLock<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (&*self.inner as *const Inner<T> as *const u8 as usize).hash(state)
+    }
+}
+
+/// Formats the protected data, if the `RwLock` isn't currently locked
+/// exclusively.  Otherwise, formats as `<locked>`.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// let lock = RwLock::<u32>::new(42);
+/// let guard = lock.try_read().unwrap();
+/// assert_eq!(format!("{}", lock), "42");
+/// drop(guard);
+/// let _guard = lock.try_write().unwrap();
+/// assert_eq!(format!("{}", lock), "<locked>");
+/// # }
+/// ```
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peek at the data without acquiring the RwLock, so formatting it
+        // doesn't count as a release or notify anybody.  Nobody can acquire
+        // it exclusively while we hold its state.
+        match self.lock_state() {
+            Ok(ref lock_data) if !lock_data.exclusive => {
+                fmt::Display::fmt(unsafe { &*self.inner.data.get() }, f)
+            }
+            _ => f.write_str("<locked>"),
+        }
+    }
+}
+
+/// Formats the `RwLock`'s name, its state, the number of readers, and the
+/// number of tasks waiting to acquire it.  The protected data is not
+/// formatted.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// let lock = RwLock::builder().name("routes").build(0u32);
+/// let _guard = lock.try_read().unwrap();
+/// assert_eq!(
+///     format!("{:?}", lock),
+///     "RwLock { name: Some(\"routes\"), state: \"Read\", readers: 1, waiters: 0 }"
+/// );
+/// # }
+/// ```
+impl<T: ?Sized> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut dbg = f.debug_struct("RwLock");
+        dbg.field("name", &self.name());
+        match self.lock_state() {
+            Ok(lock_data) => {
+                let state = match lock_data.state() {
+                    LockState::Unlocked => "Unlocked",
+                    LockState::Shared => "Read",
+                    LockState::Exclusive => "Write",
+                };
+                dbg.field("state", &state)
+                    .field("readers", &lock_data.num_readers)
+                    .field("waiters", &lock_data.live_waiters())
+            }
+            Err(_) => dbg.field("state", &"Poisoned"),
+        }
+        .finish()
     }
 }
 
@@ -753,12 +3374,67 @@ unsafe impl<T: ?Sized + Send> Sync for RwLock<T> {}
 #[cfg(test)]
 mod t {
     use super::*;
+    use futures::executor::spawn;
+    use std::thread;
+
+    /// Poison the `RwLock`'s internal bookkeeping
+    fn poison<T: ?Sized + Send + Sync + 'static>(l: &RwLock<T>) {
+        let l = l.clone();
+        thread::spawn(move || {
+            let _data = l.inner.mutex.lock().unwrap();
+            panic!("Poison!");
+        })
+        .join()
+        .unwrap_err();
+    }
 
     /// Pet Kcov
     #[test]
     fn debug() {
         let m = RwLock::<u32>::new(0);
-        format!("{:?}", &m);
+        let _ = format!("{:?}", &m);
+    }
+
+    #[test]
+    fn poison_policy_error() {
+        let lock = RwLock::<u32>::new(0);
+        lock.set_poison_policy(PoisonPolicy::Error);
+        poison(&lock);
+        assert_eq!(
+            spawn(lock.read()).wait_future().err(),
+            Some(AcquireError::Poisoned)
+        );
+        assert_eq!(
+            spawn(lock.write()).wait_future().err(),
+            Some(AcquireError::Poisoned)
+        );
+        assert!(lock.try_read().is_err());
+        assert!(lock.try_write().is_err());
+    }
+
+    #[test]
+    fn poison_policy_ignore() {
+        let lock = RwLock::<u32>::new(0);
+        lock.set_poison_policy(PoisonPolicy::Ignore);
+        poison(&lock);
+        let guard = spawn(lock.write()).wait_future().unwrap();
+        drop(guard);
+        assert_eq!(*spawn(lock.read()).wait_future().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_eq() {
+        let lock = RwLock::<u32>::new(0);
+        assert_eq!(lock, lock.clone());
+        assert_ne!(lock, RwLock::<u32>::new(0));
+    }
+
+    #[test]
+    fn test_display() {
+        let lock = RwLock::<f32>::new(1.5);
+        assert_eq!(format!("{:.3}", lock), "1.500");
+        let _guard = spawn(lock.write()).wait_future().unwrap();
+        assert_eq!(format!("{:.3}", lock), "<locked>");
     }
 
     #[test]
@@ -0,0 +1,231 @@
+// vim: tw=80
+
+//! A `Mutex` that is owned in place, rather than shared through an `Arc`.
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync;
+use super::{AcquireError, TryLockError};
+
+#[derive(Default)]
+struct ScopedMutexData {
+    owned: bool,
+    /// Pending acquisitions, oldest first.  Each is granted the lock by
+    /// sending to it.
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+/// A `Futures`-aware mutex that doesn't use an `Arc`.  Instead, it may live
+/// directly on a task's stack or inside another struct, and its
+/// [`lock`](#method.lock) method borrows it.  Its guards can't outlive it.
+///
+/// Waiters are served in FIFO order.  Use [`Mutex`](struct.Mutex.html)
+/// instead when the lock must be shared between independently-spawned tasks.
+///
+/// # Examples
+/// ```
+/// # extern crate futures;
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # use futures::Future;
+/// # fn main() {
+/// struct Account {
+///     balance: ScopedMutex<u32>,
+/// }
+///
+/// let account = Account{balance: ScopedMutex::new(100)};
+/// let fut = account.balance.lock().map(|mut guard| *guard -= 10);
+/// fut.wait().unwrap();
+/// assert_eq!(account.balance.into_inner(), 90);
+/// # }
+/// ```
+pub struct ScopedMutex<T: ?Sized> {
+    state: sync::Mutex<ScopedMutexData>,
+    data: UnsafeCell<T>,
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ScopedMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("ScopedMutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(_) => d.field("data", &format_args!("<locked>"))
+        };
+        d.finish()
+    }
+}
+
+impl<T: Default> Default for ScopedMutex<T> {
+    fn default() -> Self {
+        ScopedMutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for ScopedMutex<T> {
+    fn from(t: T) -> Self {
+        ScopedMutex::new(t)
+    }
+}
+
+impl<T> ScopedMutex<T> {
+    /// Create a new `ScopedMutex` in the unlocked state.
+    pub fn new(t: T) -> ScopedMutex<T> {
+        ScopedMutex {
+            state: sync::Mutex::new(ScopedMutexData::default()),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    /// Consumes the `ScopedMutex` and returns the wrapped data.  Since it
+    /// isn't shared, this can't fail.
+    pub fn into_inner(self) -> T {
+        // `unsafe` is no longer needed as of somewhere around 1.25.0.
+        // https://github.com/rust-lang/rust/issues/35067
+        #[allow(unused_unsafe)]
+        unsafe { self.data.into_inner() }
+    }
+}
+
+impl<T: ?Sized> ScopedMutex<T> {
+    /// Returns a reference to the underlying data.  No locking is needed,
+    /// since the `&mut` borrow proves that nobody else is using it.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe {&mut *self.data.get()}
+    }
+
+    /// Acquires the `ScopedMutex`, returning a new `Future`.  The guard that
+    /// it yields borrows the `ScopedMutex`.
+    pub fn lock(&self) -> ScopedMutexFut<'_, T> {
+        ScopedMutexFut{mutex: self, rx: None}
+    }
+
+    /// Attempts to acquire the `ScopedMutex` without waiting.
+    pub fn try_lock(&self) -> Result<ScopedMutexGuard<'_, T>, TryLockError> {
+        let mut state = self.state();
+        if state.owned {
+            Err(TryLockError::WouldBlock)
+        } else {
+            state.owned = true;
+            Ok(ScopedMutexGuard{mutex: self})
+        }
+    }
+
+    fn state(&self) -> sync::MutexGuard<'_, ScopedMutexData> {
+        // The state's invariants hold even if a panic interrupted its last
+        // user
+        self.state.lock().unwrap_or_else(sync::PoisonError::into_inner)
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state();
+        assert!(state.owned);
+        while let Some(tx) = state.waiters.pop_front() {
+            // Send ownership to the waiter, unless it was already cancelled
+            if tx.send(()).is_ok() {
+                return;
+            }
+        }
+        state.owned = false;
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for ScopedMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for ScopedMutex<T> {}
+
+/// A `Future` representing a pending `ScopedMutex` acquisition.
+pub struct ScopedMutexFut<'a, T: ?Sized + 'a> {
+    mutex: &'a ScopedMutex<T>,
+    /// Receives ownership of the `ScopedMutex`, once this future is queued
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+impl<'a, T: ?Sized> Drop for ScopedMutexFut<'a, T> {
+    fn drop(&mut self) {
+        if let Some(ref mut rx) = self.rx {
+            rx.close();
+            if let Ok(Some(())) = rx.try_recv() {
+                // This future received ownership of the mutex, but got
+                // dropped before it was ever polled.  Release the mutex.
+                self.mutex.unlock();
+            }
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Future for ScopedMutexFut<'a, T> {
+    type Item = ScopedMutexGuard<'a, T>;
+    type Error = AcquireError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.rx.is_none() {
+            let mut state = self.mutex.state();
+            if !state.owned {
+                state.owned = true;
+                return Ok(Async::Ready(ScopedMutexGuard{mutex: self.mutex}));
+            }
+            let (tx, mut rx) = oneshot::channel::<()>();
+            state.waiters.push_back(tx);
+            // Even though we know it isn't ready, we need to poll the
+            // receiver in order to register our task for notification.
+            assert!(rx.poll().unwrap().is_not_ready());
+            self.rx = Some(rx);
+            return Ok(Async::NotReady);
+        }
+        match self.rx.as_mut().unwrap().poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => {
+                self.rx = None;
+                Ok(Async::Ready(ScopedMutexGuard{mutex: self.mutex}))
+            },
+            Err(oneshot::Canceled) => {
+                // The ScopedMutex can't be dropped while we borrow it
+                unreachable!("ScopedMutex waiter dropped")
+            }
+        }
+    }
+}
+
+/// An RAII guard for a [`ScopedMutex`](struct.ScopedMutex.html), which
+/// borrows it.  The wrapped data can be accessed via its `Deref` and
+/// `DerefMut` implementations.
+pub struct ScopedMutexGuard<'a, T: ?Sized + 'a> {
+    mutex: &'a ScopedMutex<T>,
+}
+
+impl<'a, T: ?Sized> Deref for ScopedMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.mutex.data.get()}
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for ScopedMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {&mut *self.mutex.data.get()}
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for ScopedMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Display> fmt::Display for ScopedMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for ScopedMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for ScopedMutexGuard<'a, T> {}
@@ -0,0 +1,99 @@
+// vim: tw=80
+
+//! Helpers for testing code that uses this crate's locks.
+//!
+//! An executor may drop a lock-acquiring `Future` at any point in its life.
+//! These helpers deliberately drop one at each of the stages that matter, so
+//! downstream crates can check that their cleanup logic copes with exactly
+//! the cancellation semantics that this crate implements:
+//!
+//! * [`drop_unpolled`]: never polled.  The lock is never touched.
+//! * [`drop_queued`]: polled once and queued behind the current owner.  The
+//!   waiter is skipped when ownership is handed off.
+//! * [`drop_granted`]: queued, then granted ownership, but dropped before the
+//!   executor could poll it again.  The lock is released immediately.
+//!
+//! There's no separate "mid-poll" stage: `Future::poll` is synchronous, so an
+//! executor can only drop a future between polls.  A future that was woken
+//! by a grant but dropped before its next poll is what `drop_granted` covers.
+//!
+//! Each helper polls from a private task, never from the caller's.
+//!
+//! [`drop_unpolled`]: fn.drop_unpolled.html
+//! [`drop_queued`]: fn.drop_queued.html
+//! [`drop_granted`]: fn.drop_granted.html
+
+use futures::Future;
+use futures::executor::{self, Spawn};
+use std::sync::Arc;
+use super::NoopNotify;
+
+/// Poll `fut` once in a private task, and return the task.
+///
+/// # Panics
+///
+/// Panics if `fut` completes on its first poll.
+fn queue<F: Future>(fut: F) -> Spawn<F> {
+    let mut task = executor::spawn(fut);
+    let notify = Arc::new(NoopNotify);
+    match task.poll_future_notify(&notify, 0) {
+        Ok(a) if a.is_not_ready() => task,
+        _ => panic!("Future completed immediately; is the lock free?")
+    }
+}
+
+/// Drop `fut` without ever polling it.
+pub fn drop_unpolled<F: Future>(fut: F) {
+    drop(fut);
+}
+
+/// Poll `fut` once, so it joins the lock's queue, then drop it.  The lock must
+/// already be held, in a conflicting mode.
+///
+/// # Panics
+///
+/// Panics if `fut` acquires the lock immediately.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// let mtx = Mutex::<u32>::new(0);
+/// let guard = mtx.try_lock().unwrap();
+/// test_util::drop_queued(mtx.lock());
+/// drop(guard);
+/// assert!(mtx.try_lock().is_ok());
+/// # }
+/// ```
+pub fn drop_queued<F: Future>(fut: F) {
+    drop(queue(fut));
+}
+
+/// Poll `fut` once, so it joins the lock's queue, then call `release`, which
+/// should release the lock and grant it to `fut`.  Then drop `fut` without
+/// polling it again.
+///
+/// # Panics
+///
+/// Panics if `fut` acquires the lock immediately.
+///
+/// # Examples
+/// ```
+/// # extern crate futures_locks;
+/// # use futures_locks::*;
+/// # fn main() {
+/// let lock = RwLock::<u32>::new(0);
+/// let guard = lock.try_read().unwrap();
+/// test_util::drop_granted(lock.write(), || drop(guard));
+/// assert!(lock.try_write().is_ok());
+/// # }
+/// ```
+pub fn drop_granted<F, R>(fut: F, release: R)
+    where F: Future,
+          R: FnOnce()
+{
+    let task = queue(fut);
+    release();
+    drop(task);
+}
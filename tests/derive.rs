@@ -0,0 +1,41 @@
+//vim: tw=80
+
+use futures::Future;
+use futures::executor::spawn;
+use futures_locks::*;
+
+#[derive(AsyncLocked)]
+struct State {
+    pub counter: u32,
+    #[async_locked(rwlock)]
+    pub name: String,
+    history: Vec<u32>,
+}
+
+#[derive(AsyncLocked)]
+struct Generic<T: Send> {
+    value: T,
+}
+
+#[test]
+fn derive_async_locked() {
+    let state = StateLocked::from(State {
+        counter: 0,
+        name: "foo".to_owned(),
+        history: vec![],
+    });
+    let state2 = state.clone();
+
+    let fut = state2.counter().lock().map(|mut guard| *guard += 1);
+    spawn(fut).wait_future().unwrap();
+    *state.history().try_lock().unwrap() = vec![1];
+    assert_eq!(*state.counter().try_lock().unwrap(), 1);
+    assert_eq!(&*state.name().try_read().unwrap(), "foo");
+    assert_eq!(*state2.history().try_lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn derive_async_locked_generic() {
+    let g = GenericLocked::from(Generic{value: 42u64});
+    assert_eq!(*g.value().try_lock().unwrap(), 42);
+}
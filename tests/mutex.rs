@@ -1,9 +1,18 @@
 //vim: tw=80
 
-use futures::{Future, Stream, future, lazy, stream};
+use futures::{Future, Stream, executor, future, lazy, stream};
 use futures::sync::oneshot;
+#[cfg(not(feature = "chaos"))]
+use std::any::Any;
+#[cfg(feature = "tokio")]
+use std::error::Error;
 #[cfg(feature = "tokio")]
 use std::rc::Rc;
+use std::sync::{Arc, mpsc};
+#[cfg(not(feature = "chaos"))]
+use std::sync::TryLockError;
+use std::thread;
+use std::time::Duration;
 use tokio;
 #[cfg(feature = "tokio")]
 use tokio::runtime;
@@ -111,12 +120,12 @@ fn lock_contested() {
     let result = rt.block_on(lazy(|| {
         let (tx0, rx0) = oneshot::channel::<()>();
         let (tx1, rx1) = oneshot::channel::<()>();
-        let task0 = mutex.lock()
+        let task0 = mutex.lock().map_err(|_| ())
             .and_then(move |mut guard| {
                 *guard += 5;
                 rx0.map_err(|_| {drop(guard);})
             });
-        let task1 = mutex.lock().map(|guard| *guard);
+        let task1 = mutex.lock().map_err(|_| ()).map(|guard| *guard);
         // Readying task2 before task1 causes Tokio to poll the latter even
         // though it's not ready
         let task2 = rx1.map_err(|_| ()).map(|_| tx0.send(()).unwrap());
@@ -142,15 +151,19 @@ fn lock_multithreaded() {
     let parent = lazy(move || {
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             mtx_clone0.lock().map(|mut guard| { *guard += 2 })
+                .map_err(|_| ())
         }));
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             mtx_clone1.lock().map(|mut guard| { *guard += 3 })
+                .map_err(|_| ())
         }));
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             mtx_clone2.lock().map(|mut guard| { *guard += 5 })
+                .map_err(|_| ())
         }));
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             mtx_clone3.lock().map(|mut guard| { *guard += 7 })
+                .map_err(|_| ())
         }));
         future::ok::<(), ()>(())
     });
@@ -175,6 +188,7 @@ fn lock_order() {
 }
 
 // Acquire an uncontested Mutex with try_lock
+#[cfg(not(feature = "chaos"))]
 #[test]
 fn try_lock_uncontested() {
     let mutex = Mutex::<u32>::new(5);
@@ -184,6 +198,7 @@ fn try_lock_uncontested() {
 }
 
 // Try and fail to acquire a contested Mutex with try_lock
+#[cfg(not(feature = "chaos"))]
 #[test]
 fn try_lock_contested() {
     let mutex = Mutex::<u32>::new(0);
@@ -209,11 +224,11 @@ fn with_err() {
             if *guard > 0 {
                 Ok(*guard)
             } else {
-                Err("Whoops!")
+                Err(Box::<dyn Error + Send + Sync>::from("Whoops!"))
             }
         }).unwrap()
     }));
-    assert_eq!(r, Err("Whoops!"));
+    assert_eq!(r.unwrap_err().to_string(), "Whoops!");
 }
 
 #[cfg(feature = "tokio")]
@@ -223,12 +238,65 @@ fn with_ok() {
     let mut rt = current_thread::Runtime::new().unwrap();
     let r = rt.block_on(lazy(move || {
         mtx.with(|guard| {
-            Ok(*guard) as Result<i32, ()>
+            Ok(*guard) as Result<i32, AcquireError>
         }).unwrap()
     }));
     assert_eq!(r, Ok(5));
 }
 
+// with_catch should pass through the critical section's own result
+#[cfg(feature = "tokio")]
+#[test]
+fn with_catch_err() {
+    let mtx = Mutex::<i32>::new(-5);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let r = rt.block_on(lazy(|| {
+        mtx.with_catch(|_guard| -> Result<(), Box<dyn Error + Send + Sync>> {
+            Err("Whoops!".into())
+        }).unwrap()
+    }));
+    assert_eq!(r.ok().unwrap().unwrap_err().to_string(), "Whoops!");
+}
+
+// with_catch should catch a panic in the returned Future, not just in the
+// closure, and release the Mutex to the next waiter
+#[cfg(feature = "tokio")]
+#[test]
+fn with_catch_future_panic() {
+    let mtx = Mutex::<i32>::new(5);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let r = rt.block_on(lazy(|| {
+        mtx.with_catch(|mut guard| {
+            *guard += 1;
+            future::lazy(|| -> Result<(), AcquireError> { panic!("Whoops!") })
+        }).unwrap()
+    }));
+    assert_eq!(r.unwrap_err().downcast_ref(), Some(&"Whoops!"));
+    assert_eq!(rt.block_on(mtx.lock()).map(|guard| *guard), Ok(6));
+}
+
+// A panic caught by with_catch should poison the Mutex only if it was built
+// with poison_on_panic
+#[cfg(feature = "tokio")]
+#[test]
+fn with_catch_poison() {
+    let plain = Mutex::<i32>::new(0);
+    let poisonable = Mutex::builder().poison_on_panic(true).build(0i32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    for mtx in &[&plain, &poisonable] {
+        let r = rt.block_on(lazy(|| {
+            mtx.with_catch(|mut guard| -> Result<(), AcquireError> {
+                *guard += 1;
+                panic!("Whoops!")
+            }).unwrap()
+        }));
+        assert!(r.is_err());
+    }
+    assert_eq!(rt.block_on(plain.lock()).map(|guard| *guard), Ok(1));
+    assert_eq!(rt.block_on(poisonable.lock()).err(),
+               Some(AcquireError::Panicked));
+}
+
 // Mutex::with should work with multithreaded Runtimes as well as
 // single-threaded Runtimes.
 // https://github.com/asomers/futures-locks/issues/5
@@ -239,7 +307,7 @@ fn with_threadpool() {
     let mut rt = runtime::Runtime::new().unwrap();
     let r = rt.block_on(lazy(move || {
         mtx.with(|guard| {
-            Ok(*guard) as Result<i32, ()>
+            Ok(*guard) as Result<i32, AcquireError>
         }).unwrap()
     }));
     assert_eq!(r, Ok(5));
@@ -253,8 +321,1382 @@ fn with_local_ok() {
     let mut rt = current_thread::Runtime::new().unwrap();
     let r = rt.block_on(lazy(move || {
         mtx.with_local(|guard| {
-            Ok(**guard) as Result<i32, ()>
+            Ok(**guard) as Result<i32, AcquireError>
         }).unwrap()
     }));
     assert_eq!(r, Ok(5));
 }
+
+// If the Mutex can't be acquired, with should fail rather than panic
+#[cfg(feature = "tokio")]
+#[test]
+fn with_closed() {
+    let mtx = Mutex::<i32>::new(5);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    mtx.close();
+    let r = rt.block_on(lazy(|| {
+        mtx.with(|guard| Ok(*guard) as Result<i32, AcquireError>).unwrap()
+    }));
+    assert_eq!(r, Err(AcquireError::Closed));
+}
+
+// A task that tries to lock a Mutex it already holds should panic rather than
+// hang forever
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+#[test]
+#[should_panic(expected = "Deadlock detected")]
+fn lock_self_deadlock() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    rt.block_on(lazy(|| {
+        mutex.lock().and_then(|guard| {
+            mutex.lock().map(move |_| drop(guard))
+        })
+    })).unwrap();
+}
+
+// Locking a Mutex held by a different task is not a self-deadlock
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+#[test]
+fn lock_held_by_other_task() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = mutex.try_lock().unwrap();
+    let r = rt.block_on(lazy(|| {
+        let (tx, rx) = oneshot::channel::<()>();
+        current_thread::spawn(rx.map_err(|_| ()).map(move |_| drop(guard)));
+        tx.send(()).unwrap();
+        mutex.lock().map(|guard| *guard)
+    }));
+    assert_eq!(r, Ok(0));
+}
+
+// Waiting on a Mutex held by a joined future of the same task is not a
+// self-deadlock, so long as that future makes progress
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+#[test]
+fn lock_held_by_joined_future() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let r = rt.block_on(lazy(|| {
+        mutex.lock().map_err(|_| ()).and_then(|guard| {
+            let (tx, rx) = oneshot::channel::<()>();
+            let holder = rx.map_err(|_| ()).map(move |_| drop(guard));
+            let waiter = mutex.lock().map_err(|_| ()).map(|guard| *guard);
+            let sender = lazy(move || tx.send(()));
+            holder.join3(waiter, sender).map(|(_, v, _)| v)
+        })
+    }));
+    assert_eq!(r, Ok(0));
+}
+
+// The high-water mark survives the queue draining, until it gets reset
+#[cfg(all(feature = "stats", not(feature = "chaos")))]
+#[test]
+fn stats_max_queue_depth() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    assert_eq!(mutex.stats().max_queue_depth, 0);
+    let guard = mutex.try_lock().unwrap();
+    let (fut1, fut2) = rt.block_on(lazy(|| {
+        let mut fut1 = mutex.lock();
+        let mut fut2 = mutex.lock();
+        assert!(fut1.poll().unwrap().is_not_ready());
+        assert!(fut2.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>((fut1, fut2))
+    })).unwrap();
+    drop(guard);
+    let r = rt.block_on(fut1.and_then(|guard1| {
+        drop(guard1);
+        fut2.map(|guard2| *guard2)
+    }));
+    assert_eq!(r, Ok(0));
+    assert_eq!(mutex.stats().max_queue_depth, 2);
+    mutex.reset_stats();
+    assert_eq!(mutex.stats().max_queue_depth, 0);
+}
+
+// A failed invariant check panics, but still releases the Mutex
+#[cfg(all(debug_assertions, not(feature = "chaos")))]
+#[test]
+fn debug_validate_releases() {
+    let mutex = Mutex::<u32>::new(0);
+    mutex.debug_validate(|x| *x < 10);
+    let mutex2 = mutex.clone();
+
+    let r = thread::spawn(move || {
+        *mutex2.try_lock().unwrap() = 11;
+    }).join();
+    assert!(r.is_err());
+    let mut guard = mutex.try_lock().unwrap();
+    assert_eq!(*guard, 11);
+    *guard = 5;
+}
+
+// A contended acquisition records how long it waited
+#[cfg(all(feature = "metrics-histogram", not(feature = "chaos")))]
+#[test]
+fn wait_times_contested() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = mutex.try_lock().unwrap();
+    let fut = rt.block_on(lazy(|| {
+        let mut fut = mutex.lock();
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(fut)
+    })).unwrap();
+    thread::sleep(Duration::from_millis(10));
+    drop(guard);
+    rt.block_on(fut).unwrap();
+    let wait_times = mutex.stats().wait_times;
+    assert_eq!(wait_times.len(), 1);
+    assert!(wait_times.max() >= 10_000_000);
+    mutex.reset_stats();
+    assert_eq!(mutex.stats().wait_times.len(), 0);
+}
+
+// Dropping a queued acquisition must not prevent the next waiter from getting
+// ownership, nor leave the Mutex locked
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn drop_queued_then_unlock() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = mutex.try_lock().unwrap();
+    let fut = rt.block_on(lazy(|| {
+        let mut fut = mutex.lock();
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(fut)
+    })).unwrap();
+    drop(fut);
+    drop(guard);
+    assert!(mutex.try_lock().is_ok());
+}
+
+#[cfg(all(feature = "test-util", not(feature = "chaos")))]
+#[test]
+fn test_util_drop_at_each_stage() {
+    let mutex = Mutex::<u32>::new(0);
+
+    test_util::drop_unpolled(mutex.lock());
+    assert!(mutex.try_lock().is_ok());
+
+    let guard = mutex.try_lock().unwrap();
+    test_util::drop_queued(mutex.lock());
+    drop(guard);
+    assert!(mutex.try_lock().is_ok());
+
+    let guard = mutex.try_lock().unwrap();
+    test_util::drop_granted(mutex.lock(), || drop(guard));
+    assert!(mutex.try_lock().is_ok());
+}
+
+// Tasks that multi_lock! the same Mutexes in opposite orders must not deadlock
+#[test]
+fn multi_lock_opposite_orders() {
+    let a = Mutex::<u32>::new(0);
+    let b = Mutex::<u32>::new(0);
+    let c = Mutex::<u32>::new(0);
+    let (a0, b0, c0) = (a.clone(), b.clone(), c.clone());
+    let (a1, b1, c1) = (a.clone(), b.clone(), c.clone());
+
+    let parent = lazy(move || {
+        tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
+            multi_lock!(a0, b0, c0).map(|(mut ga, mut gb, mut gc)| {
+                *ga += 1;
+                *gb += 2;
+                *gc += 3;
+            }).map_err(|_| ())
+        }));
+        tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
+            multi_lock!(c1, b1, a1).map(|(mut gc, mut gb, mut ga)| {
+                *ga += 1;
+                *gb += 2;
+                *gc += 3;
+            }).map_err(|_| ())
+        }));
+        future::ok::<(), ()>(())
+    });
+
+    tokio::run(parent);
+    assert_eq!(a.try_unwrap().expect("try_unwrap"), 2_000);
+    assert_eq!(b.try_unwrap().expect("try_unwrap"), 4_000);
+    assert_eq!(c.try_unwrap().expect("try_unwrap"), 6_000);
+}
+
+// Tasks that lock_both the same Mutexes in opposite orders must not deadlock
+#[test]
+fn lock_both_opposite_orders() {
+    let a = Mutex::<u32>::new(0);
+    let b = Mutex::<u32>::new(0);
+    let (a0, b0) = (a.clone(), b.clone());
+    let (a1, b1) = (a.clone(), b.clone());
+
+    let parent = lazy(move || {
+        tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
+            Mutex::lock_both(&a0, &b0).map(|(mut ga, mut gb)| {
+                *ga += 1;
+                *gb += 2;
+            }).map_err(|_| ())
+        }));
+        tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
+            Mutex::lock_both(&b1, &a1).map(|(mut gb, mut ga)| {
+                *ga += 1;
+                *gb += 2;
+            }).map_err(|_| ())
+        }));
+        future::ok::<(), ()>(())
+    });
+
+    tokio::run(parent);
+    assert_eq!(a.try_unwrap().expect("try_unwrap"), 2_000);
+    assert_eq!(b.try_unwrap().expect("try_unwrap"), 4_000);
+}
+
+// Dropping a partially-complete lock_all should release what it acquired
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_all_dropped() {
+    let shards = (0..3).map(Mutex::<u32>::new).collect::<Vec<_>>();
+    let mut sorted = shards.clone();
+    sorted.sort_by_key(|m| m.try_lock().map(|g| &*g as *const u32).unwrap());
+    let guard = sorted[2].try_lock().unwrap();
+    let fut = Mutex::lock_all(&shards);
+    let mut task = executor::spawn(fut);
+    let notify = Arc::new(NoopNotify);
+    assert!(task.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+    assert!(sorted[0].try_lock().is_err());
+    assert!(sorted[1].try_lock().is_err());
+    drop(task);
+    assert!(sorted[0].try_lock().is_ok());
+    assert!(sorted[1].try_lock().is_ok());
+    drop(guard);
+    let guards = Mutex::lock_all(&shards).wait().unwrap();
+    assert_eq!(guards.iter().map(|g| **g).collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+#[test]
+#[should_panic(expected = "duplicate Mutex")]
+fn multi_lock_duplicate() {
+    let a = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let _ = rt.block_on(multi_lock!(a, a.clone()));
+}
+
+// A downcast guard should release the Mutex when dropped, waking waiters
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn downcast_release() {
+    let mtx: Mutex<dyn Any + Send> = Mutex::<u32>::new(0).into();
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = MutexGuard::downcast::<String>(mtx.try_lock().unwrap())
+        .err().unwrap();
+    let mut guard = MutexGuard::downcast::<u32>(guard).ok().unwrap();
+    let fut = mtx.lock().map(|guard| {
+        *MutexGuard::downcast_ref::<u32>(&guard).unwrap()
+    });
+    *guard += 5;
+    drop(guard);
+    assert_eq!(rt.block_on(fut), Ok(5));
+}
+
+// Downcasting a guard shouldn't leak a handle to the Mutex
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn downcast_no_leak() {
+    let mtx = Mutex::<u32>::new(0);
+    let any: Mutex<dyn Any + Send> = mtx.clone().into();
+
+    let guard = MutexGuard::downcast::<u32>(any.try_lock().unwrap());
+    drop(guard);
+    drop(any);
+    assert_eq!(mtx.try_unwrap().ok(), Some(0));
+}
+
+// An unsized Mutex should share its data and handle count with the original
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn unsize_shared() {
+    let mtx = Mutex::<[u32; 2]>::new([0, 0]);
+    let slice: Mutex<[u32]> = mtx.clone().into();
+    slice.try_lock().unwrap()[1] = 5;
+    assert_eq!(*mtx.try_lock().unwrap(), [0, 5]);
+    drop(slice);
+    assert_eq!(mtx.try_unwrap().ok(), Some([0, 5]));
+}
+
+// The Mutex should stay locked until every chunk of a split guard is dropped
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn split_chunks_release() {
+    let mtx = Mutex::<Vec<u32>>::new(vec![0; 10]);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let mut chunks = MutexGuard::split_chunks(mtx.try_lock().unwrap(), 4);
+    assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+               vec![4, 4, 2]);
+    let fut = mtx.lock().map(|guard| guard.clone());
+    let last = chunks.pop().unwrap();
+    let threads = chunks.into_iter().enumerate().map(|(i, mut chunk)| {
+        thread::spawn(move || {
+            for x in chunk.iter_mut() {
+                *x = i as u32 + 1;
+            }
+        })
+    }).collect::<Vec<_>>();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert!(mtx.try_lock().is_err());
+    drop(last);
+    assert_eq!(rt.block_on(fut), Ok(vec![1, 1, 1, 1, 2, 2, 2, 2, 0, 0]));
+}
+
+// Spinning acquisitions should still be mutually exclusive, and should fall
+// back to the wait queue when the Mutex stays locked
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn spin_limit_contended() {
+    let mtx = Mutex::builder().spin_limit(1000).build(0u32);
+    let threads = (0..4).map(|_| {
+        let mtx = mtx.clone();
+        thread::spawn(move || {
+            for _ in 0..1000 {
+                *mtx.lock().wait().unwrap() += 1;
+            }
+        })
+    }).collect::<Vec<_>>();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(*mtx.try_lock().unwrap(), 4000);
+
+    let guard = mtx.try_lock().unwrap();
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let fut = rt.block_on(lazy(|| {
+        let mut fut = mtx.lock();
+        assert!(fut.poll().unwrap().is_not_ready());
+        Ok::<_, ()>(fut)
+    })).unwrap();
+    assert_eq!(mtx.waiter_count(), 1);
+    drop(guard);
+    assert_eq!(rt.block_on(fut).map(|guard| *guard), Ok(4000));
+}
+
+// update_and should release the Mutex before yielding its result
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn update_and_releases() {
+    let mtx = Mutex::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = mtx.try_lock().unwrap();
+    let mtx2 = mtx.clone();
+    let fut = mtx.update_and(|x| { *x *= 10; *x })
+        .and_then(move |x| mtx2.update(move |y| y + x));
+    drop(guard);
+    assert_eq!(rt.block_on(fut), Ok(()));
+    assert_eq!(mtx.try_unwrap().unwrap(), 20);
+}
+
+// queue_position should track a waiter's progress through the queue
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn queue_position() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+
+    let mut fut1 = mtx.lock();
+    let mut fut2 = mtx.lock();
+    assert_eq!(fut1.queue_position(), None);
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    assert_eq!(fut2.queue_position(), Some(1));
+    assert_eq!(format!("{:?}", fut2),
+               "MutexFut { state: \"Pending\", queue_position: Some(1) }");
+    drop(guard);
+    assert_eq!(fut1.queue_position(), None);
+    assert_eq!(fut2.queue_position(), Some(0));
+    let guard1 = fut1.poll_once().unwrap();
+    assert!(guard1.is_ready());
+    drop(guard1);
+    assert!(fut2.poll_once().unwrap().is_ready());
+}
+
+// A LIFO Mutex should be handed to the most recent waiter first
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn builder_lifo() {
+    let mutex = Mutex::builder().fairness(Fairness::Lifo).build(Vec::new());
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    let mut fut2 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    assert_eq!(fut1.queue_position(), Some(1));
+    drop(guard);
+    fut2.map(|mut g| g.push(2)).wait().unwrap();
+    fut1.map(|mut g| g.push(1)).wait().unwrap();
+    assert_eq!(mutex.try_unwrap().unwrap(), vec![2, 1]);
+}
+
+// Waiters should be served by priority, and by arrival within a priority
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_with_priority() {
+    let mutex = Mutex::<Vec<u32>>::new(Vec::new());
+    let guard = mutex.try_lock().unwrap();
+
+    let mut futs = vec![
+        (1, mutex.lock_with_priority(1)),
+        (0, mutex.lock()),
+        (2, mutex.lock_with_priority(2)),
+        (3, mutex.lock_with_priority(1)),
+    ];
+    for &mut (_, ref mut fut) in futs.iter_mut() {
+        assert!(fut.poll_once().unwrap().is_not_ready());
+    }
+    drop(guard);
+    future::join_all(futs.into_iter().map(|(i, fut)| {
+        fut.map(move |mut g| g.push(i))
+    })).wait().unwrap();
+    assert_eq!(mutex.try_unwrap().unwrap(), vec![2, 1, 3, 0]);
+}
+
+// unlock_fair should serve the oldest waiter, even for a LIFO Mutex
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn unlock_fair_lifo() {
+    let mutex = Mutex::builder().fairness(Fairness::Lifo).build(Vec::new());
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    let mut fut2 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    MutexGuard::unlock_fair(guard);
+    let mut guard1 = fut1.wait().ok().unwrap();
+    guard1.push(1);
+    // Once it's been served, the Mutex's own fairness applies again
+    let mut fut3 = mutex.lock();
+    assert!(fut3.poll_once().unwrap().is_not_ready());
+    drop(guard1);
+    fut3.map(|mut g| g.push(3)).wait().unwrap();
+    fut2.map(|mut g| g.push(2)).wait().unwrap();
+    assert_eq!(mutex.try_unwrap().unwrap(), vec![1, 3, 2]);
+}
+
+// With barging, a new acquisition may overtake a woken waiter, which then
+// keeps its place at the head of the queue
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn fairness_barging() {
+    let mutex = Mutex::builder().fairness(Fairness::Barging)
+        .build(Vec::new());
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    let mut fut2 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    drop(guard);
+    assert!(!mutex.is_locked());
+    let mut barger = mutex.try_lock().unwrap();
+    barger.push(0);
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert_eq!(fut1.queue_position(), Some(0));
+    drop(barger);
+    fut1.map(|mut g| g.push(1)).wait().unwrap();
+    fut2.map(|mut g| g.push(2)).wait().unwrap();
+    assert_eq!(mutex.try_unwrap().unwrap(), vec![0, 1, 2]);
+}
+
+// A woken barging waiter was already admitted, so it shouldn't be refused for
+// a full queue when it has to requeue
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn fairness_barging_max_waiters() {
+    let mutex = Mutex::builder().fairness(Fairness::Barging)
+        .max_waiters(1)
+        .build(0u32);
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    drop(guard);
+    let barger = mutex.try_lock().unwrap();
+    let mut fut2 = mutex.lock();
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert_eq!(fut1.queue_position(), Some(0));
+    drop(barger);
+    assert!(fut1.wait().is_ok());
+}
+
+// With barging, release_async should wait until the woken waiter actually
+// takes the Mutex
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn fairness_barging_release_async() {
+    let mutex = Mutex::builder().fairness(Fairness::Barging).build(0u32);
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    let mut released = executor::spawn(MutexGuard::release_async(guard));
+    let notify = Arc::new(NoopNotify);
+    assert!(released.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+    let guard1 = fut1.wait().unwrap();
+    assert!(released.poll_future_notify(&notify, 0).unwrap().is_ready());
+    drop(guard1);
+}
+
+// A woken barging waiter that gives up should pass the wakeup on
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn fairness_barging_dropped() {
+    let mutex = Mutex::builder().fairness(Fairness::Barging).build(0u32);
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    let fut2 = mutex.lock();
+    let mut task2 = executor::spawn(fut2);
+    let notify = Arc::new(NoopNotify);
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(task2.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+    drop(guard);
+    drop(fut1);
+    assert!(task2.poll_future_notify(&notify, 0).unwrap().is_ready());
+}
+
+// Acquisitions beyond max_waiters should fail, but dropped waiters shouldn't
+// count against the limit
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn builder_max_waiters() {
+    let mutex = Mutex::builder().max_waiters(1).build(0u32);
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut1 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert_eq!(mutex.lock().now_or_never().err(),
+               Some(AcquireError::QueueFull));
+    drop(fut1);
+    let mut fut2 = mutex.lock();
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    drop(guard);
+    assert!(fut2.poll_once().unwrap().is_ready());
+}
+
+#[cfg(all(feature = "stats", not(feature = "chaos")))]
+#[test]
+fn builder_stats_disabled() {
+    let mutex = Mutex::builder().stats(false).build(0u32);
+    let guard = mutex.try_lock().unwrap();
+
+    let mut fut = mutex.lock();
+    assert!(fut.poll_once().unwrap().is_not_ready());
+    drop(guard);
+    fut.wait().unwrap();
+    assert_eq!(mutex.stats().max_queue_depth, 0);
+}
+
+// lock_some should keep waiting through releases that leave the Mutex empty
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_some_waits() {
+    let slot = Mutex::<Option<u32>>::new(None);
+    let slot2 = slot.clone();
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let fut = slot.lock_some().map(|guard| *guard);
+    let setter = slot.update(|_| None)
+        .and_then(move |_| slot2.update(|_| Some(7)));
+    assert_eq!(rt.block_on(fut.join(setter)), Ok((7, ())));
+    assert!(slot.try_lock().is_ok());
+}
+
+// lock_during should hold the Mutex until its Future completes
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_during_holds_lock() {
+    let mtx = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let (tx, rx) = oneshot::channel::<u32>();
+
+    let fut = rt.block_on(lazy(|| {
+        let mut fut = mtx.lock_during(move |x| {
+            *x += 1;
+            rx.map_err(|_| AcquireError::Poisoned)
+        });
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(fut)
+    })).unwrap();
+    assert!(mtx.try_lock().is_err());
+    tx.send(5).unwrap();
+    assert_eq!(rt.block_on(fut), Ok(5));
+    assert_eq!(*mtx.try_lock().unwrap(), 1);
+}
+
+// A transaction that's abandoned by an error shouldn't publish its changes
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn transaction_error_discards() {
+    let mtx = Mutex::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let fut = mtx.lock_transactional(TransactionMode::Rollback)
+        .map_err(|_| "acquire")
+        .and_then(|mut txn| {
+            *txn = 2;
+            future::err::<(), _>("failed")
+                .map(|_| TransactionGuard::commit(txn))
+        });
+    assert_eq!(rt.block_on(fut), Err("failed"));
+    assert_eq!(*mtx.try_lock().unwrap(), 1);
+}
+
+// A MappedMutex's waiters should queue along with the parent Mutex's
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn project_shares_queue() {
+    let mtx = Mutex::<(u32, u32)>::new((0, 0));
+    let second = mtx.project(|pair| &mut pair.1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = mtx.try_lock().unwrap();
+    let fut = rt.block_on(lazy(|| {
+        let mut fut = second.lock();
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(fut)
+    })).unwrap();
+    drop(guard);
+    let r = rt.block_on(fut.map(|mut guard| {
+        *guard += 1;
+        *guard
+    }));
+    assert_eq!(r, Ok(1));
+    assert_eq!(mtx.try_unwrap().ok(), None);
+    assert_eq!(*second.try_lock().unwrap(), 1);
+}
+
+// A subscriber should be woken by a release in another task
+#[test]
+fn changed_wakes() {
+    let mtx = Mutex::<u32>::new(0);
+    let mtx2 = mtx.clone();
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let (tx, rx) = oneshot::channel::<()>();
+    let watcher = mtx.changed().and_then(move |_| {
+        mtx2.lock().map(|guard| *guard)
+    });
+    rt.spawn(rx.map_err(|_| ()).and_then(move |_| {
+        mtx.update(|x| x + 1).map_err(|_| ())
+    }));
+    let r = rt.block_on(lazy(move || {
+        tx.send(()).unwrap();
+        watcher
+    }));
+    assert_eq!(r, Ok(1));
+}
+
+// A changes Stream shouldn't keep its Mutex alive, and should end once the
+// last clone is dropped, even if it's already waiting
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn changes_end() {
+    let mtx = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let changes = mtx.changes();
+    drop(mtx.try_lock().unwrap());
+    let (change, changes) = rt.block_on(changes.into_future())
+        .ok().unwrap();
+    assert_eq!(change, Some(()));
+    let (tx, rx) = oneshot::channel::<()>();
+    rt.spawn(rx.map_err(|_| ()).map(move |_| drop(mtx)));
+    let r = rt.block_on(lazy(move || {
+        tx.send(()).unwrap();
+        changes.collect()
+    }));
+    assert_eq!(r, Ok(vec![]));
+}
+
+// wait_until should only resolve once the predicate holds, and should leave
+// the Mutex free for other tasks while it waits
+#[test]
+fn wait_until_predicate() {
+    let mtx = Mutex::<u32>::new(0);
+    let mtx2 = mtx.clone();
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let waiter = mtx.wait_until(|x| *x == 3).map(|guard| *guard);
+    let incrementer = stream::iter_ok::<_, AcquireError>(0..3)
+        .for_each(move |_| mtx2.update(|x| x + 1));
+    assert_eq!(rt.block_on(waiter.join(incrementer)), Ok((3, ())));
+}
+
+// Debug output should identify the Mutex and count only live waiters
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn debug_named() {
+    let mutex = Mutex::builder().name("cache").build(0u32);
+    assert_eq!(format!("{:?}", mutex),
+               "Mutex { name: Some(\"cache\"), state: \"Unlocked\", waiters: 0 }");
+    let guard = mutex.try_lock().unwrap();
+    let mut fut1 = mutex.lock();
+    let mut fut2 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    drop(fut2);
+    assert_eq!(format!("{:?}", mutex),
+               "Mutex { name: Some(\"cache\"), state: \"Locked\", waiters: 1 }");
+    drop(guard);
+}
+
+// A downgraded guard should keep the Mutex locked until it's dropped
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn downgrade_guard_releases() {
+    let mutex = Mutex::<u32>::new(0);
+    let mut guard = mutex.try_lock().unwrap();
+    *guard += 1;
+    let ro = MutexGuard::downgrade(guard);
+    let mut fut = mutex.lock();
+    assert!(fut.poll_once().unwrap().is_not_ready());
+    assert_eq!(*ro, 1);
+    drop(ro);
+    assert_eq!(*fut.wait().ok().unwrap(), 1);
+}
+
+// A BlockingAdapter should contend with asynchronous users of the same Mutex
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn blocking_adapter_shares_lock() {
+    let mutex = Mutex::<u32>::new(0);
+    let blocking = BlockingAdapter::from(mutex.clone());
+    let guard = mutex.try_lock().unwrap();
+    match blocking.try_lock() {
+        Err(TryLockError::WouldBlock) => (),
+        _ => panic!("try_lock should've failed")
+    }
+    let blocking2 = blocking.clone();
+    let t = thread::spawn(move || {
+        *blocking2.lock().unwrap() += 1;
+    });
+    drop(guard);
+    t.join().unwrap();
+    assert_eq!(*blocking.try_lock().unwrap(), 1);
+    assert!(Mutex::ptr_eq(blocking.mutex(), &mutex));
+}
+
+// A HoldLimitGuard should release its Mutex once the limit expires, even
+// though the guard itself is still alive
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn hold_limit_expires() {
+    let mtx = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let limited = rt.block_on(lazy(|| {
+        let guard = mtx.try_lock().unwrap();
+        MutexGuard::with_hold_limit(guard, Duration::from_millis(10))
+    })).ok().unwrap();
+    assert_eq!(HoldLimitGuard::with(&limited, |x| { *x += 1; *x }), Some(1));
+    let guard = rt.block_on(mtx.lock()).ok().unwrap();
+    assert!(HoldLimitGuard::is_expired(&limited));
+    assert_eq!(HoldLimitGuard::with(&limited, |x| *x), None);
+    assert_eq!(*guard, 1);
+}
+
+// Without a timer, with_hold_limit should fail and release the Mutex, rather
+// than pretend to enforce the limit
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn hold_limit_no_timer() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let r = MutexGuard::with_hold_limit(guard, Duration::from_secs(60));
+    match r {
+        Err(HoldLimitError::NoTimer) => (),
+        _ => panic!("Unexpected result")
+    }
+    assert!(mtx.try_lock().is_ok());
+}
+
+// events should report queueing, cancellation, and hand-offs in order
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn events_contended() {
+    let mutex = Mutex::<u32>::new(0);
+    let events = mutex.events();
+    let guard = mutex.try_lock().unwrap();
+    let mut fut1 = mutex.lock();
+    let mut fut2 = mutex.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    drop(fut1);
+    drop(guard);
+    drop(fut2.wait().ok().unwrap());
+    drop(mutex);
+    assert_eq!(events.collect().wait().unwrap(), vec![
+        LockEvent::Acquired,
+        LockEvent::Queued,
+        LockEvent::Queued,
+        LockEvent::Cancelled,
+        LockEvent::Released,
+        LockEvent::Acquired,
+        LockEvent::Released,
+    ]);
+}
+
+// release_async should wait for the successor to take the Mutex, even if
+// the first waiter it was handed to gave up
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn release_async_waits_for_successor() {
+    let mtx = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = mtx.try_lock().unwrap();
+    let mut fut1 = mtx.lock();
+    let mut fut2 = mtx.lock();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    let mut released = MutexGuard::release_async(guard);
+    rt.block_on(lazy(move || {
+        assert!(released.poll().unwrap().is_not_ready());
+        drop(fut1);
+        assert!(released.poll().unwrap().is_not_ready());
+        let guard2 = fut2.poll().unwrap();
+        assert!(guard2.is_ready());
+        assert!(released.poll().unwrap().is_ready());
+        Ok::<(), ()>(())
+    })).unwrap();
+}
+
+// While unlocked, a guard's Mutex should be available to waiters, and the
+// guard should be reacquired afterwards
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn guard_unlocked() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let mut waiter = mtx.lock();
+    assert!(waiter.poll_once().unwrap().is_not_ready());
+    let fut = MutexGuard::unlocked(guard, waiter.map(|mut g| {
+        *g += 1;
+    }));
+    let (mut guard, ()) = fut.wait().ok().unwrap();
+    *guard += 1;
+    drop(guard);
+    assert_eq!(mtx.try_unwrap().unwrap(), 2);
+}
+
+// Bumping an uncontended guard should keep the Mutex locked throughout
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn guard_bump_uncontended() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let mut bumped = MutexGuard::bump(guard);
+    assert!(mtx.try_lock().is_err());
+    let guard = bumped.poll().unwrap();
+    assert!(guard.is_ready());
+    assert!(mtx.try_lock().is_err());
+}
+
+// A cancelled acquisition should leave the queue at once, and an unfired token
+// shouldn't get in the way
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_cancellable() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let (cancel, token) = oneshot::channel::<()>();
+    let mut fut = executor::spawn(mtx.lock_cancellable(token));
+    let noop = Arc::new(NoopNotify);
+    assert!(fut.poll_future_notify(&noop, 0).unwrap().is_not_ready());
+    assert_eq!(mtx.waiter_count(), 1);
+    cancel.send(()).unwrap();
+    assert_eq!(fut.wait_future().err(), Some(AcquireError::Cancelled));
+    assert_eq!(mtx.info().waiters(), 0);
+
+    let (_cancel, token) = oneshot::channel::<()>();
+    let fut = mtx.lock_cancellable(token);
+    drop(guard);
+    assert_eq!(*fut.wait().unwrap(), 0);
+}
+
+// blocking_lock must refuse to block an executor's thread
+#[test]
+#[should_panic(expected = "called from within a task")]
+fn blocking_lock_in_task() {
+    let mtx = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    rt.block_on(lazy(|| {
+        let _guard = mtx.blocking_lock();
+        Ok::<(), ()>(())
+    })).unwrap();
+}
+
+// BlockingAdapter::lock must refuse to block an executor's thread, too
+#[test]
+#[should_panic(expected = "called from within a task")]
+fn blocking_adapter_lock_in_task() {
+    let blocking = BlockingAdapter::new(Mutex::<u32>::new(0));
+    let mut rt = current_thread::Runtime::new().unwrap();
+    rt.block_on(lazy(|| {
+        let _guard = blocking.lock();
+        Ok::<(), ()>(())
+    })).unwrap();
+}
+
+// A closed Mutex will never become available, so BlockingAdapter::try_lock
+// should panic like lock does, rather than report WouldBlock
+#[test]
+#[should_panic(expected = "lock is closed")]
+fn blocking_adapter_try_lock_closed() {
+    let mtx = Mutex::<u32>::new(0);
+    mtx.close();
+    let _ = BlockingAdapter::new(mtx).try_lock();
+}
+
+// With nobody waiting, release_async should complete immediately
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn release_async_uncontended() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    assert_eq!(MutexGuard::release_async(guard).wait(), Ok(()));
+    assert!(mtx.try_lock().is_ok());
+}
+
+// After using up its yield budget, a task should yield once before acquiring
+// the Mutex again
+#[test]
+fn builder_yield_budget() {
+    let mtx = Mutex::builder().yield_budget(2).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    rt.block_on(lazy(|| {
+        assert!(mtx.lock().poll().unwrap().is_ready());
+        assert!(mtx.lock().poll().unwrap().is_ready());
+        let mut fut = mtx.lock();
+        assert!(fut.poll().unwrap().is_not_ready());
+        assert!(fut.poll().unwrap().is_ready());
+        Ok::<(), ()>(())
+    })).unwrap();
+}
+
+// info should report every setting chosen by the builder, and whether the
+// Mutex has been closed
+#[test]
+fn info_settings() {
+    let mtx = Mutex::builder()
+        .spin_limit(10)
+        .poison_on_panic(true)
+        .build(0u32);
+    let info = mtx.info();
+    assert_eq!(info.spin_limit(), 10);
+    assert!(info.poison_on_panic());
+    assert_eq!(info.max_readers(), None);
+    assert!(!info.is_closed());
+    mtx.close();
+    assert!(mtx.info().is_closed());
+}
+
+// Chaos mode should make try_lock fail even though the Mutex is free.  Other
+// tests that rely on try_lock are disabled with chaos, so this can't disturb
+// them.
+#[cfg(feature = "chaos")]
+#[test]
+fn chaos_would_block() {
+    chaos::set_failure_probability(1.0);
+    let mtx = Mutex::<u32>::new(0);
+    assert_eq!(mtx.try_lock().err(),
+               Some(futures_locks::TryLockError::WouldBlock));
+    assert!(!mtx.is_locked());
+    assert!(mtx.lock().wait().is_ok());
+}
+
+// A yield budget of 0 would make every acquisition yield forever
+#[test]
+#[should_panic(expected = "a yield budget must be at least 1")]
+fn builder_yield_budget_zero() {
+    Mutex::builder().yield_budget(0).build(0u32);
+}
+
+// A timed-out acquisition should leave the queue, and not affect later ones
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn lock_timeout_leaves_queue() {
+    let mtx = Mutex::builder().max_waiters(1).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = mtx.try_lock().unwrap();
+    let r = rt.block_on(mtx.lock_timeout(Duration::from_millis(10)));
+    assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    assert_eq!(mtx.info().waiters(), 0);
+    let fut = mtx.lock_timeout(Duration::from_secs(60));
+    drop(guard);
+    assert_eq!(*rt.block_on(fut).ok().unwrap(), 0);
+}
+
+// events should tell a timed-out acquisition from a cancelled one
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn events_timed_out() {
+    let mtx = Mutex::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = mtx.try_lock().unwrap();
+    let events = mtx.events();
+    let r = rt.block_on(mtx.lock_timeout(Duration::from_millis(10)));
+    assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    let (cancel, token) = oneshot::channel::<()>();
+    let fut = mtx.lock_cancellable(token);
+    let fut = rt.block_on(lazy(move || {
+        let mut fut = fut;
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(fut)
+    })).unwrap();
+    drop(cancel);
+    assert_eq!(rt.block_on(fut).err(), Some(AcquireError::Cancelled));
+    drop(guard);
+    drop(mtx);
+    assert_eq!(events.collect().wait().unwrap(), vec![
+        LockEvent::Queued,
+        LockEvent::TimedOut,
+        LockEvent::Queued,
+        LockEvent::Cancelled,
+        LockEvent::Released,
+    ]);
+}
+
+// Without a timer, lock_timeout can't wait at all
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn lock_timeout_no_timer() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let r = mtx.lock_timeout(Duration::from_secs(60)).wait();
+    assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    drop(guard);
+    assert!(mtx.lock_timeout(Duration::from_secs(60)).wait().is_ok());
+}
+
+// Only a Mutex built with poison_on_panic should be poisoned by a panic, and
+// BlockingAdapter should report it
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn poison_on_panic() {
+    let plain = Mutex::<u32>::new(0);
+    let poisonable = Mutex::builder().poison_on_panic(true).build(0u32);
+    let (plain2, poisonable2) = (plain.clone(), poisonable.clone());
+    thread::spawn(move || {
+        let _g1 = plain2.try_lock().unwrap();
+        let _g2 = poisonable2.try_lock().unwrap();
+        panic!("Whoops!");
+    }).join().unwrap_err();
+    assert!(!plain.is_poisoned());
+    assert!(plain.lock_checked().wait().unwrap().is_ok());
+    assert!(poisonable.is_poisoned());
+    assert_eq!(poisonable.lock().wait().err(), Some(AcquireError::Panicked));
+    assert_eq!(poisonable.try_lock().err(),
+               Some(futures_locks::TryLockError::Panicked));
+    let blocking = BlockingAdapter::new(poisonable.clone());
+    match blocking.try_lock() {
+        Err(TryLockError::Poisoned(e)) => assert_eq!(*e.into_inner(), 0),
+        _ => panic!("try_lock should've reported poison")
+    }
+    assert!(blocking.lock().is_err());
+    poisonable.clear_poison();
+    assert!(blocking.lock().is_ok());
+}
+
+// An acquisition that's waiting when the Mutex gets poisoned should fail, and
+// pass the Mutex on
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn poisoned_while_waiting() {
+    let mtx = Mutex::builder().poison_on_panic(true).build(0u32);
+    let guard = mtx.try_lock().unwrap();
+    let mut fut = mtx.lock();
+    assert!(fut.poll_once().unwrap().is_not_ready());
+    thread::spawn(move || {
+        let _guard = guard;
+        panic!("Whoops!");
+    }).join().unwrap_err();
+    assert_eq!(fut.wait().err(), Some(AcquireError::Panicked));
+    assert!(!mtx.is_locked());
+    let r = mtx.lock_checked().wait().unwrap();
+    assert_eq!(*r.err().unwrap().into_inner(), 0);
+}
+
+// into_inner should wait for clones and guards held elsewhere to be dropped
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn into_inner_waits() {
+    let mtx = Mutex::<u32>::new(0);
+    let clone = mtx.clone();
+    let weak = Mutex::downgrade(&mtx);
+    let guard = clone.try_lock().unwrap();
+    drop(clone);
+    let mut task = executor::spawn(mtx.into_inner());
+    let notify = Arc::new(NoopNotify);
+    assert!(task.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+    let upgraded = weak.upgrade().unwrap();
+    drop(guard);
+    assert!(task.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+    thread::spawn(move || drop(upgraded));
+    assert_eq!(task.wait_future().unwrap(), 0);
+}
+
+// A defaulted Mutex must count its handle like any other, or into_inner would
+// wait forever.
+#[test]
+fn into_inner_default() {
+    let mtx = Mutex::<u32>::default();
+    let clone = mtx.clone();
+    drop(clone);
+    assert_eq!(executor::spawn(mtx.into_inner()).wait_future().unwrap(), 0);
+}
+
+// close should wake every queued waiter with an error
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn close_wakes_waiters() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let (fut1, fut2) = rt.block_on(lazy(|| {
+        let mut fut1 = mtx.lock();
+        let mut fut2 = mtx.lock();
+        assert!(fut1.poll().unwrap().is_not_ready());
+        assert!(fut2.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((fut1, fut2))
+    })).unwrap();
+    mtx.close();
+    assert!(mtx.is_closed());
+    let r = rt.block_on(fut1.then(|r1| fut2.then(move |r2| {
+        Ok::<_, ()>((r1.err(), r2.err()))
+    }))).unwrap();
+    assert_eq!(r, (Some(AcquireError::Closed), Some(AcquireError::Closed)));
+    drop(guard);
+    assert!(!mtx.is_locked());
+}
+
+// close should also wake tasks that wait for a release, like wait_until's and
+// lock_some's, since no release may ever come
+#[test]
+fn close_wakes_watchers() {
+    let mtx = Mutex::<u32>::new(0);
+    let slot = Mutex::<Option<u32>>::new(None);
+    let (tx, rx) = mpsc::channel();
+    let (mtx2, slot2) = (mtx.clone(), slot.clone());
+    let t = thread::spawn(move || {
+        let r1 = mtx2.wait_until(|x| *x > 0).wait().err();
+        let r2 = slot2.lock_some().wait().err();
+        tx.send((r1, r2)).unwrap();
+    });
+    // Let the thread park in wait_until before closing
+    thread::sleep(Duration::from_millis(50));
+    mtx.close();
+    thread::sleep(Duration::from_millis(50));
+    slot.close();
+    let r = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(r, (Some(AcquireError::Closed), Some(AcquireError::Closed)));
+    t.join().unwrap();
+}
+
+// lock_stream should wait its turn behind other owners, and end when closed
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_stream_contended() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let stream = rt.block_on(lazy(|| {
+        let mut stream = mtx.lock_stream();
+        assert!(stream.poll().unwrap().is_not_ready());
+        Ok::<_, ()>(stream)
+    })).unwrap();
+    drop(guard);
+    let (guard, stream) = rt.block_on(stream.into_future()).ok().unwrap();
+    assert!(mtx.try_lock().is_err());
+    drop(guard);
+    mtx.close();
+    let (item, _stream) = rt.block_on(stream.into_future()).ok().unwrap();
+    assert!(item.is_none());
+}
+
+// A panicking thread shouldn't leave its half-finished changes behind
+#[test]
+fn lock_transactional_panic() {
+    let mtx = Mutex::<(u32, u32)>::new((0, 0));
+    let mtx2 = mtx.clone();
+    let r = thread::spawn(move || {
+        let mut guard = mtx2.lock_transactional(TransactionMode::Commit)
+            .wait().unwrap();
+        guard.0 += 1;
+        panic!("before updating the second field");
+    }).join();
+    assert!(r.is_err());
+    let mut guard = mtx.lock_transactional(TransactionMode::Commit)
+        .wait().unwrap();
+    assert_eq!(*guard, (0, 0));
+    *guard = (1, 1);
+    drop(guard);
+    assert_eq!(mtx.try_unwrap().unwrap(), (1, 1));
+}
+
+// A transaction that rolls back during a panic restores the data, so it should
+// neither poison the Mutex nor trip its validator
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn lock_transactional_panic_no_poison() {
+    let mtx = Mutex::builder().poison_on_panic(true).build((0u32, 0u32));
+    mtx.debug_validate(|&(a, b)| a == b);
+    let mtx2 = mtx.clone();
+    let r = thread::spawn(move || {
+        let mut guard = mtx2.lock_transactional(TransactionMode::Commit)
+            .wait().unwrap();
+        guard.0 += 1;
+        panic!("before updating the second field");
+    }).join();
+    assert!(r.is_err());
+    assert!(!mtx.is_poisoned());
+    let mut guard = mtx.lock_transactional(TransactionMode::Commit)
+        .wait().unwrap();
+    guard.1 += 1;
+    TransactionGuard::rollback(guard);
+    assert_eq!(*mtx.try_lock().unwrap(), (0, 0));
+}
+
+// A mapped guard should only expose its field, and should keep the Mutex
+// locked until it's dropped
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn guard_map() {
+    let mtx = Mutex::<(u32, Vec<u32>)>::new((0, vec![1]));
+    let guard = mtx.try_lock().unwrap();
+    let v = MutexGuard::map(guard, |x| &mut x.1);
+    let mut first = MappedMutexGuard::map(v, |v| &mut v[0]);
+    *first += 1;
+    assert!(mtx.try_lock().is_err());
+    drop(first);
+    assert_eq!(mtx.try_unwrap().unwrap(), (0, vec![2]));
+}
+
+// Guards should format as their protected data
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn guard_fmt() {
+    let mtx = Mutex::<(u32, String)>::new((1, "foo".to_owned()));
+    let guard = mtx.try_lock().unwrap();
+    assert_eq!(format!("{:?}", guard), "(1, \"foo\")");
+    let s = MutexGuard::map(guard, |x| &mut x.1);
+    assert_eq!(format!("{} {:?}", s, s), "foo \"foo\"");
+}
+
+// try_map should hand back the original guard when the projection fails
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn guard_try_map() {
+    let mtx = Mutex::<Result<u32, String>>::new(Err("foo".to_owned()));
+    let guard = mtx.try_lock().unwrap();
+    let guard = MutexGuard::try_map(guard, |r| r.as_mut().ok()).err().unwrap();
+    let mut e = MutexGuard::try_map(guard, |r| r.as_mut().err()).ok().unwrap();
+    e.push('!');
+    let e = MappedMutexGuard::try_map(e, |s| Some(s).filter(|s| s.is_empty()))
+        .err().unwrap();
+    assert!(mtx.try_lock().is_err());
+    drop(e);
+    assert_eq!(mtx.try_unwrap().unwrap(), Err("foo!".to_owned()));
+}
+
+// is_locked should stay true while ownership is handed between waiters
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn is_locked_during_handoff() {
+    let mtx = Mutex::<u32>::new(0);
+    let guard = mtx.try_lock().unwrap();
+    let mut fut = mtx.lock();
+    assert!(fut.poll_once().unwrap().is_not_ready());
+    drop(guard);
+    assert!(mtx.is_locked());
+    drop(fut);
+    assert!(!mtx.is_locked());
+}
+
+struct NoopNotify;
+
+impl executor::Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+// A ReentrantMutex should only be released by its owner's outermost guard, and
+// should then pass to the next task in line
+#[test]
+fn reentrant_nested() {
+    let mtx = ReentrantMutex::<u32>::new(0);
+    let noop = Arc::new(NoopNotify);
+    let (outer, inner) = executor::spawn(mtx.lock().and_then(|outer| {
+        mtx.lock().map(move |inner| (outer, inner))
+    })).wait_future().ok().unwrap();
+    assert_eq!(*outer + *inner, 0);
+
+    let mut other = executor::spawn(mtx.lock());
+    assert!(other.poll_future_notify(&noop, 0).unwrap().is_not_ready());
+    drop(outer);
+    assert!(other.poll_future_notify(&noop, 0).unwrap().is_not_ready());
+    drop(inner);
+    assert!(other.poll_future_notify(&noop, 0).unwrap().is_ready());
+}
+
+// A LocalMutex should serve its waiters in order, skipping dropped ones
+#[test]
+fn local_mutex_contended() {
+    let mtx = LocalMutex::<Vec<u32>>::new(Vec::new());
+    let guard = mtx.try_lock().unwrap();
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let (fut1, fut3) = rt.block_on(lazy(|| {
+        let mut fut1 = mtx.lock().map(|mut g| g.push(1));
+        let mut fut2 = mtx.lock();
+        let mut fut3 = mtx.lock().map(|mut g| g.push(3));
+        assert!(fut1.poll().unwrap().is_not_ready());
+        assert!(fut2.poll().unwrap().is_not_ready());
+        assert!(fut3.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((fut1, fut3))
+    })).unwrap();
+    drop(guard);
+    rt.block_on(fut3.join(fut1)).unwrap();
+    assert_eq!(mtx.try_unwrap().unwrap(), vec![1, 3]);
+}
+
+// A ScopedMutex should serve its waiters in order, skipping dropped ones, and
+// its guards should only borrow it
+#[test]
+fn scoped_mutex_contended() {
+    let mtx = ScopedMutex::<Vec<u32>>::new(Vec::new());
+    let guard = mtx.try_lock().unwrap();
+    let mut rt = current_thread::Runtime::new().unwrap();
+    rt.block_on(lazy(|| {
+        let mut fut1 = mtx.lock().map(|mut g| g.push(1));
+        let mut fut2 = mtx.lock();
+        let mut fut3 = mtx.lock().map(|mut g| g.push(3));
+        assert!(fut1.poll().unwrap().is_not_ready());
+        assert!(fut2.poll().unwrap().is_not_ready());
+        assert!(fut3.poll().unwrap().is_not_ready());
+        drop(fut2);
+        drop(guard);
+        fut3.join(fut1)
+    })).unwrap();
+    assert_eq!(mtx.into_inner(), vec![1, 3]);
+}
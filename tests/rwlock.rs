@@ -3,7 +3,15 @@
 use futures::{Future, Stream, future, lazy, stream};
 use futures::sync::oneshot;
 #[cfg(feature = "tokio")]
+use std::error::Error;
+#[cfg(feature = "tokio")]
 use std::rc::Rc;
+#[cfg(not(feature = "chaos"))]
+use std::sync::{Arc, Mutex as StdMutex};
+#[cfg(not(feature = "chaos"))]
+use std::thread;
+#[cfg(not(feature = "chaos"))]
+use std::time::Duration;
 use tokio;
 #[cfg(feature = "tokio")]
 use tokio::runtime;
@@ -82,12 +90,12 @@ fn read_shared() {
     let result = rt.block_on(lazy(|| {
         let (tx0, rx0) = oneshot::channel::<()>();
         let (tx1, rx1) = oneshot::channel::<()>();
-        let task0 = rwlock.read()
+        let task0 = rwlock.read().map_err(|_| ())
             .and_then(move |guard| {
                 tx1.send(()).unwrap();
                 rx0.map(move |_| *guard).map_err(|_| ())
             });
-        let task1 = rwlock.read()
+        let task1 = rwlock.read().map_err(|_| ())
             .and_then(move |guard| {
                 tx0.send(()).unwrap();
                 rx1.map(move |_| *guard).map_err(|_| ())
@@ -122,13 +130,13 @@ fn read_contested() {
     let result = rt.block_on(lazy(|| {
         let (tx0, rx0) = oneshot::channel::<()>();
         let (tx1, rx1) = oneshot::channel::<()>();
-        let task0 = rwlock.write()
+        let task0 = rwlock.write().map_err(|_| ())
             .and_then(move |mut guard| {
                 *guard += 5;
                 rx0.map_err(|_| {drop(guard);})
             });
-        let task1 = rwlock.read().map(|guard| *guard);
-        let task2 = rwlock.read().map(|guard| *guard);
+        let task1 = rwlock.read().map_err(|_| ()).map(|guard| *guard);
+        let task2 = rwlock.read().map_err(|_| ()).map(|guard| *guard);
         // Readying task3 before task1 and task2 causes Tokio to poll the latter
         // even though they're not ready
         let task3 = rx1.map_err(|_| ()).map(|_| tx0.send(()).unwrap());
@@ -160,12 +168,12 @@ fn read_write_contested() {
     let result = rt.block_on(lazy(|| {
         let (tx0, rx0) = oneshot::channel::<()>();
         let (tx1, rx1) = oneshot::channel::<()>();
-        let task0 = rwlock.read()
+        let task0 = rwlock.read().map_err(|_| ())
             .and_then(move |guard| {
                 rx0.map(move |_| { *guard }).map_err(|_| ())
             });
-        let task1 = rwlock.write().map(|mut guard| *guard += 1);
-        let task2 = rwlock.read().map(|guard| *guard);
+        let task1 = rwlock.write().map_err(|_| ()).map(|mut guard| *guard += 1);
+        let task2 = rwlock.read().map_err(|_| ()).map(|guard| *guard);
         // Readying task3 before task1 and task2 causes Tokio to poll the latter
         // even though they're not ready
         let task3 = rx1.map_err(|_| ()).map(|_| tx0.send(()).unwrap());
@@ -180,6 +188,7 @@ fn read_write_contested() {
     assert_eq!(rwlock.try_unwrap().expect("try_unwrap"), 43);
 }
 
+#[cfg(not(feature = "chaos"))]
 #[test]
 fn try_read_uncontested() {
     let rwlock = RwLock::<u32>::new(42);
@@ -200,6 +209,7 @@ fn try_unwrap_multiply_referenced() {
     assert!(rwlock.try_unwrap().is_err());
 }
 
+#[cfg(not(feature = "chaos"))]
 #[test]
 fn try_write_uncontested() {
     let rwlock = RwLock::<u32>::new(0);
@@ -240,12 +250,12 @@ fn write_contested() {
     let result = rt.block_on(lazy(|| {
         let (tx0, rx0) = oneshot::channel::<()>();
         let (tx1, rx1) = oneshot::channel::<()>();
-        let task0 = rwlock.write()
+        let task0 = rwlock.write().map_err(|_| ())
             .and_then(move |mut guard| {
                 *guard += 5;
                 rx0.map_err(|_| {drop(guard);})
             });
-        let task1 = rwlock.write().map(|guard| *guard);
+        let task1 = rwlock.write().map_err(|_| ()).map(|guard| *guard);
         // Readying task2 before task1 causes Tokio to poll the latter
         // even though it's not ready
         let task2 = rx1.map_err(|_| ()).map(|_| tx0.send(()).unwrap());
@@ -288,21 +298,25 @@ fn multithreaded() {
             let rwlock_clone4 = rwlock_clone0.clone();
             rwlock_clone0.write().map(|mut guard| { *guard += 2 })
                 .and_then(move |_| rwlock_clone4.read().map(|_| ()))
+                .map_err(|_| ())
         }));
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             let rwlock_clone5 = rwlock_clone1.clone();
             rwlock_clone1.write().map(|mut guard| { *guard += 3 })
                 .and_then(move |_| rwlock_clone5.read().map(|_| ()))
+                .map_err(|_| ())
         }));
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             let rwlock_clone6 = rwlock_clone2.clone();
             rwlock_clone2.write().map(|mut guard| { *guard += 5 })
                 .and_then(move |_| rwlock_clone6.read().map(|_| ()))
+                .map_err(|_| ())
         }));
         tokio::spawn(stream::iter_ok::<_, ()>(0..1000).for_each(move |_| {
             let rwlock_clone7 = rwlock_clone3.clone();
             rwlock_clone3.write().map(|mut guard| { *guard += 7 })
                 .and_then(move |_| rwlock_clone7.read().map(|_| ()))
+                .map_err(|_| ())
         }));
         future::ok::<(), ()>(())
     });
@@ -322,11 +336,11 @@ fn with_read_err() {
             if *guard > 0 {
                 Ok(*guard)
             } else {
-                Err("Whoops!")
+                Err(Box::<dyn Error + Send + Sync>::from("Whoops!"))
             }
         }).unwrap()
     }));
-    assert_eq!(r, Err("Whoops!"));
+    assert_eq!(r.unwrap_err().to_string(), "Whoops!");
 }
 
 #[cfg(feature = "tokio")]
@@ -337,7 +351,7 @@ fn with_read_ok() {
 
     let r = rt.block_on(lazy(move || {
         mtx.with_read(|guard| {
-            Ok(*guard) as Result<i32, ()>
+            Ok(*guard) as Result<i32, AcquireError>
         }).unwrap()
     }));
     assert_eq!(r, Ok(5));
@@ -354,7 +368,7 @@ fn with_read_threadpool() {
 
     let r = rt.block_on(lazy(move || {
         mtx.with_read(|guard| {
-            Ok(*guard) as Result<i32, ()>
+            Ok(*guard) as Result<i32, AcquireError>
         }).unwrap()
     }));
     assert_eq!(r, Ok(5));
@@ -368,7 +382,7 @@ fn with_read_local_ok() {
     let mut rt = current_thread::Runtime::new().unwrap();
     let r = rt.block_on(lazy(move || {
         rwlock.with_read_local(|guard| {
-            Ok(**guard) as Result<i32, ()>
+            Ok(**guard) as Result<i32, AcquireError>
         }).unwrap()
     }));
     assert_eq!(r, Ok(5));
@@ -386,11 +400,11 @@ fn with_write_err() {
                 *guard -= 1;
                 Ok(())
             } else {
-                Err("Whoops!")
+                Err(Box::<dyn Error + Send + Sync>::from("Whoops!"))
             }
         }).unwrap()
     }));
-    assert_eq!(r, Err("Whoops!"));
+    assert_eq!(r.unwrap_err().to_string(), "Whoops!");
 }
 
 #[cfg(feature = "tokio")]
@@ -402,7 +416,7 @@ fn with_write_ok() {
     let r = rt.block_on(lazy(|| {
         mtx.with_write(|mut guard| {
             *guard += 1;
-            Ok(()) as Result<(), ()>
+            Ok(()) as Result<(), AcquireError>
         }).unwrap()
     }));
     assert!(r.is_ok());
@@ -422,7 +436,7 @@ fn with_write_threadpool() {
     let r = rt.block_on(lazy(move || {
         mtx.with_write(|mut guard| {
             *guard += 1;
-            Ok(()) as Result<(), ()>
+            Ok(()) as Result<(), AcquireError>
         }).unwrap()
     }));
     assert!(r.is_ok());
@@ -438,9 +452,803 @@ fn with_write_local_ok() {
     let r = rt.block_on(lazy(|| {
         rwlock.with_write_local(|mut guard| {
             *Rc::get_mut(&mut *guard).unwrap() += 1;
-            Ok(()) as Result<(), ()>
+            Ok(()) as Result<(), AcquireError>
         }).unwrap()
     }));
     assert!(r.is_ok());
     assert_eq!(*rwlock.try_unwrap().unwrap(), 6);
 }
+
+// Readers and writers count toward the same queue depth
+#[cfg(all(feature = "stats", not(feature = "chaos")))]
+#[test]
+fn stats_max_queue_depth() {
+    let rwlock = RwLock::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = rwlock.try_write().unwrap();
+    let (fut1, fut2) = rt.block_on(lazy(|| {
+        let mut fut1 = rwlock.read();
+        let mut fut2 = rwlock.write();
+        assert!(fut1.poll().unwrap().is_not_ready());
+        assert!(fut2.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>((fut1, fut2))
+    })).unwrap();
+    assert_eq!(rwlock.stats().max_queue_depth, 2);
+    drop(guard);
+    // Pending writers take priority over pending readers
+    let r = rt.block_on(fut2.and_then(|guard2| {
+        drop(guard2);
+        fut1.map(|guard1| *guard1)
+    }));
+    assert_eq!(r, Ok(0));
+    assert_eq!(rwlock.stats().max_queue_depth, 2);
+    rwlock.reset_stats();
+    assert_eq!(rwlock.stats().max_queue_depth, 0);
+}
+
+// A failed invariant check panics, but still releases the RwLock
+#[cfg(all(debug_assertions, not(feature = "chaos")))]
+#[test]
+fn debug_validate_releases() {
+    let rwlock = RwLock::<u32>::new(0);
+    rwlock.debug_validate(|x| *x < 10);
+    let rwlock2 = rwlock.clone();
+
+    let r = thread::spawn(move || {
+        *rwlock2.try_write().unwrap() = 11;
+    })
+    .join();
+    assert!(r.is_err());
+    assert_eq!(*rwlock.try_read().unwrap(), 11);
+    *rwlock.try_write().unwrap() = 5;
+}
+
+// A writer that waits too long behind readers gets reported, exactly once
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn writer_starvation() {
+    let rwlock = RwLock::<u32>::new(0);
+    let events = Arc::new(StdMutex::new(Vec::new()));
+    let events2 = events.clone();
+    rwlock.on_writer_starvation(Duration::from_millis(10), move |ev| {
+        events2.lock().unwrap().push(ev);
+    });
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard1 = rwlock.try_read().unwrap();
+    let fut = rt.block_on(lazy(|| {
+        let mut fut = rwlock.write();
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(fut)
+    })).unwrap();
+    let guard2 = rwlock.try_read().unwrap();
+    assert!(events.lock().unwrap().is_empty());
+    thread::sleep(Duration::from_millis(20));
+    let guard3 = rwlock.try_read().unwrap();
+    drop(guard1);
+    {
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].num_readers, 3);
+        assert!(events[0].waited >= Duration::from_millis(10));
+    }
+    drop(guard2);
+    drop(guard3);
+    let r = rt.block_on(fut.map(|guard| *guard));
+    assert_eq!(r, Ok(0));
+    assert_eq!(events.lock().unwrap().len(), 1);
+}
+
+// A starved writer notices on its own when it gets polled again, even if no
+// reader comes or goes
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn writer_starvation_on_poll() {
+    let rwlock = RwLock::<u32>::new(0);
+    let events = Arc::new(StdMutex::new(Vec::new()));
+    let events2 = events.clone();
+    rwlock.on_writer_starvation(Duration::from_millis(10), move |ev| {
+        events2.lock().unwrap().push(ev);
+    });
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = rwlock.try_read().unwrap();
+    let mut fut = rwlock.write();
+    rt.block_on(lazy(|| {
+        assert!(fut.poll().unwrap().is_not_ready());
+        thread::sleep(Duration::from_millis(20));
+        assert!(fut.poll().unwrap().is_not_ready());
+        future::ok::<(), ()>(())
+    })).unwrap();
+    assert_eq!(events.lock().unwrap().len(), 1);
+    assert_eq!(events.lock().unwrap()[0].num_readers, 1);
+    drop(guard);
+    assert_eq!(rt.block_on(fut.map(|guard| *guard)), Ok(0));
+}
+
+#[cfg(all(feature = "test-util", not(feature = "chaos")))]
+#[test]
+fn test_util_drop_at_each_stage() {
+    let rwlock = RwLock::<u32>::new(0);
+
+    test_util::drop_unpolled(rwlock.write());
+    assert!(rwlock.try_write().is_ok());
+
+    let guard = rwlock.try_write().unwrap();
+    test_util::drop_queued(rwlock.read());
+    test_util::drop_queued(rwlock.write());
+    drop(guard);
+    assert!(rwlock.try_write().is_ok());
+
+    let guard = rwlock.try_write().unwrap();
+    test_util::drop_granted(rwlock.read(), || drop(guard));
+    assert!(rwlock.try_write().is_ok());
+
+    let guard = rwlock.try_read().unwrap();
+    test_util::drop_granted(rwlock.write(), || drop(guard));
+    assert!(rwlock.try_write().is_ok());
+}
+
+// A writer's queue_position should ignore readers, and now_or_never shouldn't
+// leave anything queued
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn write_queue_position() {
+    let rwlock = RwLock::<u32>::new(0);
+    let guard = rwlock.try_write().unwrap();
+
+    assert!(rwlock.write().now_or_never().unwrap().is_none());
+    let mut rfut = rwlock.read();
+    assert!(rfut.poll_once().unwrap().is_not_ready());
+    assert_eq!(format!("{:?}", rfut),
+               "RwLockReadFut { state: \"Pending\", queue_position: Some(0) }");
+    let mut wfut = rwlock.write();
+    assert!(wfut.poll_once().unwrap().is_not_ready());
+    assert_eq!(wfut.queue_position(), Some(0));
+    drop(guard);
+    assert_eq!(wfut.queue_position(), None);
+    drop(wfut.poll_once().unwrap());
+    assert!(rfut.poll_once().unwrap().is_ready());
+}
+
+// A reader's queue_position should count only the live readers ahead of it
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn read_queue_position() {
+    let rwlock = RwLock::<u32>::new(0);
+    let guard = rwlock.try_write().unwrap();
+
+    let mut rfut1 = rwlock.read();
+    assert_eq!(rfut1.queue_position(), None);
+    assert!(rfut1.poll_once().unwrap().is_not_ready());
+    let mut rfut2 = rwlock.read();
+    assert!(rfut2.poll_once().unwrap().is_not_ready());
+    assert_eq!(rfut1.queue_position(), Some(0));
+    assert_eq!(rfut2.queue_position(), Some(1));
+    drop(rfut1);
+    assert_eq!(rfut2.queue_position(), Some(0));
+    drop(guard);
+    assert_eq!(rfut2.queue_position(), None);
+    assert!(rfut2.poll_once().unwrap().is_ready());
+}
+
+// max_waiters should count readers and writers together
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn builder_max_waiters() {
+    let rwlock = RwLock::builder().name("x").max_waiters(2).build(0u32);
+    let guard = rwlock.try_write().unwrap();
+
+    let mut rfut = rwlock.read();
+    let mut wfut = rwlock.write();
+    assert!(rfut.poll_once().unwrap().is_not_ready());
+    assert!(wfut.poll_once().unwrap().is_not_ready());
+    assert_eq!(rwlock.read().now_or_never().err(),
+               Some(AcquireError::QueueFull));
+    assert_eq!(rwlock.write().now_or_never().err(),
+               Some(AcquireError::QueueFull));
+    drop(guard);
+    drop(wfut.poll_once().unwrap());
+    assert!(rfut.poll_once().unwrap().is_ready());
+    assert_eq!(rwlock.name(), Some("x"));
+}
+
+// A snapshot should release the RwLock as soon as it's taken
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn snapshot_releases() {
+    let rwlock = RwLock::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let snapshot = rt.block_on(rwlock.snapshot()).unwrap();
+    *rwlock.try_write().unwrap() = 2;
+    assert_eq!(*snapshot, 1);
+    let guard = rwlock.try_read().unwrap();
+    assert_eq!(*RwLockReadGuard::to_arc(&guard), 2);
+}
+
+// A clone-on-write guard should keep writers out until it's done, and shouldn't
+// write anything back unless it was mutated and committed
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn read_cow() {
+    let rwlock = RwLock::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let guard = rt.block_on(rwlock.read_cow()).unwrap();
+    assert!(rwlock.try_write().is_err());
+    assert!(!RwLockCowGuard::is_owned(&guard));
+    assert_eq!(*rwlock.try_read().unwrap(), 1);
+    rt.block_on(RwLockCowGuard::commit(guard)).unwrap();
+    assert!(rwlock.try_write().is_ok());
+
+    // With no other readers, the first mutation upgrades the guard
+    let mut guard = rt.block_on(rwlock.read_cow()).unwrap();
+    *guard += 1;
+    assert!(RwLockCowGuard::is_owned(&guard));
+    assert!(rwlock.try_read().is_err());
+    rt.block_on(RwLockCowGuard::commit(guard)).unwrap();
+    assert_eq!(*rwlock.try_read().unwrap(), 2);
+
+    let mut guard = rt.block_on(rwlock.read_cow()).unwrap();
+    *guard += 1;
+    drop(guard);
+    assert_eq!(*rwlock.try_read().unwrap(), 2);
+}
+
+// A clone-on-write guard that's mutated while other readers hold the RwLock
+// should upgrade when it's committed, without letting a writer in between
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn read_cow_contended() {
+    let rwlock = RwLock::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+
+    let reader = rwlock.try_read().unwrap();
+    let mut guard = rt.block_on(rwlock.read_cow()).unwrap();
+    *guard += 1;
+    assert!(rwlock.try_write().is_err());
+    assert!(rwlock.try_read().is_ok());
+    let mut writer = rwlock.write();
+    let fut = rt.block_on(lazy(move || {
+        assert!(writer.poll().unwrap().is_not_ready());
+        let mut commit = RwLockCowGuard::commit(guard);
+        assert!(commit.poll().unwrap().is_not_ready());
+        future::ok::<_, ()>(commit.join(writer.map(|mut guard| {
+            *guard *= 10;
+        })))
+    })).unwrap();
+    drop(reader);
+    rt.block_on(fut).unwrap();
+    assert_eq!(*rwlock.try_read().unwrap(), 20);
+}
+
+// Debug output should identify the RwLock and count both kinds of waiters
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn debug_named() {
+    let rwlock = RwLock::builder().name("config").build(0u32);
+    let guard = rwlock.try_write().unwrap();
+    let mut rfut = rwlock.read();
+    let mut wfut = rwlock.write();
+    assert!(rfut.poll_once().unwrap().is_not_ready());
+    assert!(wfut.poll_once().unwrap().is_not_ready());
+    assert_eq!(
+        format!("{:?}", rwlock),
+        "RwLock { name: Some(\"config\"), state: \"Write\", readers: 0, \
+         waiters: 2 }"
+    );
+    drop(guard);
+}
+
+// try_unwrap's error should describe the lock's readers and waiters
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn try_unwrap_error() {
+    let rwlock = RwLock::<u32>::new(0);
+    let guard = rwlock.try_read().unwrap();
+    let mut wfut = rwlock.write();
+    assert!(wfut.poll_once().unwrap().is_not_ready());
+    let e = rwlock.try_unwrap().unwrap_err();
+    assert_eq!(e.handles(), 3);
+    assert!(e.is_locked());
+    assert_eq!(e.waiters(), 1);
+    drop(wfut);
+    drop(guard);
+    assert_eq!(e.into_inner().try_unwrap().ok(), Some(0));
+}
+
+// The version should only advance when a writer releases the lock
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn version_advances_on_write() {
+    let rwlock = RwLock::<u32>::new(0);
+    assert_eq!(rwlock.version(), 0);
+    let rguard = rwlock.try_read().unwrap();
+    assert_eq!(RwLockReadGuard::version(&rguard), 0);
+    let wfut = rwlock.write();
+    drop(rguard);
+    let wguard = wfut.wait().ok().unwrap();
+    assert_eq!(RwLockWriteGuard::version(&wguard), 0);
+    drop(wguard);
+    assert_eq!(rwlock.version(), 1);
+    drop(rwlock.try_read().unwrap());
+    assert_eq!(rwlock.version(), 1);
+}
+
+// Readers and writers share the yield budget
+#[test]
+fn builder_yield_budget() {
+    let rwlock = RwLock::builder().yield_budget(1).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    rt.block_on(lazy(|| {
+        assert!(rwlock.read().poll().unwrap().is_ready());
+        let mut fut = rwlock.write();
+        assert!(fut.poll().unwrap().is_not_ready());
+        assert!(fut.poll().unwrap().is_ready());
+        Ok::<(), ()>(())
+    })).unwrap();
+}
+
+// Once a panic poisons an RwLock, ordinary acquisitions should fail until it's
+// cleared, but the checked ones should still succeed
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn poison_on_panic() {
+    let rwlock = RwLock::builder().poison_on_panic(true).build(0u32);
+    let rwlock2 = rwlock.clone();
+    thread::spawn(move || {
+        let _guard = rwlock2.try_write().unwrap();
+        panic!("Whoops!");
+    })
+    .join()
+    .unwrap_err();
+    assert_eq!(rwlock.read().wait().err(), Some(AcquireError::Panicked));
+    assert_eq!(rwlock.write().wait().err(), Some(AcquireError::Panicked));
+    assert_eq!(rwlock.try_read().err(), Some(TryLockError::Panicked));
+    assert_eq!(rwlock.try_write().err(), Some(TryLockError::Panicked));
+    assert_eq!(
+        rwlock.try_upgradable_read().err(),
+        Some(TryLockError::Panicked)
+    );
+    assert!(rwlock.read_checked().wait().unwrap().is_err());
+    assert!(rwlock.write_checked().wait().unwrap().is_err());
+    rwlock.clear_poison();
+    assert!(rwlock.try_write().is_ok());
+}
+
+// info should report every setting chosen by the builder
+#[test]
+fn info_settings() {
+    let rwlock = RwLock::builder()
+        .preference(Preference::Writers)
+        .max_readers(4)
+        .poison_on_panic(true)
+        .build(0u32);
+    let info = rwlock.info();
+    assert_eq!(info.preference(), Some(Preference::Writers));
+    assert_eq!(info.max_readers(), Some(4));
+    assert!(info.poison_on_panic());
+    assert_eq!(info.spin_limit(), 0);
+    assert!(!info.is_closed());
+}
+
+// Chaos mode should make the try_* methods fail even though the RwLock is
+// free.  Other tests that rely on them are disabled with chaos, so this can't
+// disturb them.
+#[cfg(feature = "chaos")]
+#[test]
+fn chaos_would_block() {
+    chaos::set_failure_probability(1.0);
+    let rwlock = RwLock::<u32>::new(0);
+    assert_eq!(rwlock.try_read().err(), Some(TryLockError::WouldBlock));
+    assert_eq!(rwlock.try_write().err(), Some(TryLockError::WouldBlock));
+    assert_eq!(rwlock.try_upgradable_read().err(),
+               Some(TryLockError::WouldBlock));
+    assert!(rwlock.write().wait().is_ok());
+}
+
+// A yield budget of 0 would make every acquisition yield forever
+#[test]
+#[should_panic(expected = "a yield budget must be at least 1")]
+fn builder_yield_budget_zero() {
+    RwLock::builder().yield_budget(0).build(0u32);
+}
+
+// A cancelled acquisition should fail, and leave the queue at once
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn read_cancellable() {
+    let rwlock = RwLock::<u32>::new(0);
+    let guard = rwlock.try_write().unwrap();
+    let (cancel, token) = oneshot::channel::<()>();
+    let fut = rwlock.read_cancellable(token);
+    drop(cancel);
+    assert_eq!(fut.wait().err(), Some(AcquireError::Cancelled));
+    assert_eq!(rwlock.info().waiters(), 0);
+    drop(guard);
+    let (_cancel, token) = oneshot::channel::<()>();
+    assert_eq!(*rwlock.read_cancellable(token).wait().unwrap(), 0);
+}
+
+// Contended nonblocking acquisitions should say why they failed
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn try_lock_error() {
+    let rwlock = RwLock::<u32>::new(0);
+    let guard = rwlock.try_read().unwrap();
+    assert_eq!(rwlock.try_write().err(), Some(TryLockError::WouldBlock));
+    drop(guard);
+    let _guard = rwlock.try_write().unwrap();
+    assert_eq!(rwlock.try_read().err(), Some(TryLockError::WouldBlock));
+}
+
+// A future that took delivery of its grant via is_acquired should still
+// complete, or release the RwLock if it's dropped instead
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn is_acquired() {
+    let rwlock = RwLock::<u32>::new(0);
+    let guard = rwlock.try_write().unwrap();
+    let mut fut1 = rwlock.read();
+    let mut fut2 = rwlock.read();
+    assert!(fut1.poll_once().unwrap().is_not_ready());
+    assert!(fut2.poll_once().unwrap().is_not_ready());
+    drop(guard);
+    assert!(fut1.is_acquired());
+    assert!(fut1.is_acquired());
+    assert!(fut2.is_acquired());
+    drop(fut2);
+    let guard = fut1.wait().ok().unwrap();
+    assert!(rwlock.try_write().is_err());
+    drop(guard);
+    assert!(rwlock.try_write().is_ok());
+}
+
+// Guards should format as their protected data
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn guard_fmt() {
+    let rwlock = RwLock::<u32>::new(42);
+    let guard = rwlock.try_read().unwrap();
+    assert_eq!(format!("{} {:?}", guard, guard), "42 42");
+    drop(guard);
+    let guard = rwlock.try_write().unwrap();
+    assert_eq!(format!("{:>4}", guard), "  42");
+}
+
+// Default and From should both create an unlocked RwLock
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn default_and_from() {
+    let a = RwLock::<Vec<u32>>::default();
+    let b: RwLock<Vec<u32>> = vec![1].into();
+    a.try_write().unwrap().push(1);
+    assert_eq!(*a.try_read().unwrap(), *b.try_read().unwrap());
+}
+
+// A LocalRwLock's writer should wait for its readers, and vice versa
+#[test]
+fn local_rwlock_contended() {
+    let rwlock = LocalRwLock::<u32>::new(0);
+    let reader = rwlock.try_read().unwrap();
+    assert!(rwlock.try_write().is_err());
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let (writer, late_reader) = rt.block_on(lazy(|| {
+        let mut writer = rwlock.write().map(|mut g| *g += 1);
+        assert!(writer.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((writer, rwlock.read().map(|g| *g)))
+    })).unwrap();
+    drop(reader);
+    rt.block_on(writer).unwrap();
+    assert_eq!(rt.block_on(late_reader), Ok(1));
+    assert_eq!(rwlock.try_unwrap().unwrap(), 1);
+}
+
+// An RwLockWeak shouldn't keep the data alive, but should share it while the
+// RwLock lives
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn rwlock_weak() {
+    let rwlock = RwLock::<u32>::new(0);
+    let weak = RwLock::downgrade(&rwlock);
+    *weak.upgrade().unwrap().try_write().unwrap() = 5;
+    assert_eq!(rwlock.try_unwrap().unwrap(), 5);
+    assert!(weak.upgrade().is_none());
+}
+
+// Only one upgradable guard may exist at a time, but it coexists with readers.
+// When it's released, the next upgradable waiter takes it over.
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn upgradable_read_exclusive() {
+    let rwlock = RwLock::<u32>::new(42);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard1 = rwlock.try_upgradable_read().unwrap();
+    let (fut, guard2) = rt.block_on(lazy(|| {
+        let mut fut = rwlock.upgradable_read();
+        assert!(fut.poll().unwrap().is_not_ready());
+        let guard2 = rwlock.read().poll().unwrap();
+        Ok::<_, ()>((fut, guard2))
+    })).unwrap();
+    assert!(guard2.is_ready());
+    drop(guard1);
+    let guard3 = rt.block_on(fut).unwrap();
+    assert!(rwlock.try_upgradable_read().is_err());
+    drop(guard2);
+    assert!(rwlock.try_write().is_err());
+    drop(guard3);
+    assert!(rwlock.try_write().is_ok());
+}
+
+// An upgrade should wait for the other readers, while holding off new readers
+// and writers
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn upgrade_waits_for_readers() {
+    let rwlock = RwLock::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let reader = rwlock.try_read().unwrap();
+    let guard = rwlock.try_upgradable_read().unwrap();
+    let (upgrade, read, write) = rt.block_on(lazy(|| {
+        let mut write = rwlock.write();
+        assert!(write.poll().unwrap().is_not_ready());
+        let mut upgrade = RwLockUpgradableReadGuard::upgrade(guard);
+        assert!(upgrade.poll().unwrap().is_not_ready());
+        let mut read = rwlock.read();
+        assert!(read.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((upgrade, read, write))
+    })).unwrap();
+    assert!(rwlock.try_read().is_err());
+    drop(reader);
+    let mut guard = rt.block_on(upgrade).unwrap();
+    *guard += 1;
+    drop(guard);
+    let guard = rt.block_on(write).unwrap();
+    assert_eq!(*guard, 2);
+    drop(guard);
+    assert_eq!(*rt.block_on(read).unwrap(), 2);
+}
+
+// Abandoning an upgrade should release the RwLock and admit the readers that
+// were waiting for it
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn upgrade_dropped() {
+    let rwlock = RwLock::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let reader = rwlock.try_read().unwrap();
+    let guard = rwlock.try_upgradable_read().unwrap();
+    let (upgrade, read) = rt.block_on(lazy(|| {
+        let mut upgrade = RwLockUpgradableReadGuard::upgrade(guard);
+        assert!(upgrade.poll().unwrap().is_not_ready());
+        let mut read = rwlock.read();
+        assert!(read.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((upgrade, read))
+    })).unwrap();
+    drop(upgrade);
+    assert_eq!(*rt.block_on(read).unwrap(), 1);
+    assert!(rwlock.try_upgradable_read().is_ok());
+    drop(reader);
+    assert!(rwlock.try_write().is_ok());
+}
+
+// Downgrading should admit waiting readers, but not waiting writers
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn downgrade_admits_readers() {
+    let rwlock = RwLock::<u32>::new(1);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let mut guard = rwlock.try_write().unwrap();
+    let (read, write) = rt.block_on(lazy(|| {
+        let mut write = rwlock.write();
+        assert!(write.poll().unwrap().is_not_ready());
+        let mut read = rwlock.read();
+        assert!(read.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((read, write))
+    })).unwrap();
+    *guard += 1;
+    let guard = RwLockWriteGuard::downgrade(guard);
+    assert_eq!(rwlock.version(), 1);
+    assert_eq!(*rt.block_on(read).unwrap(), 2);
+    assert!(rwlock.try_write().is_err());
+    drop(guard);
+    assert_eq!(*rt.block_on(write).unwrap(), 2);
+}
+
+// A mapped read guard should hold the read lock, and release it when dropped
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn map_read_guard() {
+    let rwlock = RwLock::<(u32, Option<u32>)>::new((1, Some(2)));
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = rwlock.try_read().unwrap();
+    let second = RwLockReadGuard::map(guard, |t| &t.1);
+    let second = MappedRwLockReadGuard::try_map(second, Option::as_ref)
+        .ok()
+        .unwrap();
+    assert_eq!(*second, 2);
+    let write = rt.block_on(lazy(|| {
+        let mut write = rwlock.write();
+        assert!(write.poll().unwrap().is_not_ready());
+        Ok::<_, ()>(write)
+    })).unwrap();
+    assert_eq!(*rwlock.try_read().unwrap(), (1, Some(2)));
+    drop(second);
+    assert_eq!(rt.block_on(write).unwrap().1, Some(2));
+}
+
+// A mapped write guard should hold the write lock, and bump the version when
+// dropped
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn map_write_guard() {
+    let rwlock = RwLock::<(u32, Option<u32>)>::new((1, None));
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = rwlock.try_write().unwrap();
+    let mut second = RwLockWriteGuard::map(guard, |t| &mut t.1);
+    *second = Some(2);
+    let read = rt.block_on(lazy(|| {
+        let mut read = rwlock.read();
+        assert!(read.poll().unwrap().is_not_ready());
+        Ok::<_, ()>(read)
+    })).unwrap();
+    let second = MappedRwLockWriteGuard::try_map(second, Option::as_mut)
+        .ok()
+        .unwrap();
+    assert_eq!(*second, 2);
+    assert_eq!(rwlock.version(), 0);
+    drop(second);
+    assert_eq!(*rt.block_on(read).unwrap(), (1, Some(2)));
+    assert_eq!(rwlock.version(), 1);
+}
+
+// Introspection should count readers granted access from the wait queue, even
+// before their futures are polled
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn reader_count_during_handoff() {
+    let rwlock = RwLock::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = rwlock.try_write().unwrap();
+    let (r1, r2) = rt.block_on(lazy(|| {
+        let mut r1 = rwlock.read();
+        assert!(r1.poll().unwrap().is_not_ready());
+        let mut r2 = rwlock.read();
+        assert!(r2.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((r1, r2))
+    })).unwrap();
+    assert!(rwlock.is_write_locked());
+    assert_eq!(rwlock.reader_count(), 0);
+    drop(guard);
+    assert!(!rwlock.is_write_locked());
+    assert!(rwlock.is_locked());
+    assert_eq!(rwlock.reader_count(), 2);
+    drop(r1);
+    drop(r2);
+    assert!(!rwlock.is_locked());
+}
+
+// A timed-out writer should leave the queue, so it no longer holds back
+// readers under writer preference
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn write_timeout_leaves_queue() {
+    let rwlock = RwLock::builder().preference(Preference::Writers).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = rwlock.try_read().unwrap();
+    let r = rt.block_on(rwlock.write_timeout(Duration::from_millis(10)));
+    assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    assert_eq!(rwlock.info().waiters(), 0);
+    assert!(rwlock.try_read().is_ok());
+    let fut = rwlock.write_timeout(Duration::from_secs(60));
+    drop(guard);
+    assert_eq!(*rt.block_on(fut).ok().unwrap(), 0);
+}
+
+// A timed-out reader should leave the queue too
+#[cfg(all(feature = "timer", not(feature = "chaos")))]
+#[test]
+fn read_timeout_leaves_queue() {
+    let rwlock = RwLock::<u32>::new(0);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let guard = rwlock.try_write().unwrap();
+    let r = rt.block_on(rwlock.read_timeout(Duration::from_millis(10)));
+    assert_eq!(r.err(), Some(AcquireError::TimedOut));
+    assert_eq!(rwlock.info().waiters(), 0);
+    drop(guard);
+    assert_eq!(rwlock.reader_count(), 0);
+    let r = rt.block_on(rwlock.read_timeout(Duration::from_secs(60)));
+    assert_eq!(*r.ok().unwrap(), 0);
+}
+
+// With writer preference, a waiting writer should be served before readers
+// that arrived after it
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn prefer_writers() {
+    let rwlock = RwLock::builder().preference(Preference::Writers).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let reader = rwlock.try_read().unwrap();
+    let (write, read) = rt.block_on(lazy(|| {
+        let mut write = rwlock.write();
+        assert!(write.poll().unwrap().is_not_ready());
+        let mut read = rwlock.read();
+        assert!(read.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((write, read))
+    })).unwrap();
+    assert!(rwlock.try_upgradable_read().is_err());
+    drop(reader);
+    let mut guard = rt.block_on(write).unwrap();
+    *guard += 1;
+    drop(guard);
+    assert_eq!(*rt.block_on(read).unwrap(), 1);
+}
+
+// With writer preference, readers waiting behind a writer should be admitted
+// if that writer gives up
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn prefer_writers_cancelled() {
+    let rwlock = RwLock::builder().preference(Preference::Writers).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let reader = rwlock.try_read().unwrap();
+    let (write, read) = rt.block_on(lazy(|| {
+        let mut write = rwlock.write();
+        assert!(write.poll().unwrap().is_not_ready());
+        let mut read = rwlock.read();
+        assert!(read.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((write, read))
+    })).unwrap();
+    drop(write);
+    assert_eq!(*rt.block_on(read).unwrap(), 0);
+    drop(reader);
+    assert!(rwlock.try_write().is_ok());
+}
+
+// By default, readers should pass a queued writer, which only gets the RwLock
+// once every reader has released it
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn prefer_readers() {
+    let rwlock = RwLock::<u32>::new(0);
+    assert_eq!(rwlock.preference(), Preference::Readers);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let reader1 = rwlock.try_read().unwrap();
+    let write = rt.block_on(lazy(|| {
+        let mut write = rwlock.write();
+        assert!(write.poll().unwrap().is_not_ready());
+        Ok::<_, ()>(write)
+    })).unwrap();
+    let reader2 = rt.block_on(rwlock.read()).unwrap();
+    assert!(rwlock.try_upgradable_read().is_ok());
+    drop(reader1);
+    drop(reader2);
+    assert!(rt.block_on(write).is_ok());
+}
+
+// Readers beyond max_readers should wait, and be admitted one at a time as
+// earlier readers leave
+#[cfg(not(feature = "chaos"))]
+#[test]
+fn max_readers_queue() {
+    let rwlock = RwLock::builder().max_readers(1).build(0u32);
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let reader = rwlock.try_read().unwrap();
+    let (read1, read2) = rt.block_on(lazy(|| {
+        let mut read1 = rwlock.read();
+        assert!(read1.poll().unwrap().is_not_ready());
+        let mut read2 = rwlock.read();
+        assert!(read2.poll().unwrap().is_not_ready());
+        Ok::<_, ()>((read1, read2))
+    })).unwrap();
+    assert!(rwlock.try_upgradable_read().is_err());
+    drop(reader);
+    let reader = rt.block_on(read1).unwrap();
+    assert_eq!(rwlock.info().readers(), 1);
+    drop(reader);
+    let reader = rt.block_on(read2).unwrap();
+    drop(reader);
+    assert!(rwlock.try_write().is_ok());
+}
@@ -4,5 +4,10 @@ extern crate futures;
 extern crate tokio_ as tokio;
 extern crate futures_locks;
 
+// The derive tests rely on try_lock, which chaos makes fail at random
+#[cfg(all(feature = "derive", not(feature = "chaos")))]
+mod derive;
+#[cfg(feature = "mutex")]
 mod mutex;
+#[cfg(feature = "rwlock")]
 mod rwlock;